@@ -0,0 +1,308 @@
+use anyhow::{bail, Context};
+use indexmap::IndexMap;
+use std::{
+    ffi::OsStr,
+    fs::{self, Metadata},
+    iter,
+    os::unix::{ffi::OsStrExt, fs::PermissionsExt},
+    path::Path,
+};
+
+use crate::{
+    commit::identity,
+    file::{bytes_path, ignore_by_file_name, list_files, slash_path_bytes},
+    index::{dir_prune, path_matches, Index, IndexEntry},
+    leaf::{tree_flatten, GitTreeLeaf},
+    refs::{head_branch, ref_resolve, reflog_append},
+    repository::RGitRepository,
+    revparse::previous_checkout,
+    status::Status,
+    Result,
+};
+
+/// Switches work tree, index and HEAD to target. A branch name attaches HEAD
+/// to the branch, anything else resolving to a commit detaches it. Local
+/// changes to paths that differ between the two trees stop the checkout,
+/// changes to other paths are kept.
+pub fn checkout(repo: &RGitRepository, target: &str) -> Result<()> {
+    // "-" and "@{-N}" name the branch checked out before, not only its commit.
+    let target = match target {
+        "-" => previous_checkout(repo, 1)?,
+        target => match target.strip_prefix("@{-").and_then(|n| n.strip_suffix('}')) {
+            Some(n) => previous_checkout(repo, n.parse()?)?,
+            None => target.to_string(),
+        },
+    };
+    let target = target.as_str();
+    let branch_ref = format!("refs/heads/{}", target);
+    let branch = ref_resolve(repo, &branch_ref)?.is_some();
+    let sha = match branch {
+        true => repo.object_find(&branch_ref, Some("commit".into()), None)?,
+        false => repo.object_find(target, Some("commit".into()), None)?,
+    };
+
+    let head = ref_resolve(repo, "HEAD")?;
+    let old = match &head {
+        Some(head) => tree_read(repo, head)?,
+        None => IndexMap::new(),
+    };
+    let new = tree_read(repo, &sha)?;
+
+    let status = Status::read(repo)?;
+    if !status.unmerged.is_empty() {
+        bail!("you need to resolve your current index first");
+    }
+    let changed: Vec<_> = status
+        .changes
+        .iter()
+        // a staged rename also changes its path in HEAD.
+        .filter(|e| {
+            iter::once(&e.path)
+                .chain(&e.orig_path)
+                .any(|path| old.get(path) != new.get(path))
+        })
+        .map(|e| String::from_utf8_lossy(&e.path))
+        .collect();
+    if !changed.is_empty() {
+        bail!(
+            "your local changes to the following files would be overwritten by checkout:\n\t{}\ncommit your changes before you switch branches",
+            changed.join("\n\t")
+        );
+    }
+
+    let mut index = Index::read(repo)?;
+    let work_tree = repo.work_tree();
+    // a file is in the way of a new path itself or of one of its directories,
+    // unless it is tracked and goes away; a directory if it has untracked files.
+    let in_the_way = |path: &[u8]| -> bool {
+        if old.contains_key(path) || index.entry(path).is_some() {
+            return false;
+        }
+        match fs::symlink_metadata(work_tree.join(bytes_path(path))) {
+            Ok(metadata) if metadata.is_dir() => {
+                let dir = work_tree.join(bytes_path(path));
+                let skip = [ignore_by_file_name(".git".into())];
+                list_files(&dir, &skip).map_or(true, |files| {
+                    files.iter().any(|f| {
+                        let relative = f.strip_prefix(work_tree).map(slash_path_bytes);
+                        relative.map_or(true, |r| !old.contains_key(&r))
+                    })
+                })
+            }
+            Ok(_) => true,
+            Err(_) => false,
+        }
+    };
+    let mut untracked = vec![];
+    for path in new.keys().filter(|path| !old.contains_key(*path)) {
+        let mut dirs = (0..path.len())
+            .filter(|&i| path[i] == b'/')
+            .map(|i| &path[..i]);
+        match dirs.find(|dir| {
+            fs::symlink_metadata(work_tree.join(bytes_path(dir))).map_or(false, |m| !m.is_dir())
+                && in_the_way(dir)
+        }) {
+            Some(dir) => untracked.push(String::from_utf8_lossy(dir)),
+            None if in_the_way(path) => untracked.push(String::from_utf8_lossy(path)),
+            None => {}
+        }
+    }
+    if !untracked.is_empty() {
+        bail!(
+            "the following untracked working tree files would be overwritten by checkout:\n\t{}",
+            untracked.join("\n\t")
+        );
+    }
+
+    // removals go first, so files can take place of directories and back.
+    for path in old.keys().filter(|path| !new.contains_key(*path)) {
+        let fs_path = work_tree.join(bytes_path(path));
+        if let Ok(metadata) = fs::symlink_metadata(&fs_path) {
+            match metadata.is_dir() {
+                true => fs::remove_dir(&fs_path)?,
+                false => fs::remove_file(&fs_path)?,
+            }
+        }
+        dir_prune(repo, &fs_path);
+        index.entries.retain(|e| &e.path != path);
+    }
+    for leaf in new
+        .values()
+        .filter(|leaf| old.get(&leaf.path) != Some(leaf))
+    {
+        index_checkout(repo, &mut index, leaf)?;
+    }
+    index.write(repo)?;
+
+    let from = match head_branch(repo)? {
+        Some(branch) => branch,
+        None => head.clone().unwrap_or_default(),
+    };
+    let head_content = match branch {
+        true => format!("ref: {}\n", branch_ref),
+        false => format!("{}\n", sha),
+    };
+    fs::write(repo.repo_path(&["HEAD"]), head_content)?;
+    reflog_append(
+        repo,
+        "HEAD",
+        head.as_deref(),
+        &sha,
+        &identity(repo, "COMMITTER")?,
+        &format!("checkout: moving from {} to {}", from, target),
+    )
+}
+
+/// Restores paths, given relative to current directory, from target tree-ish
+/// to the work tree and the index, or from the index when target is None.
+/// HEAD does not move and local changes of these paths are overwritten.
+pub fn checkout_paths(repo: &RGitRepository, target: Option<&str>, paths: &[String]) -> Result<()> {
+    let mut index = Index::read(repo)?;
+    let source: Vec<GitTreeLeaf> = match target {
+        Some(target) => tree_read(repo, target)?.into_values().collect(),
+        None => index
+            .entries
+            .iter()
+            .filter(|e| e.stage == 0)
+            .map(|e| GitTreeLeaf::new(format!("{:o}", e.mode), e.path.clone(), e.sha.clone()))
+            .collect(),
+    };
+
+    for path in paths {
+        let relative = repo.relative_path(Path::new(path))?;
+        let matched: Vec<&GitTreeLeaf> = source
+            .iter()
+            .filter(|leaf| path_matches(&leaf.path, relative.as_bytes()))
+            .collect();
+        if matched.is_empty() {
+            bail!("pathspec '{}' did not match any known files", path);
+        }
+        for leaf in matched {
+            index_checkout(repo, &mut index, leaf)?;
+        }
+    }
+    index.write(repo)
+}
+
+/// Flattened tree of a tree-ish.
+fn tree_read(repo: &RGitRepository, treeish: &str) -> Result<IndexMap<Vec<u8>, GitTreeLeaf>> {
+    tree_flatten(repo, &repo.object_find(treeish, Some("tree".into()), None)?)
+}
+
+/// Writes leaf to the work tree and stages it with stat data of the new file.
+fn index_checkout(repo: &RGitRepository, index: &mut Index, leaf: &GitTreeLeaf) -> Result<()> {
+    let mode = u32::from_str_radix(&leaf.mode, 8)
+        .with_context(|| format!("bad mode {} of {}", leaf.mode, leaf.path_str()))?;
+    let fs_path = repo.work_tree().join(bytes_path(&leaf.path));
+    let metadata = blob_checkout(repo, &fs_path, mode, &leaf.sha)?;
+    index.entries.retain(|e| e.path != leaf.path);
+    index.entries.push(IndexEntry::from_metadata(
+        leaf.path.clone(),
+        leaf.sha.clone(),
+        mode,
+        &metadata,
+    ));
+    Ok(())
+}
+
+/// Writes blob as a regular file, executable or symlink depending on mode,
+/// replacing whatever was at path. Gitlinks become empty directories.
+fn blob_checkout(repo: &RGitRepository, path: &Path, mode: u32, sha: &str) -> Result<Metadata> {
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        match metadata.is_dir() {
+            true if mode == 0o160000 => return Ok(metadata),
+            true => fs::remove_dir(path)
+                .with_context(|| format!("directory {:?} is in the way", path))?,
+            false => fs::remove_file(path)?,
+        }
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if mode == 0o160000 {
+        fs::create_dir(path)?;
+        return Ok(fs::symlink_metadata(path)?);
+    }
+
+    let (_, data) = repo.object_read_raw(sha)?;
+    if mode == 0o120000 {
+        std::os::unix::fs::symlink(OsStr::from_bytes(&data), path)?;
+    } else {
+        fs::write(path, &data)?;
+        if mode == 0o100755 {
+            // executable bits follow read bits, so umask is respected.
+            let mut permissions = fs::metadata(path)?.permissions();
+            permissions.set_mode(permissions.mode() | (permissions.mode() & 0o444) >> 2);
+            fs::set_permissions(path, permissions)?;
+        }
+    }
+    Ok(fs::symlink_metadata(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{checkout, checkout_paths};
+    use crate::{index::Index, refs::ref_create, testing::TempRepo};
+    use std::fs;
+
+    #[test]
+    fn test_checkout() {
+        let repo = TempRepo::new("checkout");
+        let master = repo.tree(&[("a.txt", "a"), ("d/x", "x")]);
+        let master = repo.commit(&master).write();
+        let other = repo.tree(&[("a.txt", "a2"), ("b.txt", "b"), ("d", "file")]);
+        let other = repo.commit(&other).parents(&[&master]).write();
+        ref_create(&repo, "refs/heads/master", &master).unwrap();
+        ref_create(&repo, "refs/heads/other", &other).unwrap();
+        let read = |a: &str, b: &str| (repo.read(a), repo.read(b));
+        let some = |a: &str, b: &str| (Some(a.to_string()), Some(b.to_string()));
+        let head = || repo.read(".git/HEAD").unwrap();
+
+        // the whole tree of master, restored into an empty work tree.
+        checkout_paths(&repo, Some("master"), &[repo.path("")]).unwrap();
+        assert_eq!(read("a.txt", "d/x"), some("a", "x"));
+
+        // local changes to a path that differs between the branches.
+        repo.write("a.txt", "local");
+        let changed = checkout(&repo, "other").unwrap_err().to_string();
+        assert!(changed.contains("would be overwritten by checkout:\n\ta.txt\n"));
+        checkout_paths(&repo, None, &[repo.path("a.txt")]).unwrap();
+        assert_eq!(repo.read("a.txt").as_deref(), Some("a"));
+
+        // an untracked file where the other branch has one.
+        repo.write("b.txt", "mine");
+        let untracked = checkout(&repo, "other").unwrap_err().to_string();
+        assert!(untracked.contains("untracked working tree files would be overwritten"));
+        assert!(untracked.ends_with("\n\tb.txt"));
+        assert_eq!(head(), "ref: refs/heads/master\n");
+        fs::remove_file(repo.dir().join("b.txt")).unwrap();
+
+        // d/x gives way to the file d, and back.
+        checkout(&repo, "other").unwrap();
+        assert_eq!(head(), "ref: refs/heads/other\n");
+        assert_eq!(read("a.txt", "d"), some("a2", "file"));
+        checkout(&repo, "-").unwrap();
+        assert_eq!(head(), "ref: refs/heads/master\n");
+        assert_eq!(read("b.txt", "d/x"), (None, Some("x".into())));
+        checkout(&repo, "@{-1}").unwrap();
+        assert_eq!(read("a.txt", "b.txt"), some("a2", "b"));
+        checkout(&repo, &master).unwrap();
+        assert_eq!(head(), format!("{}\n", master));
+        checkout(&repo, "@{-2}").unwrap();
+        assert_eq!(head(), "ref: refs/heads/master\n");
+
+        repo.write("a.txt", "local");
+        checkout_paths(
+            &repo,
+            Some("other"),
+            &[repo.path("a.txt"), repo.path("b.txt")],
+        )
+        .unwrap();
+        assert_eq!(read("a.txt", "b.txt"), some("a2", "b"));
+        let index = Index::read(&repo).unwrap();
+        let paths: Vec<&[u8]> = index.entries.iter().map(|e| e.path.as_slice()).collect();
+        assert_eq!(paths.len(), 3);
+        assert!(paths.contains(&b"b.txt".as_slice()) && !paths.contains(&b"d".as_slice()));
+        assert!(checkout_paths(&repo, None, &[repo.path("nothing")]).is_err());
+    }
+}
@@ -1,11 +1,40 @@
 use crate::{
+    checkout::{checkout, checkout_paths},
+    color::{color_enabled, ColorWhen},
+    commit::{approxidate, commit_index, identity, tag_create, Commit},
+    diff::{Algorithm, DiffOptions, WordDiff},
+    file::{bytes_path, ignore_by_file_name, list_files, slash_path_bytes, FileNode},
+    history::{DateFormat, Decorate, Decorations, LogFormat, LogOptions, LogWriter},
+    ignore::Ignore,
+    index::{self, file_mode, path_matches, Index},
+    leaf::GitTreeLeaf,
     object::{GitObject, GitObjectType},
+    patch::{
+        changes_limit, content_read, entries_diff, file_pairs, index_entries, names_write,
+        numstat_write, patch_write, quote_path, stat_write, tree_entries, worktree_entries,
+        DiffSide, FilePair,
+    },
+    pickaxe::Pickaxe,
+    refs::{head_branch, ref_create, ref_list, ref_resolve},
+    repack::{pack_refs, repack},
     repository::{repo_find, RGitRepository},
+    revparse::{range_parse, RevRange},
+    revwalk::{basic_regex, merge_bases, CommitFilter, Order, RevWalk, WalkOptions},
+    status::Status,
+    treediff::{detect_renames, tree_diff, RenameOptions, TreeChange},
     Result,
 };
-use anyhow::{Context, Ok};
-use clap::{Parser, Subcommand};
-use std::{collections::HashSet, fs};
+use anyhow::{bail, Context, Ok};
+use clap::{Args, Parser, Subcommand};
+use regex::bytes::RegexBuilder;
+use std::{
+    env, fs,
+    io::{self, Read, Write},
+    os::unix::ffi::OsStrExt,
+    path::Path,
+    rc::Rc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use crate::repository::repo_create;
 
@@ -55,12 +84,698 @@ pub enum Commands {
 
     /// Display history of a given commit.
     Log {
-        /// Commit to start at.
+        #[clap(flatten)]
+        walk: WalkFlags,
+
+        #[clap(flatten)]
+        flags: LogFlags,
+    },
+
+    /// List commits reachable from some revisions but not others.
+    RevList {
+        #[clap(flatten)]
+        walk: WalkFlags,
+    },
+
+    /// Add file contents to the index.
+    Add {
+        /// Files to add content from, directories are added recursively.
+        #[clap(required = true)]
+        paths: Vec<String>,
+    },
+
+    /// Remove files from the working tree and from the index.
+    Rm {
+        /// Only remove from the index, keeping files in the working tree.
+        #[clap(long)]
+        cached: bool,
+
+        /// Allow recursive removal when a leading directory name is given.
+        #[clap(short)]
+        recursive: bool,
+
+        /// Remove files even if they have changes not in the index.
+        #[clap(short, long)]
+        force: bool,
+
+        /// Files to remove.
+        #[clap(required = true)]
+        paths: Vec<String>,
+    },
+
+    /// Show the working tree status.
+    Status {
+        /// Give the output in the short format.
+        #[clap(short, long)]
+        short: bool,
+
+        /// Give the output in a stable format for scripts, "v1" (the default) or "v2".
+        #[clap(
+            long,
+            value_name = "VERSION",
+            min_values = 0,
+            require_equals = true,
+            default_missing_value = "v1"
+        )]
+        porcelain: Option<String>,
+    },
+
+    /// Show changes between the index and the work tree, commits or files.
+    Diff {
+        /// Compare the index with HEAD, or with the given commit, instead of the
+        /// work tree with the index.
+        #[clap(long, alias = "staged")]
+        cached: bool,
+
+        /// Compare two paths on the filesystem, which need not be in a repository.
+        #[clap(long)]
+        no_index: bool,
+
+        /// Show only names of changed files.
+        #[clap(long)]
+        name_only: bool,
+
+        /// Show only names and status of changed files.
+        #[clap(long)]
+        name_status: bool,
+
+        /// Show a diffstat of changed files instead of a patch.
+        #[clap(long)]
+        stat: bool,
+
+        /// Show numbers of added and deleted lines in decimal, one file per line.
+        #[clap(long)]
+        numstat: bool,
+
+        /// Detect renames with similarity of at least N, like "-M50%" or "-M5"
+        /// for half of the file. On by default at 50%.
+        #[clap(short = 'M', long, value_name = "N", min_values = 0)]
+        find_renames: Option<Option<String>>,
+
+        /// Detect copies of modified files as well as renames.
+        #[clap(short = 'C', long, value_name = "N", min_values = 0)]
+        find_copies: Option<Option<String>>,
+
+        /// Turn off rename detection.
+        #[clap(long)]
+        no_renames: bool,
+
+        #[clap(flatten)]
+        patch: PatchFlags,
+
+        /// A commit to compare the work tree (or the index) with, two commits
+        /// or "A..B" to compare with each other. The two paths with --no-index.
+        revisions: Vec<String>,
+
+        /// Limit the diff to these paths.
+        #[clap(last = true)]
+        paths: Vec<String>,
+    },
+
+    /// Debug gitignore and exclude files.
+    CheckIgnore {
+        /// Show the matching pattern and the file it comes from.
+        #[clap(short, long)]
+        verbose: bool,
+
+        /// Paths to check, printed if they are ignored.
+        #[clap(required = true)]
+        paths: Vec<String>,
+    },
+
+    /// Create a tree object from the index.
+    WriteTree {
+        /// Snapshot files of a directory instead of the index, skipping ignored ones.
+        #[clap(long, value_name = "DIR")]
+        directory: Option<String>,
+    },
+
+    /// Create a new commit object.
+    CommitTree {
+        /// An existing tree object.
+        tree: String,
+
+        /// Id of a parent commit object, can be given more than once.
+        #[clap(short = 'p', value_name = "PARENT")]
+        parents: Vec<String>,
+
+        /// A paragraph in the commit log message, can be given more than once.
+        #[clap(short, long)]
+        message: Vec<String>,
+
+        /// Read the commit log message from the given file, "-" for standard input.
+        #[clap(short = 'F', long)]
+        file: Option<String>,
+    },
+
+    /// Record changes to the repository.
+    Commit {
+        /// A paragraph in the commit log message, can be given more than once.
+        #[clap(short, long)]
+        message: Vec<String>,
+
+        /// Read the commit log message from the given file, "-" for standard input.
+        #[clap(short = 'F', long)]
+        file: Option<String>,
+
+        /// Replace the tip of the current branch by creating a new commit.
+        #[clap(long)]
+        amend: bool,
+
+        /// Allow recording a commit with the same tree as its parent.
+        #[clap(long)]
+        allow_empty: bool,
+    },
+
+    /// Switch branches or restore working tree files.
+    Checkout {
+        /// Branch to switch to, or commit to detach HEAD at. With paths, the
+        /// tree-ish to restore them from instead of the index.
+        target: Option<String>,
+
+        /// Paths to restore, HEAD does not move.
+        #[clap(last = true)]
+        paths: Vec<String>,
+    },
+
+    /// Show information about files in the index.
+    LsFiles {
+        /// Show staged contents' mode bits, object name and stage number.
+        #[clap(short, long)]
+        stage: bool,
+
+        /// Show stat data of every entry.
+        #[clap(long)]
+        debug: bool,
+    },
+
+    /// Pick out and massage parameters.
+    RevParse {
+        /// Show the absolute path of the top-level directory of the working tree.
+        #[clap(long)]
+        show_toplevel: bool,
+
+        /// Show the path to the .git directory.
+        #[clap(long)]
+        git_dir: bool,
+
+        /// Verify that exactly one parameter is given and it names an object.
+        #[clap(long)]
+        verify: bool,
+
+        /// Print shortest unique object names, at least <SHORT> characters long. Implies --verify.
+        #[clap(
+            long,
+            min_values = 0,
+            require_equals = true,
+            default_missing_value = "7"
+        )]
+        short: Option<usize>,
+
+        /// Revisions to parse, "^<rev>" and "<rev>..<rev>" are printed as exclusions.
+        #[clap(allow_hyphen_values = true)]
+        revisions: Vec<String>,
+    },
+
+    /// Pack unpacked objects in a repository.
+    Repack {
+        /// Pack everything reachable into a single pack, instead of only loose objects.
+        #[clap(short)]
+        all: bool,
+
+        /// Remove redundant packs and loose objects after packing.
+        #[clap(short)]
+        delete: bool,
+
+        /// Number of objects considered as delta base, defaults to pack.window or 10.
+        #[clap(long)]
+        window: Option<usize>,
+
+        /// Maximum delta chain length, defaults to pack.depth or 50.
+        #[clap(long)]
+        depth: Option<usize>,
+    },
+
+    /// Cleanup unnecessary files and optimize the local repository.
+    Gc {
+        /// Number of objects considered as delta base, defaults to pack.window or 10.
+        #[clap(long)]
+        window: Option<usize>,
+
+        /// Maximum delta chain length, defaults to pack.depth or 50.
+        #[clap(long)]
+        depth: Option<usize>,
+    },
+
+    /// List and create tags.
+    Tag {
+        /// Whether to create a tag object.
+        #[clap(short = 'a')]
+        annotate: bool,
+
+        /// Message of the annotated tag, implies -a.
+        #[clap(short, long)]
+        message: Option<String>,
+
+        /// The new tag's name, lists tags if omitted.
+        name: Option<String>,
+
+        /// The object the new tag will point to.
         #[clap(default_value = "HEAD")]
-        commit: String,
+        object: String,
     },
 }
 
+/// How contents are diffed, shared by commands showing patches.
+#[derive(Args, Debug)]
+pub struct PatchFlags {
+    /// Number of context lines around changes.
+    #[clap(short = 'U', long, value_name = "N", default_value = "3")]
+    unified: usize,
+
+    /// Diff algorithm, defaults to diff.algorithm or myers.
+    #[clap(long, arg_enum, value_name = "ALGORITHM")]
+    diff_algorithm: Option<Algorithm>,
+
+    /// Spend extra time to make the diff as small as possible.
+    #[clap(long)]
+    minimal: bool,
+
+    /// Same as --diff-algorithm=patience.
+    #[clap(long)]
+    patience: bool,
+
+    /// Same as --diff-algorithm=histogram.
+    #[clap(long)]
+    histogram: bool,
+
+    /// Slide changes to where indentation suggests a block boundary. On
+    /// unless diff.indentHeuristic is false.
+    #[clap(long)]
+    indent_heuristic: bool,
+
+    /// Turn off the indent heuristic.
+    #[clap(long, conflicts_with = "indent-heuristic")]
+    no_indent_heuristic: bool,
+
+    /// Show changed words instead of changed lines, marked as MODE says.
+    #[clap(
+        long,
+        arg_enum,
+        value_name = "MODE",
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "plain"
+    )]
+    word_diff: Option<WordDiff>,
+
+    /// What a word is for --word-diff, which it implies. Defaults to
+    /// diff.wordRegex, or runs of non-whitespace.
+    #[clap(long, value_name = "REGEX")]
+    word_diff_regex: Option<String>,
+
+    /// Same as --word-diff=color, and --word-diff-regex=REGEX when given.
+    #[clap(long, value_name = "REGEX", min_values = 0, require_equals = true)]
+    color_words: Option<Option<String>>,
+
+    #[clap(flatten)]
+    color: ColorFlags,
+}
+
+/// How commits are printed, shared by commands showing history.
+#[derive(Args, Debug)]
+pub struct LogFlags {
+    /// How to print commits: oneline, short, medium, full, fuller, raw,
+    /// dot for a Graphviz graph of parents, or "format:<template>" with
+    /// placeholders like %H, %h, %an, %ad and %s.
+    #[clap(
+        long,
+        visible_alias = "pretty",
+        value_name = "FORMAT",
+        default_value = "medium"
+    )]
+    format: LogFormat,
+
+    /// Same as --format=oneline --abbrev-commit.
+    #[clap(long)]
+    oneline: bool,
+
+    /// Show shortest unique prefixes of commit names.
+    #[clap(long)]
+    abbrev_commit: bool,
+
+    /// How to show dates.
+    #[clap(long, arg_enum, value_name = "FORMAT", default_value = "default")]
+    date: DateFormat,
+
+    /// Draw lines of history left of commits, implies --topo-order unless
+    /// --date-order is given.
+    #[clap(long, conflicts_with = "reverse")]
+    graph: bool,
+
+    /// Show names of branches and tags pointing at commits, defaults to
+    /// log.decorate or auto for a terminal.
+    #[clap(
+        long,
+        arg_enum,
+        value_name = "STYLE",
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "short"
+    )]
+    decorate: Option<Decorate>,
+
+    /// Same as --decorate=no.
+    #[clap(long, conflicts_with = "decorate")]
+    no_decorate: bool,
+
+    /// Show the patch of each commit against its parent, merges only with
+    /// --first-parent.
+    #[clap(short = 'p', long)]
+    patch: bool,
+
+    /// Show only commits changing the number of occurrences of STRING in a
+    /// file.
+    #[clap(short = 'S', value_name = "STRING", conflicts_with = "pickaxe-regex")]
+    pickaxe_string: Option<String>,
+
+    /// Show only commits adding or removing lines matching REGEX, an
+    /// extended regular expression.
+    #[clap(short = 'G', value_name = "REGEX")]
+    pickaxe_regex: Option<String>,
+
+    /// Show every change of commits -S or -G finds, not only the files
+    /// they find.
+    #[clap(long)]
+    pickaxe_all: bool,
+
+    #[clap(flatten)]
+    diff: PatchFlags,
+}
+
+impl LogFlags {
+    /// Log options from flags, falling back to log.decorate and color
+    /// config of repo.
+    fn options(&self, repo: &RGitRepository) -> Result<LogOptions> {
+        let format = match self.oneline {
+            true => LogFormat::Oneline,
+            false => self.format.clone(),
+        };
+        let decorate = match (self.no_decorate, self.decorate) {
+            (true, _) => Decorate::No,
+            (_, Some(decorate)) => decorate,
+            _ => match repo.config_get("log", "decorate") {
+                Some(value) => value.parse()?,
+                None => Decorate::Auto,
+            },
+        };
+        let decorate = match decorate {
+            Decorate::Auto if atty::is(atty::Stream::Stdout) => Decorate::Short,
+            Decorate::Auto => Decorate::No,
+            decorate => decorate,
+        };
+        // "%d" names commits even when log does not.
+        let placeholders = match &format {
+            LogFormat::Format { template, .. } => {
+                template.contains("%d") || template.contains("%D")
+            }
+            _ => false,
+        };
+        let decorations = match decorate != Decorate::No || placeholders {
+            true => Decorations::load(repo, decorate == Decorate::Full)?,
+            false => Decorations::default(),
+        };
+        Ok(LogOptions {
+            format,
+            abbrev_commit: self.oneline || self.abbrev_commit,
+            date: self.date,
+            color: self.diff.color.enabled(Some(repo))?,
+            decorate: decorate != Decorate::No,
+            decorations,
+            graph: self.graph,
+            patch: match self.patch {
+                true => Some(self.diff.options(Some(repo))?),
+                false => None,
+            },
+        })
+    }
+
+    /// Pickaxe of -S or -G, which -i makes ignore case.
+    fn pickaxe(&self, ignore_case: bool) -> Result<Option<Pickaxe>> {
+        match (&self.pickaxe_string, &self.pickaxe_regex) {
+            (Some(string), _) => Ok(Some(Pickaxe::count(string, ignore_case)?)),
+            (_, Some(regex)) => Ok(Some(Pickaxe::grep(regex, ignore_case)?)),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Which commits are walked and in what order, shared by commands listing
+/// history.
+#[derive(Args, Debug)]
+pub struct WalkFlags {
+    /// Revisions to start at, "^<rev>" excludes commits reachable from
+    /// <rev>, "<rev1>..<rev2>" is "^<rev1> <rev2>" and "<rev1>...<rev2>"
+    /// commits reachable from either but not both.
+    revisions: Vec<String>,
+
+    /// Revisions given with the meaning of "^" reversed.
+    #[clap(long, value_name = "REVISION", multiple_values = true)]
+    not: Vec<String>,
+
+    /// Follow only the first parent of merges.
+    #[clap(long)]
+    first_parent: bool,
+
+    /// Show no parent before all its children, and commits of a line of
+    /// history together.
+    #[clap(long)]
+    topo_order: bool,
+
+    /// Show no parent before all its children, otherwise by commit date.
+    #[clap(long, conflicts_with = "topo-order")]
+    date_order: bool,
+
+    /// Show commits oldest first.
+    #[clap(long)]
+    reverse: bool,
+
+    /// Show at most N commits.
+    #[clap(short = 'n', long, value_name = "N")]
+    max_count: Option<usize>,
+
+    /// Leave out the first N commits.
+    #[clap(long, value_name = "N", default_value = "0")]
+    skip: usize,
+
+    /// Show commits whose author matches PATTERN, a basic regular
+    /// expression.
+    #[clap(long, value_name = "PATTERN")]
+    author: Vec<String>,
+
+    /// Show commits whose committer matches PATTERN.
+    #[clap(long, value_name = "PATTERN")]
+    committer: Vec<String>,
+
+    /// Show commits with a line of the message matching PATTERN.
+    #[clap(long, value_name = "PATTERN")]
+    grep: Vec<String>,
+
+    /// Match patterns regardless of case.
+    #[clap(short = 'i', long)]
+    regexp_ignore_case: bool,
+
+    /// Show commits matching every --grep pattern, not any.
+    #[clap(long)]
+    all_match: bool,
+
+    /// Show commits more recent than DATE, like "2 weeks ago" or
+    /// "2005-04-07".
+    #[clap(long, visible_alias = "after", value_name = "DATE")]
+    since: Option<String>,
+
+    /// Show commits older than DATE.
+    #[clap(long, visible_alias = "before", value_name = "DATE")]
+    until: Option<String>,
+
+    /// Show only merges.
+    #[clap(long)]
+    merges: bool,
+
+    /// Show no merges.
+    #[clap(long, conflicts_with = "merges")]
+    no_merges: bool,
+
+    /// Show only commits changing these paths, following the side of merges
+    /// they come from.
+    #[clap(last = true)]
+    paths: Vec<String>,
+}
+
+impl WalkFlags {
+    /// Walk over the revisions given, or default when there are none. A
+    /// graph keeps children before parents and needs rewritten parents.
+    fn walk<'a>(
+        &self,
+        repo: &'a RGitRepository,
+        default: Option<&str>,
+        graph: bool,
+    ) -> Result<RevWalk<'a>> {
+        let order = match (self.topo_order, self.date_order, graph) {
+            (true, ..) => Order::Topo,
+            (_, true, _) => Order::Date,
+            (.., true) => Order::Topo,
+            _ => Order::Default,
+        };
+        let mut walk = RevWalk::new(
+            repo,
+            WalkOptions {
+                order,
+                first_parent: self.first_parent,
+                reverse: self.reverse,
+                max_count: self.max_count,
+                skip: self.skip,
+                rewrite_parents: graph,
+                filter: self.filter(repo)?,
+            },
+        );
+        for revision in &self.revisions {
+            walk.push(revision)?;
+        }
+        for revision in &self.not {
+            walk.hide(revision)?;
+        }
+        if self.revisions.is_empty() && self.not.is_empty() {
+            match default {
+                Some(default) => walk.push(default)?,
+                None => bail!("no revisions given"),
+            }
+        }
+        Ok(walk)
+    }
+
+    fn filter(&self, repo: &RGitRepository) -> Result<CommitFilter> {
+        let patterns = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|p| basic_regex(p, self.regexp_ignore_case))
+                .collect::<Result<Vec<_>>>()
+        };
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        let date = |date: &Option<String>| date.as_ref().map(|d| approxidate(d, now)).transpose();
+        Ok(CommitFilter {
+            authors: patterns(&self.author)?,
+            committers: patterns(&self.committer)?,
+            grep: patterns(&self.grep)?,
+            all_match: self.all_match,
+            since: date(&self.since)?,
+            until: date(&self.until)?,
+            merges: match (self.merges, self.no_merges) {
+                (true, _) => Some(true),
+                (_, true) => Some(false),
+                _ => None,
+            },
+            paths: self.pathspecs(repo)?,
+        })
+    }
+
+    /// Paths given, relative to the top of repo.
+    fn pathspecs(&self, repo: &RGitRepository) -> Result<Vec<String>> {
+        self.paths
+            .iter()
+            .map(|p| repo.relative_path(Path::new(p)))
+            .collect()
+    }
+}
+
+/// When to paint output, shared by commands writing colors.
+#[derive(Args, Debug)]
+pub struct ColorFlags {
+    /// When to paint output, defaults to color.diff or color.ui, or auto for
+    /// a terminal.
+    #[clap(
+        long,
+        arg_enum,
+        value_name = "WHEN",
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "always"
+    )]
+    color: Option<ColorWhen>,
+
+    /// Same as --color=never.
+    #[clap(long, conflicts_with = "color")]
+    no_color: bool,
+}
+
+impl ColorFlags {
+    /// Whether to paint output, falling back to color.diff and color.ui of repo.
+    fn enabled(&self, repo: Option<&RGitRepository>) -> Result<bool> {
+        let when = match self.no_color {
+            true => Some(ColorWhen::Never),
+            false => self.color,
+        };
+        color_enabled(repo, "diff", when)
+    }
+}
+
+impl PatchFlags {
+    /// Diff options from flags, falling back to diff.algorithm,
+    /// diff.indentHeuristic, diff.wordRegex and color config of repo.
+    fn options(&self, repo: Option<&RGitRepository>) -> Result<DiffOptions> {
+        let config = |key| repo.and_then(|repo| repo.config_get("diff", key));
+        let algorithm = match (
+            self.diff_algorithm,
+            self.histogram,
+            self.patience,
+            self.minimal,
+        ) {
+            (Some(algorithm), ..) => algorithm,
+            (_, true, ..) => Algorithm::Histogram,
+            (_, _, true, _) => Algorithm::Patience,
+            (.., true) => Algorithm::Minimal,
+            _ => match config("algorithm") {
+                Some(algorithm) => algorithm.parse()?,
+                None => Algorithm::Myers,
+            },
+        };
+        let indent_heuristic = match (self.indent_heuristic, self.no_indent_heuristic) {
+            (true, _) => true,
+            (_, true) => false,
+            _ => config("indentheuristic").map_or(true, |v| v == "true"),
+        };
+        let color_words = self.color_words.as_ref();
+        let word_diff = match (self.word_diff, color_words, &self.word_diff_regex) {
+            (Some(mode), ..) => Some(mode),
+            (_, Some(_), _) => Some(WordDiff::Color),
+            (_, _, Some(_)) => Some(WordDiff::Plain),
+            _ => None,
+        };
+        let word_regex = match self
+            .word_diff_regex
+            .clone()
+            .or_else(|| color_words.cloned().flatten())
+            .or_else(|| config("wordregex"))
+        {
+            Some(regex) if word_diff.is_some() => Some(
+                RegexBuilder::new(&regex)
+                    .multi_line(true)
+                    .build()
+                    .with_context(|| format!("invalid regular expression: {}", regex))?,
+            ),
+            _ => None,
+        };
+        Ok(DiffOptions {
+            algorithm,
+            indent_heuristic,
+            context: self.unified,
+            color: word_diff == Some(WordDiff::Color) || self.color.enabled(repo)?,
+            word_diff,
+            word_regex,
+        })
+    }
+}
+
 impl Commands {
     fn run(&self) {
         match self {
@@ -72,7 +787,96 @@ impl Commands {
             Commands::HashObject { tpe, write, file } => {
                 cmd_hash_object(tpe, *write, file).expect("cmd hash object failed")
             }
-            Commands::Log { commit } => cmd_log(commit).expect("cmd log failed"),
+            Commands::Log { walk, flags } => cmd_log(walk, flags).expect("cmd log failed"),
+            Commands::RevList { walk } => cmd_rev_list(walk).expect("cmd rev-list failed"),
+            Commands::Add { paths } => cmd_add(paths).expect("cmd add failed"),
+            Commands::Rm {
+                cached,
+                recursive,
+                force,
+                paths,
+            } => cmd_rm(*cached, *recursive, *force, paths).expect("cmd rm failed"),
+            Commands::Status { short, porcelain } => {
+                cmd_status(*short, porcelain.as_deref()).expect("cmd status failed")
+            }
+            Commands::Diff {
+                cached,
+                no_index,
+                name_only,
+                name_status,
+                stat,
+                numstat,
+                find_renames,
+                find_copies,
+                no_renames,
+                patch,
+                revisions,
+                paths,
+            } => {
+                let format = match (*name_only, *name_status, *stat, *numstat) {
+                    (true, ..) => DiffFormat::NameOnly,
+                    (_, true, ..) => DiffFormat::NameStatus,
+                    (_, _, true, _) => DiffFormat::Stat,
+                    (.., true) => DiffFormat::Numstat,
+                    _ => DiffFormat::Patch,
+                };
+                let renames = (find_renames.as_ref(), find_copies.as_ref(), *no_renames);
+                if cmd_diff(*cached, *no_index, format, renames, patch, revisions, paths)
+                    .expect("cmd diff failed")
+                {
+                    std::process::exit(1);
+                }
+            }
+            Commands::CheckIgnore { verbose, paths } => {
+                if !cmd_check_ignore(*verbose, paths).expect("cmd check-ignore failed") {
+                    std::process::exit(1);
+                }
+            }
+            Commands::WriteTree { directory } => {
+                cmd_write_tree(directory.as_deref()).expect("cmd write-tree failed")
+            }
+            Commands::CommitTree {
+                tree,
+                parents,
+                message,
+                file,
+            } => cmd_commit_tree(tree, parents, message, file.as_deref())
+                .expect("cmd commit-tree failed"),
+            Commands::Commit {
+                message,
+                file,
+                amend,
+                allow_empty,
+            } => cmd_commit(message, file.as_deref(), *amend, *allow_empty)
+                .expect("cmd commit failed"),
+            Commands::Checkout { target, paths } => {
+                cmd_checkout(target.as_deref(), paths).expect("cmd checkout failed")
+            }
+            Commands::LsFiles { stage, debug } => {
+                cmd_ls_files(*stage, *debug).expect("cmd ls-files failed")
+            }
+            Commands::RevParse {
+                show_toplevel,
+                git_dir,
+                verify,
+                short,
+                revisions,
+            } => cmd_rev_parse(*show_toplevel, *git_dir, *verify, *short, revisions)
+                .expect("cmd rev-parse failed"),
+            Commands::Repack {
+                all,
+                delete,
+                window,
+                depth,
+            } => cmd_repack(*all, *delete, *window, *depth).expect("cmd repack failed"),
+            Commands::Gc { window, depth } => cmd_gc(*window, *depth).expect("cmd gc failed"),
+            Commands::Tag {
+                annotate,
+                message,
+                name,
+                object,
+            } => cmd_tag(*annotate, message.as_deref(), name.as_deref(), object)
+                .expect("cmd tag failed"),
         }
     }
 }
@@ -88,7 +892,7 @@ fn cmd_cat_file(object_type: &GitObjectType, object: &str) -> Result<()> {
 fn cmd_hash_object(object_type: &GitObjectType, write: bool, file: &str) -> Result<()> {
     let repo = RGitRepository::init(".", false)?;
 
-    let data = fs::read_to_string(file)?;
+    let data = fs::read(file)?;
 
     let data = GitObject::new(&repo, Some(data), Some(*object_type))?.object_write(Some(write))?;
     println!("{}", data);
@@ -96,43 +900,692 @@ fn cmd_hash_object(object_type: &GitObjectType, write: bool, file: &str) -> Resu
     Ok(())
 }
 
-fn cmd_log(commit: &str) -> Result<()> {
+fn cmd_log(walk_flags: &WalkFlags, flags: &LogFlags) -> Result<()> {
     let repo = repo_find::<&str>(None, None)?.context("repo not found")?;
+    let mut walk = walk_flags.walk(&repo, Some("HEAD"), flags.graph)?;
 
-    println!("digraph wyaglog{{");
-    log_graphviz(
-        &repo,
-        &repo.object_find(commit, None, None),
-        &mut HashSet::new(),
-    )?;
-    println!("}}");
+    if flags.format == LogFormat::Dot {
+        println!("digraph wyaglog{{");
+        for item in walk {
+            let (sha, commit) = item?;
+            for p in &commit.parents {
+                println!("c_{} -> c_{}", sha, p);
+            }
+        }
+        println!("}}");
+        return Ok(());
+    }
+    let options = flags.options(&repo)?;
+    let pickaxe = flags.pickaxe(walk_flags.regexp_ignore_case)?;
+    let diff_options = flags.diff.options(Some(&repo))?;
+    let renames = rename_options(&repo, (None, None, false))?;
+    let pathspecs = walk_flags.pathspecs(&repo)?;
+    let mut writer = LogWriter::new(&repo, &options);
+    let mut stdout = io::stdout().lock();
+    while let Some(item) = walk.next() {
+        let (sha, mut commit) = item?;
+        // commits are shown and diffed with their parents rewritten.
+        commit.parents = walk.parents(&sha);
+        let parents = walk.parents_shown(&sha)?;
+        let mut pairs = vec![];
+        if flags.patch || pickaxe.is_some() {
+            let mut changes = commit_changes(&repo, &commit, walk_flags.first_parent)?;
+            changes_limit(&mut changes, &pathspecs);
+            if let Some(renames) = renames {
+                let read =
+                    |leaf: &GitTreeLeaf, new| Ok(content_read(&repo, leaf, new, false)?.data);
+                changes = detect_renames(changes, renames, read)?;
+            }
+            pairs = file_pairs(&repo, &changes, false)?;
+        }
+        if let Some(pickaxe) = &pickaxe {
+            pairs = pickaxe.filter(pairs, flags.pickaxe_all, &diff_options);
+            // commits the pickaxe passes over do not count towards -n.
+            if pairs.is_empty() {
+                writer.commit_skip(&sha, &parents);
+                walk.uncount();
+                continue;
+            }
+        }
+        let mut out = vec![];
+        writer.commit_write(&mut out, &sha, &commit, &parents, &pairs)?;
+        // a pager or head quitting early is not an error.
+        match stdout.write_all(&out) {
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+            result => result?,
+        }
+    }
     Ok(())
 }
 
-fn log_graphviz(repo: &RGitRepository, sha: &str, seen: &mut HashSet<String>) -> Result<()> {
-    if seen.contains(sha) {
-        return Ok(());
+/// Changes a commit makes to its parent, or to nothing for a root commit.
+/// Merges have none unless only first parents are followed.
+fn commit_changes(
+    repo: &RGitRepository,
+    commit: &Commit,
+    first_parent: bool,
+) -> Result<Vec<TreeChange>> {
+    let parent = match (commit.parents.as_slice(), first_parent) {
+        ([], _) => None,
+        ([parent], _) | ([parent, ..], true) => Some(repo.object_read(parent)?.commit()?.tree),
+        _ => return Ok(vec![]),
+    };
+    tree_diff(repo, parent.as_deref(), Some(&commit.tree))
+}
+
+fn cmd_rev_list(walk: &WalkFlags) -> Result<()> {
+    let repo = repo_find::<&str>(None, None)?.context("repo not found")?;
+    let mut stdout = io::stdout().lock();
+    for item in walk.walk(&repo, None, false)? {
+        match writeln!(stdout, "{}", item?.0) {
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+            result => result?,
+        }
+    }
+    Ok(())
+}
+
+fn cmd_add(paths: &[String]) -> Result<()> {
+    let repo = repo_find::<&str>(None, None)?.context("repo not found")?;
+
+    index::add(&repo, paths)
+}
+
+fn cmd_rm(cached: bool, recursive: bool, force: bool, paths: &[String]) -> Result<()> {
+    let repo = repo_find::<&str>(None, None)?.context("repo not found")?;
+
+    let mut stdout = io::stdout().lock();
+    for path in index::rm(&repo, paths, cached, recursive, force)? {
+        stdout.write_all(&[b"rm '", path.as_slice(), b"'\n"].concat())?;
+    }
+    Ok(())
+}
+
+fn cmd_status(short: bool, porcelain: Option<&str>) -> Result<()> {
+    let repo = repo_find::<&str>(None, None)?.context("repo not found")?;
+
+    let status = Status::read(&repo)?;
+    let prefix = repo.relative_path(Path::new("."))?;
+    let output = match porcelain {
+        Some("v1") => status.short_format(""),
+        Some("v2") => status.porcelain_v2(),
+        Some(version) => bail!("unsupported porcelain version {}", version),
+        None if short => status.short_format(&prefix),
+        None => status.long_format(&repo, &prefix)?,
+    };
+    print!("{}", output);
+    Ok(())
+}
+
+/// Output format of diff.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DiffFormat {
+    Patch,
+    NameOnly,
+    NameStatus,
+    Stat,
+    Numstat,
+}
+
+/// -M, -C and --no-renames of diff.
+type RenameFlags<'a> = (Option<&'a Option<String>>, Option<&'a Option<String>>, bool);
+
+/// Returns true if files compared with --no-index differ.
+fn cmd_diff(
+    cached: bool,
+    no_index: bool,
+    format: DiffFormat,
+    renames: RenameFlags,
+    patch: &PatchFlags,
+    revisions: &[String],
+    paths: &[String],
+) -> Result<bool> {
+    let mut out = vec![];
+    if no_index {
+        let (old, new) = match revisions {
+            [old, new] => (old, new),
+            _ => bail!("--no-index needs exactly two paths"),
+        };
+        let repo = repo_find::<&str>(None, None).ok().flatten();
+        let options = patch.options(repo.as_ref())?;
+        let pairs = no_index_pairs(Path::new(old), Path::new(new))?;
+        let leaf = |side: &DiffSide| {
+            GitTreeLeaf::new(
+                format!("{:o}", side.mode),
+                side.path.clone(),
+                side.sha.clone(),
+            )
+        };
+        let changes: Vec<TreeChange> = pairs
+            .iter()
+            .map(|pair| TreeChange {
+                change: pair.change,
+                score: pair.score,
+                old: pair.old.as_ref().map(leaf),
+                new: pair.new.as_ref().map(leaf),
+            })
+            .collect();
+        diff_write(
+            &mut out,
+            repo.as_ref(),
+            &changes,
+            || Ok(pairs.clone()),
+            format,
+            &options,
+        )?;
+        io::stdout().write_all(&out)?;
+        return Ok(!pairs.is_empty());
+    }
+
+    let repo = repo_find::<&str>(None, None)?.context("repo not found")?;
+    let options = patch.options(Some(&repo))?;
+    let pathspecs = paths
+        .iter()
+        .map(|p| repo.relative_path(Path::new(p)))
+        .collect::<Result<Vec<_>>>()?;
+    let revisions: Vec<String> = match revisions {
+        [range] => match range_parse(range) {
+            // "A...B" diffs B against where it forked from A.
+            RevRange::Symmetric(from, to) => {
+                let commit = |r: &str| repo.object_find(r, Some("commit".into()), None);
+                let (from, to) = (commit(&from)?, commit(&to)?);
+                let base = merge_bases(&repo, &from, &to)?
+                    .into_iter()
+                    .next()
+                    .with_context(|| format!("{}: no merge base", range))?;
+                vec![base, to]
+            }
+            RevRange::Between(from, to) => vec![from, to],
+            RevRange::Single(rev) => vec![rev],
+        },
+        revisions => revisions.to_vec(),
+    };
+    let revisions: Vec<&str> = revisions.iter().map(String::as_str).collect();
+
+    let index = Index::read(&repo)?;
+    let head = || ref_resolve(&repo, "HEAD");
+    let (mut changes, worktree) = match (cached, revisions.as_slice()) {
+        (true, []) => (
+            entries_diff(
+                &tree_entries(&repo, head()?.as_deref())?,
+                &index_entries(&index),
+            )?,
+            false,
+        ),
+        (true, [commit]) => (
+            entries_diff(&tree_entries(&repo, Some(commit))?, &index_entries(&index))?,
+            false,
+        ),
+        (false, []) => (
+            entries_diff(&index_entries(&index), &worktree_entries(&repo, &index)?)?,
+            true,
+        ),
+        (false, [commit]) => (
+            entries_diff(
+                &tree_entries(&repo, Some(commit))?,
+                &worktree_entries(&repo, &index)?,
+            )?,
+            true,
+        ),
+        (false, [old, new]) => {
+            let tree = |commit: &str| repo.object_find(commit, Some("tree".into()), None);
+            (
+                tree_diff(&repo, Some(&tree(old)?), Some(&tree(new)?))?,
+                false,
+            )
+        }
+        _ => bail!("too many revisions, give at most two commits"),
+    };
+    changes_limit(&mut changes, &pathspecs);
+    if let Some(options) = rename_options(&repo, renames)? {
+        let read = |leaf: &GitTreeLeaf, new| Ok(content_read(&repo, leaf, new, worktree)?.data);
+        changes = detect_renames(changes, options, read)?;
+    }
+
+    // conflicted paths are not in the merged entries of the index.
+    if revisions.len() < 2 && format == DiffFormat::Patch {
+        let mut unmerged: Vec<&[u8]> = index
+            .entries
+            .iter()
+            .filter(|e| e.stage > 0)
+            .map(|e| e.path.as_slice())
+            .filter(|path| {
+                pathspecs.is_empty() || pathspecs.iter().any(|p| path_matches(path, p.as_bytes()))
+            })
+            .collect();
+        unmerged.dedup();
+        for path in unmerged {
+            out.extend_from_slice(format!("* Unmerged path {}\n", quote_path(path)).as_bytes());
+        }
+    }
+    let pairs = || file_pairs(&repo, &changes, worktree);
+    diff_write(&mut out, Some(&repo), &changes, pairs, format, &options)?;
+    io::stdout().write_all(&out)?;
+    Ok(false)
+}
+
+/// Writes changes in format, content of files is read with pairs only when the
+/// format needs it.
+fn diff_write<F>(
+    out: &mut Vec<u8>,
+    repo: Option<&RGitRepository>,
+    changes: &[TreeChange],
+    pairs: F,
+    format: DiffFormat,
+    options: &DiffOptions,
+) -> Result<()>
+where
+    F: FnOnce() -> Result<Vec<FilePair>>,
+{
+    match format {
+        DiffFormat::NameOnly => names_write(out, changes, false),
+        DiffFormat::NameStatus => names_write(out, changes, true),
+        DiffFormat::Stat => {
+            let width = env::var("COLUMNS").ok().and_then(|c| c.parse().ok());
+            stat_write(out, &pairs()?, width.unwrap_or(80), options);
+        }
+        DiffFormat::Numstat => numstat_write(out, &pairs()?, options),
+        DiffFormat::Patch => {
+            for pair in &pairs()? {
+                patch_write(out, repo, pair, options)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Rename detection from flags, falling back to diff.renames ("true", "false"
+/// or "copies"). None if it is turned off.
+fn rename_options(
+    repo: &RGitRepository,
+    (renames, copies, off): RenameFlags,
+) -> Result<Option<RenameOptions>> {
+    if off {
+        return Ok(None);
+    }
+    let config = repo.config_get("diff", "renames");
+    let mut options = match config.as_deref() {
+        Some("false") if renames.is_none() && copies.is_none() => return Ok(None),
+        Some("copies" | "copy") => RenameOptions {
+            copies: true,
+            ..Default::default()
+        },
+        _ => RenameOptions::default(),
+    };
+    if let Some(Some(n)) = renames {
+        options.threshold = similarity_parse(n)?;
+    }
+    if let Some(copies) = copies {
+        options.copies = true;
+        if let Some(n) = copies {
+            options.threshold = similarity_parse(n)?;
+        }
+    }
+    Ok(Some(options))
+}
+
+/// Parses similarity of -M and -C: "50%", or digits read as a fraction so
+/// "5" is 50% and "05" is 5%.
+fn similarity_parse(n: &str) -> Result<u8> {
+    let ret = match n.strip_suffix('%') {
+        Some(percent) => percent.parse::<f64>(),
+        None => format!("0.{}", n).parse::<f64>().map(|f| f * 100.0),
+    }
+    .with_context(|| format!("bad similarity {}", n))?;
+    if !(0.0..=100.0).contains(&ret) {
+        bail!("similarity {} is out of range", n);
+    }
+    Ok(ret as u8)
+}
+
+/// File pairs of two files, or of files under two directories by their path
+/// relative to each directory.
+fn no_index_pairs(old: &Path, new: &Path) -> Result<Vec<FilePair>> {
+    let side = |path: &Path| -> Result<DiffSide> {
+        let mode = file_mode(&fs::symlink_metadata(path)?, true);
+        // like git, absolute paths show as "a/tmp/x", not "a//tmp/x".
+        let name = path.as_os_str().as_bytes();
+        let slashes = name.iter().take_while(|&&b| b == b'/').count();
+        DiffSide::file(&name[slashes..], path, mode)
+    };
+    if !old.is_dir() || !new.is_dir() {
+        if old.is_dir() || new.is_dir() {
+            bail!("cannot compare a directory with a file");
+        }
+        let (old, new) = (side(old)?, side(new)?);
+        let pair = FilePair::new(Some(old), Some(new));
+        return Ok(pair.into_iter().collect());
+    }
+
+    let relative_files = |dir: &Path| -> Result<Vec<Vec<u8>>> {
+        Ok(list_files(dir, &[])?
+            .iter()
+            .filter_map(|f| f.strip_prefix(dir).ok().map(slash_path_bytes))
+            .collect())
+    };
+    let mut files = relative_files(old)?;
+    files.extend(relative_files(new)?);
+    files.sort();
+    files.dedup();
+
+    let mut ret = vec![];
+    for file in files {
+        let (old, new) = (old.join(bytes_path(&file)), new.join(bytes_path(&file)));
+        let old = fs::symlink_metadata(&old)
+            .is_ok()
+            .then(|| side(&old))
+            .transpose()?;
+        let new = fs::symlink_metadata(&new)
+            .is_ok()
+            .then(|| side(&new))
+            .transpose()?;
+        ret.extend(FilePair::new(old, new));
+    }
+    Ok(ret)
+}
+
+/// Returns false if no path was ignored.
+fn cmd_check_ignore(verbose: bool, paths: &[String]) -> Result<bool> {
+    let repo = repo_find::<&str>(None, None)?.context("repo not found")?;
+
+    let ignore = Ignore::for_repo(&repo)?;
+    let index = Index::read(&repo)?;
+    let mut ignored = false;
+    for path in paths {
+        let relative = repo.relative_path(Path::new(path))?;
+        // tracked files are not subject to ignore rules.
+        if index.entries.iter().any(|e| e.path == relative.as_bytes()) {
+            continue;
+        }
+        let pattern = match ignore.pattern_for(&relative, Path::new(path).is_dir())? {
+            Some(pattern) if verbose || !pattern.is_negated() => pattern,
+            _ => continue,
+        };
+        ignored = true;
+        match verbose {
+            true => println!(
+                "{}:{}:{}\t{}",
+                pattern.source, pattern.line_number, pattern.line, path
+            ),
+            false => println!("{}", path),
+        }
     }
-    seen.insert(sha.to_string());
+    Ok(ignored)
+}
+
+fn cmd_write_tree(directory: Option<&str>) -> Result<()> {
+    let repo = repo_find::<&str>(None, None)?.context("repo not found")?;
+
+    let sha = match directory {
+        Some(directory) => {
+            let skip = vec![
+                ignore_by_file_name(".git".into()),
+                Rc::new(Ignore::new(Path::new(directory))).skip_predicate(),
+            ];
+            FileNode::traverse_and_build(Path::new(directory), skip)?
+                .write_tree(&repo, index::filemode(&repo))?
+        }
+        None => Index::read(&repo)?.write_tree(&repo)?,
+    };
+    println!("{}", sha);
+    Ok(())
+}
+
+fn cmd_commit_tree(
+    tree: &str,
+    parents: &[String],
+    message: &[String],
+    file: Option<&str>,
+) -> Result<()> {
+    let repo = repo_find::<&str>(None, None)?.context("repo not found")?;
 
-    let commit = repo.object_read(sha)?;
-    assert_eq!(
-        commit.object_type.context("object type is None")?,
-        GitObjectType::Commit
+    let message = match message_read(message, file)? {
+        Some(message) => message,
+        None => stdin_read()?,
+    };
+    let commit = Commit {
+        tree: repo.object_find(tree, Some("tree".into()), None)?,
+        parents: parents
+            .iter()
+            .map(|p| repo.object_find(p, Some("commit".into()), None))
+            .collect::<Result<_>>()?,
+        author: identity(&repo, "AUTHOR")?,
+        committer: identity(&repo, "COMMITTER")?,
+        encoding: None,
+        extra_headers: vec![],
+        message: message.into_bytes(),
+    };
+    println!("{}", commit.write(&repo)?);
+    Ok(())
+}
+
+fn cmd_commit(
+    message: &[String],
+    file: Option<&str>,
+    amend: bool,
+    allow_empty: bool,
+) -> Result<()> {
+    let repo = repo_find::<&str>(None, None)?.context("repo not found")?;
+
+    let message = message_read(message, file)?;
+    let (sha, commit) = commit_index(&repo, message, amend, allow_empty)?;
+
+    let branch = match head_branch(&repo)? {
+        Some(branch) => branch,
+        None => "detached HEAD".to_string(),
+    };
+    let root = match commit.parents.is_empty() {
+        true => " (root-commit)",
+        false => "",
+    };
+    println!(
+        "[{}{} {}] {}",
+        branch,
+        root,
+        repo.abbreviate(&sha, 7)?,
+        commit.summary()
     );
+    Ok(())
+}
 
-    let kvlm = commit.kvlm.context("kvlm is empty")?;
-    if !kvlm.contains_key("parent") {
-        // initial commit.
-        return Ok(());
+/// Commit message from -m paragraphs or -F file ("-" reads standard input),
+/// None if neither is given.
+fn message_read(message: &[String], file: Option<&str>) -> Result<Option<String>> {
+    match (message.is_empty(), file) {
+        (false, Some(_)) => bail!("options -m and -F cannot be used together"),
+        (true, Some("-")) => Ok(Some(stdin_read()?)),
+        (true, Some(file)) => Ok(Some(
+            fs::read_to_string(file).with_context(|| format!("could not read {}", file))?,
+        )),
+        (false, None) => Ok(Some(
+            message
+                .iter()
+                .map(|m| format!("{}\n", m))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        )),
+        (true, None) => Ok(None),
     }
+}
 
-    let parents = &kvlm["parent"];
+fn stdin_read() -> Result<String> {
+    let mut ret = String::new();
+    io::stdin().read_to_string(&mut ret)?;
+    Ok(ret)
+}
 
-    for p in parents {
-        println!("c_{} -> c_{}", sha, p);
-        log_graphviz(repo, p, seen)?
+fn cmd_checkout(target: Option<&str>, paths: &[String]) -> Result<()> {
+    let repo = repo_find::<&str>(None, None)?.context("repo not found")?;
+
+    if !paths.is_empty() {
+        return checkout_paths(&repo, target, paths);
     }
+    let target = target.context("nothing to checkout, give a branch, commit or paths")?;
 
+    let previous = head_branch(&repo)?;
+    checkout(&repo, target)?;
+    match head_branch(&repo)? {
+        Some(branch) if previous.as_ref() == Some(&branch) => println!("Already on '{}'", branch),
+        Some(branch) => println!("Switched to branch '{}'", branch),
+        None => {
+            let sha = repo.object_find("HEAD", None, None)?;
+            println!(
+                "HEAD is now at {} {}",
+                repo.abbreviate(&sha, 7)?,
+                repo.object_read(&sha)?.commit()?.summary()
+            );
+        }
+    }
     Ok(())
 }
+
+fn cmd_ls_files(stage: bool, debug: bool) -> Result<()> {
+    let repo = repo_find::<&str>(None, None)?.context("repo not found")?;
+
+    for e in Index::read(&repo)?.entries {
+        match stage {
+            true => println!(
+                "{:06o} {} {}\t{}",
+                e.mode,
+                e.sha,
+                e.stage,
+                quote_path(&e.path)
+            ),
+            false => println!("{}", quote_path(&e.path)),
+        }
+        if debug {
+            println!("  ctime: {}:{}", e.ctime.0, e.ctime.1);
+            println!("  mtime: {}:{}", e.mtime.0, e.mtime.1);
+            println!("  dev: {}\tino: {}", e.dev, e.ino);
+            println!("  uid: {}\tgid: {}", e.uid, e.gid);
+            println!("  size: {}\tflags: {:x}", e.size, (e.stage as u32) << 12);
+        }
+    }
+    Ok(())
+}
+
+fn cmd_rev_parse(
+    show_toplevel: bool,
+    git_dir: bool,
+    verify: bool,
+    short: Option<usize>,
+    revisions: &[String],
+) -> Result<()> {
+    let repo = repo_find::<&str>(None, None)?.context("repo not found")?;
+
+    if show_toplevel {
+        println!("{}", fs::canonicalize(repo.work_tree())?.display());
+    }
+    if git_dir {
+        match repo.work_tree() == Path::new(".") {
+            true => println!(".git"),
+            false => println!("{}", fs::canonicalize(repo.git_dir())?.display()),
+        }
+    }
+
+    let print = |sha: &str, negated: bool| -> Result<()> {
+        let name = match short {
+            Some(len) => repo.abbreviate(sha, len)?,
+            None => sha.to_string(),
+        };
+        println!("{}{}", if negated { "^" } else { "" }, name);
+        Ok(())
+    };
+
+    if verify || short.is_some() {
+        if revisions.len() != 1 {
+            bail!("needed a single revision");
+        }
+        return print(&repo.object_find(&revisions[0], None, None)?, false);
+    }
+
+    for revision in revisions {
+        match range_parse(revision) {
+            RevRange::Symmetric(from, to) => {
+                print(&repo.object_find(&to, None, None)?, false)?;
+                print(&repo.object_find(&from, None, None)?, false)?;
+                let commit = |rev: &str| repo.object_find(rev, Some("commit".into()), None);
+                for base in merge_bases(&repo, &commit(&from)?, &commit(&to)?)? {
+                    print(&base, true)?;
+                }
+            }
+            RevRange::Between(from, to) => {
+                print(&repo.object_find(&to, None, None)?, false)?;
+                print(&repo.object_find(&from, None, None)?, true)?;
+            }
+            RevRange::Single(revision) => match revision.strip_prefix('^') {
+                Some(rev) => print(&repo.object_find(rev, None, None)?, true)?,
+                None => print(&repo.object_find(&revision, None, None)?, false)?,
+            },
+        }
+    }
+    Ok(())
+}
+
+fn cmd_repack(all: bool, delete: bool, window: Option<usize>, depth: Option<usize>) -> Result<()> {
+    let repo = repo_find::<&str>(None, None)?.context("repo not found")?;
+
+    let (window, depth) = pack_options(&repo, window, depth)?;
+    match repack(&repo, all, delete, window, depth)? {
+        Some(name) => println!("pack-{}", name),
+        None => println!("Nothing new to pack."),
+    }
+    Ok(())
+}
+
+fn cmd_gc(window: Option<usize>, depth: Option<usize>) -> Result<()> {
+    let repo = repo_find::<&str>(None, None)?.context("repo not found")?;
+
+    let (window, depth) = pack_options(&repo, window, depth)?;
+    pack_refs(&repo)?;
+    repack(&repo, true, true, window, depth)?;
+    Ok(())
+}
+
+/// Delta window and depth from arguments, falling back to pack.window and pack.depth config.
+fn pack_options(
+    repo: &RGitRepository,
+    window: Option<usize>,
+    depth: Option<usize>,
+) -> Result<(usize, usize)> {
+    let config = |key: &str, default: usize| -> Result<usize> {
+        match repo.config_get("pack", key) {
+            Some(v) => v
+                .parse()
+                .with_context(|| format!("invalid pack.{} {}", key, v)),
+            None => Ok(default),
+        }
+    };
+    Ok((
+        window.map_or_else(|| config("window", 10), Ok)?,
+        depth.map_or_else(|| config("depth", 50), Ok)?,
+    ))
+}
+
+fn cmd_tag(annotate: bool, message: Option<&str>, name: Option<&str>, object: &str) -> Result<()> {
+    let repo = repo_find::<&str>(None, None)?.context("repo not found")?;
+
+    let name = match name {
+        Some(name) => name,
+        None => {
+            for tag in ref_list(&repo, "refs/tags")?.keys() {
+                println!("{}", tag.trim_start_matches("refs/tags/"));
+            }
+            return Ok(());
+        }
+    };
+
+    let reference = format!("refs/tags/{}", name);
+    if ref_list(&repo, "refs/tags")?.contains_key(&reference) {
+        bail!("tag '{}' already exists", name);
+    }
+
+    let sha = repo.object_find(object, None, None)?;
+    if !annotate && message.is_none() {
+        return ref_create(&repo, &reference, &sha);
+    }
+
+    let message = message.context("annotated tag requires a message (-m)")?;
+    let sha = tag_create(&repo, name, &sha, message)?;
+    ref_create(&repo, &reference, &sha)
+}
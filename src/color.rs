@@ -0,0 +1,173 @@
+use anyhow::{bail, Context};
+use clap::clap_derive::ArgEnum;
+use std::{env, str::FromStr};
+
+use crate::{repository::RGitRepository, Result};
+
+// git's default palette.
+pub const RESET: &str = "\x1b[m";
+pub const BOLD: &str = "\x1b[1m";
+pub const RED: &str = "\x1b[31m";
+pub const GREEN: &str = "\x1b[32m";
+pub const YELLOW: &str = "\x1b[33m";
+pub const BLUE: &str = "\x1b[34m";
+pub const CYAN: &str = "\x1b[36m";
+pub const BG_RED: &str = "\x1b[41m";
+
+/// When output is painted.
+#[derive(Debug, Clone, Copy, ArgEnum, PartialEq, Eq)]
+pub enum ColorWhen {
+    Always,
+    Never,
+    Auto,
+}
+
+impl FromStr for ColorWhen {
+    type Err = anyhow::Error;
+
+    /// Values of color.* config, where booleans mean auto and never.
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s.to_lowercase().as_str() {
+            "always" => ColorWhen::Always,
+            "never" | "false" | "no" | "off" | "0" => ColorWhen::Never,
+            "auto" | "true" | "yes" | "on" | "1" => ColorWhen::Auto,
+            _ => bail!("bad color value '{}'", s),
+        })
+    }
+}
+
+/// Whether output of command is painted: when says so, else color.<command>
+/// or color.ui of repo, else only if stdout is a terminal that knows colors.
+pub fn color_enabled(
+    repo: Option<&RGitRepository>,
+    command: &str,
+    when: Option<ColorWhen>,
+) -> Result<bool> {
+    let when = match when {
+        Some(when) => when,
+        None => match repo.and_then(|repo| {
+            repo.config_get("color", command)
+                .or_else(|| repo.config_get("color", "ui"))
+        }) {
+            Some(value) => value.parse()?,
+            None => ColorWhen::Auto,
+        },
+    };
+    Ok(match when {
+        ColorWhen::Always => true,
+        ColorWhen::Never => false,
+        ColorWhen::Auto => {
+            atty::is(atty::Stream::Stdout) && env::var("TERM").map_or(false, |term| term != "dumb")
+        }
+    })
+}
+
+/// Writes text in color if painting, as is otherwise.
+pub fn paint(out: &mut Vec<u8>, color: bool, code: &str, text: &[u8]) {
+    match color {
+        true => {
+            out.extend_from_slice(code.as_bytes());
+            out.extend_from_slice(text);
+            out.extend_from_slice(RESET.as_bytes());
+        }
+        false => out.extend_from_slice(text),
+    }
+}
+
+const COLOR_NAMES: [&str; 8] = [
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+];
+
+const ATTRIBUTES: [(&str, u8); 7] = [
+    ("bold", 1),
+    ("dim", 2),
+    ("italic", 3),
+    ("ul", 4),
+    ("blink", 5),
+    ("reverse", 7),
+    ("strike", 9),
+];
+
+/// ANSI code of a color as git config and formats name it, like "red" or
+/// "bold yellow black": attributes in any order, then foreground and
+/// background, each a name, "bright" name, 0-255 or "#rrggbb".
+pub fn parse_color(spec: &str) -> Result<String> {
+    if spec.trim().eq_ignore_ascii_case("reset") {
+        return Ok(RESET.to_string());
+    }
+    let mut attributes = vec![];
+    let mut colors = vec![];
+    for word in spec.split_whitespace().map(str::to_lowercase) {
+        if let Some((_, code)) = ATTRIBUTES.iter().find(|(name, _)| *name == word) {
+            attributes.push(*code);
+            continue;
+        }
+        if colors.len() == 2 {
+            bail!("bad color '{}'", spec);
+        }
+        // foreground codes, background ones are 10 more.
+        let color = match word.as_str() {
+            "normal" => None,
+            "default" => Some("39".to_string()),
+            word => match (
+                COLOR_NAMES.iter().position(|name| *name == word),
+                word.strip_prefix("bright")
+                    .and_then(|name| COLOR_NAMES.iter().position(|n| *n == name)),
+            ) {
+                (Some(i), _) => Some((30 + i).to_string()),
+                (_, Some(i)) => Some((90 + i).to_string()),
+                _ => match (word.parse::<u8>(), word.strip_prefix('#')) {
+                    (Ok(n), _) => Some(format!("38;5;{}", n)),
+                    (_, Some(hex)) if hex.len() == 6 => {
+                        let rgb = u32::from_str_radix(hex, 16)
+                            .with_context(|| format!("bad color '{}'", spec))?;
+                        Some(format!(
+                            "38;2;{};{};{}",
+                            rgb >> 16,
+                            (rgb >> 8) & 0xff,
+                            rgb & 0xff
+                        ))
+                    }
+                    _ => bail!("bad color '{}'", spec),
+                },
+            },
+        };
+        colors.push(color);
+    }
+    attributes.sort_unstable();
+    attributes.dedup();
+    let mut codes: Vec<String> = attributes.iter().map(u8::to_string).collect();
+    for (i, color) in colors.into_iter().enumerate() {
+        if let Some(color) = color {
+            codes.push(match i {
+                0 => color,
+                // "3x" becomes "4x", "9x" "10x" and "38;..." "48;...".
+                _ if color.starts_with('9') => format!("10{}", &color[1..]),
+                _ => format!("4{}", &color[1..]),
+            });
+        }
+    }
+    Ok(match codes.is_empty() {
+        true => String::new(),
+        false => format!("\x1b[{}m", codes.join(";")),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_color;
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(parse_color("red").unwrap(), "\x1b[31m");
+        assert_eq!(parse_color("yellow ul").unwrap(), "\x1b[4;33m");
+        assert_eq!(parse_color("bold blue black").unwrap(), "\x1b[1;34;40m");
+        assert_eq!(parse_color("normal brightred").unwrap(), "\x1b[101m");
+        assert_eq!(
+            parse_color("208 #ff0000").unwrap(),
+            "\x1b[38;5;208;48;2;255;0;0m"
+        );
+        assert_eq!(parse_color("normal").unwrap(), "");
+        assert!(parse_color("red green blue").is_err());
+    }
+}
@@ -0,0 +1,713 @@
+use anyhow::{bail, Context};
+use indexmap::IndexMap;
+use std::{
+    borrow::Cow,
+    env,
+    fmt::Display,
+    str::{from_utf8, FromStr},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    index::Index,
+    object::{GitObject, GitObjectType, Kvlm},
+    refs::{head_update, ref_resolve},
+    repository::RGitRepository,
+    Result,
+};
+
+/// SHA of the tree without entries.
+pub const EMPTY_TREE: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+
+/// Identity and timestamp of an author, committer or tagger, written by git as
+/// "Name <email> 1527025023 +0200".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub name: String,
+    pub email: String,
+    /// Seconds since unix epoch.
+    pub time: i64,
+    /// Offset from UTC in minutes.
+    pub tz_offset: i32,
+}
+
+impl FromStr for Signature {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let lt = s.find('<').context("signature without '<'")?;
+        let gt = s[lt..]
+            .find('>')
+            .map(|i| i + lt)
+            .context("signature without '>'")?;
+
+        let name = s[..lt].strip_suffix(' ').unwrap_or(&s[..lt]);
+        let email = &s[lt + 1..gt];
+
+        let mut rest = s[gt + 1..].split_whitespace();
+        let time = rest
+            .next()
+            .context("signature without timestamp")?
+            .parse()
+            .with_context(|| format!("bad timestamp in signature {}", s))?;
+        let tz_offset = parse_tz_offset(rest.next().context("signature without timezone")?)?;
+
+        Ok(Self {
+            name: name.into(),
+            email: email.into(),
+            time,
+            tz_offset,
+        })
+    }
+}
+
+impl Display for Signature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} <{}> {} {}",
+            self.name,
+            self.email,
+            self.time,
+            format_tz_offset(self.tz_offset)
+        )
+    }
+}
+
+/// Parses "+0200" style timezone into minutes.
+pub fn parse_tz_offset(tz: &str) -> Result<i32> {
+    let sign = match tz.chars().next() {
+        Some('+') => 1,
+        Some('-') => -1,
+        _ => bail!("bad timezone {}", tz),
+    };
+    if tz.len() != 5 || !tz[1..].chars().all(|c| c.is_ascii_digit()) {
+        bail!("bad timezone {}", tz);
+    }
+    let hours: i32 = tz[1..3].parse()?;
+    let minutes: i32 = tz[3..5].parse()?;
+    Ok(sign * (hours * 60 + minutes))
+}
+
+/// Formats offset in minutes as "+0200" style timezone.
+pub fn format_tz_offset(tz_offset: i32) -> String {
+    let sign = if tz_offset < 0 { '-' } else { '+' };
+    let abs = tz_offset.abs();
+    format!("{}{:02}{:02}", sign, abs / 60, abs % 60)
+}
+
+/// Offset in minutes of the local timezone at given time, as TZ or
+/// /etc/localtime set it.
+pub fn local_tz_offset(time: i64) -> i32 {
+    let time = time as libc::time_t;
+    // SAFETY: localtime_r only writes to tm, which outlives the call.
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    match unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        true => 0,
+        false => (tm.tm_gmtoff / 60) as i32,
+    }
+}
+
+/// Parses date the way GIT_AUTHOR_DATE and GIT_COMMITTER_DATE are given: git's
+/// raw "1527025023 +0200" (optionally prefixed by "@"), RFC 2822
+/// "Thu, 07 Apr 2005 22:13:13 +0200" or ISO 8601 "2005-04-07T22:13:13+02:00".
+/// Returns seconds since unix epoch and timezone offset in minutes.
+pub fn parse_date(date: &str) -> Result<(i64, i32)> {
+    let date = date.trim();
+    let (stamp, tz) = match date.rsplit_once(' ') {
+        Some((stamp, tz)) if parse_tz_offset(tz).is_ok() => {
+            (stamp.trim(), Some(parse_tz_offset(tz)?))
+        }
+        _ => (date, None),
+    };
+
+    let raw = stamp.strip_prefix('@').unwrap_or(stamp);
+    if !raw.is_empty() && raw.bytes().all(|b| b.is_ascii_digit()) {
+        return Ok((raw.parse()?, tz.unwrap_or(0)));
+    }
+
+    // RFC 2822, the day of week is optional.
+    let rfc = stamp.split_once(", ").map_or(stamp, |(_, rest)| rest);
+    if let [day, month, year, time] = rfc.split_whitespace().collect::<Vec<_>>()[..] {
+        let month = MONTHS
+            .iter()
+            .position(|m| m.eq_ignore_ascii_case(month))
+            .with_context(|| format!("bad month in date {}", date))?;
+        let days = days_from_civil(year.parse()?, month as i64 + 1, day.parse()?);
+        let tz = tz.with_context(|| format!("no timezone in date {}", date))?;
+        return Ok((days * 86400 + parse_time(time)? - tz as i64 * 60, tz));
+    }
+
+    // ISO 8601, the timezone may be attached to the time.
+    let (day, time) = stamp
+        .split_once(|c| c == 'T' || c == ' ')
+        .with_context(|| format!("unsupported date format {}", date))?;
+    let ymd: Vec<i64> = day
+        .split('-')
+        .map(str::parse)
+        .collect::<std::result::Result<_, _>>()
+        .with_context(|| format!("bad date {}", date))?;
+    if ymd.len() != 3 || time.len() < 8 {
+        bail!("bad date {}", date);
+    }
+    let (time, mut zone) = time.split_at(8);
+    zone = zone.trim_start_matches(|c: char| c == '.' || c.is_ascii_digit());
+    let tz = match zone {
+        "" => tz.unwrap_or(0),
+        "Z" => 0,
+        zone => parse_tz_offset(&zone.replace(':', ""))?,
+    };
+    let days = days_from_civil(ymd[0], ymd[1], ymd[2]);
+    Ok((days * 86400 + parse_time(time)? - tz as i64 * 60, tz))
+}
+
+/// Parses date the loose way --since and --until take it, relative to now:
+/// "now", "yesterday", "2 weeks ago" (or "2.weeks.ago") in seconds up to
+/// years, anything parse_date reads, or a bare "2005-04-07" at the current
+/// time of day. Returns seconds since unix epoch.
+pub fn approxidate(date: &str, now: i64) -> Result<i64> {
+    let date = date.trim();
+    let lower = date.to_lowercase();
+    let words: Vec<&str> = lower
+        .split(|c: char| c == '.' || c.is_whitespace())
+        .filter(|w| !w.is_empty())
+        .collect();
+    let (n, unit) = match words[..] {
+        ["now"] => return Ok(now),
+        ["yesterday"] => return Ok(now - 86400),
+        [n, unit] | [n, unit, "ago"] if n.parse::<i64>().is_ok() => (n.parse::<i64>()?, unit),
+        _ => {
+            if let Ok((time, _)) = parse_date(date) {
+                return Ok(time);
+            }
+            let ymd: Vec<i64> = date
+                .split('-')
+                .map(str::parse)
+                .collect::<std::result::Result<_, _>>()
+                .with_context(|| format!("bad date {}", date))?;
+            match ymd[..] {
+                [year, month, day] => {
+                    return Ok(days_from_civil(year, month, day) * 86400 + now.rem_euclid(86400))
+                }
+                _ => bail!("bad date {}", date),
+            }
+        }
+    };
+    let seconds = match unit.strip_suffix('s').unwrap_or(unit) {
+        "second" => 1,
+        "minute" => 60,
+        "hour" => 3600,
+        "day" => 86400,
+        "week" => 7 * 86400,
+        // months and years go by the calendar, days past the end of a month
+        // spilling into the next one.
+        unit @ ("month" | "year") => {
+            let (year, month, day) = civil_from_days(now.div_euclid(86400));
+            let months = year * 12 + month
+                - 1
+                - match unit {
+                    "month" => n,
+                    _ => n * 12,
+                };
+            let days = days_from_civil(months.div_euclid(12), months.rem_euclid(12) + 1, day);
+            return Ok(days * 86400 + now.rem_euclid(86400));
+        }
+        _ => bail!("bad date {}", date),
+    };
+    Ok(now - n * seconds)
+}
+
+pub const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Parses "22:13:13" into seconds since midnight.
+fn parse_time(time: &str) -> Result<i64> {
+    let hms: Vec<i64> = time
+        .split(':')
+        .map(str::parse)
+        .collect::<std::result::Result<_, _>>()
+        .with_context(|| format!("bad time {}", time))?;
+    match hms[..] {
+        [h, m, s] => Ok(h * 3600 + m * 60 + s),
+        _ => bail!("bad time {}", time),
+    }
+}
+
+/// Number of days since 1970-01-01 of a date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Date (year, month, day) of a number of days since 1970-01-01, the inverse
+/// of days_from_civil.
+pub fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = if days >= 0 { days } else { days - 146096 } / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = match month_index < 10 {
+        true => month_index + 3,
+        false => month_index - 9,
+    };
+    (year_of_era + era * 400 + (month <= 2) as i64, month, day)
+}
+
+/// Identity of the author or committer (role "AUTHOR" or "COMMITTER"), from
+/// user.name and user.email, overridden by GIT_<role>_NAME, GIT_<role>_EMAIL
+/// and GIT_<role>_DATE. Defaults to "rgit <rgit@localhost>" and current local
+/// time.
+pub fn identity(repo: &RGitRepository, role: &str) -> Result<Signature> {
+    let var = |key: &str| {
+        env::var(format!("GIT_{}_{}", role, key))
+            .ok()
+            .filter(|v| !v.is_empty())
+    };
+
+    let name = var("NAME")
+        .or_else(|| repo.config_get("user", "name"))
+        .unwrap_or_else(|| "rgit".into());
+    let email = var("EMAIL")
+        .or_else(|| repo.config_get("user", "email"))
+        .unwrap_or_else(|| "rgit@localhost".into());
+    let (time, tz_offset) = match var("DATE") {
+        Some(date) => parse_date(&date)?,
+        None => {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+            (now, local_tz_offset(now))
+        }
+    };
+
+    Ok(Signature {
+        name,
+        email,
+        time,
+        tz_offset,
+    })
+}
+
+/// Typed view over commit's kvlm.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Commit {
+    pub tree: String,
+    pub parents: Vec<String>,
+    pub author: Signature,
+    pub committer: Signature,
+    pub encoding: Option<String>,
+    /// Remaining headers like gpgsig or mergetag, in order of appearance.
+    pub extra_headers: Vec<(String, Vec<u8>)>,
+    /// Message bytes as stored, in encoding, or UTF-8 if it is None.
+    pub message: Vec<u8>,
+}
+
+impl Commit {
+    pub fn from_kvlm(kvlm: &Kvlm) -> Result<Self> {
+        let text = |key: &str, value: &[u8]| -> Result<String> {
+            let value = from_utf8(value).with_context(|| format!("{} is not valid utf-8", key))?;
+            Ok(value.to_string())
+        };
+        let single = |key: &str| -> Result<&[u8]> {
+            match kvlm.get(key).map(Vec::as_slice) {
+                Some([value]) => Ok(value),
+                Some(_) => bail!("commit has multiple {} headers", key),
+                None => bail!("commit has no {} header", key),
+            }
+        };
+        // names may be in the commit's encoding, so they are decoded lossily.
+        let signature =
+            |key: &str| -> Result<Signature> { String::from_utf8_lossy(single(key)?).parse() };
+
+        let encoding = match kvlm.get("encoding") {
+            Some(_) => Some(text("encoding", single("encoding")?)?),
+            None => None,
+        };
+
+        let extra_headers = kvlm
+            .iter()
+            .filter(|(k, _)| {
+                !["", "tree", "parent", "author", "committer", "encoding"].contains(&k.as_str())
+            })
+            .flat_map(|(k, values)| values.iter().map(move |v| (k.clone(), v.clone())))
+            .collect();
+
+        let parents = kvlm.get("parent").map(Vec::as_slice).unwrap_or_default();
+        Ok(Self {
+            tree: text("tree", single("tree")?)?,
+            parents: parents
+                .iter()
+                .map(|p| text("parent", p))
+                .collect::<Result<_>>()?,
+            author: signature("author")?,
+            committer: signature("committer")?,
+            encoding,
+            extra_headers,
+            message: kvlm
+                .get("")
+                .and_then(|m| m.first())
+                .cloned()
+                .unwrap_or_default(),
+        })
+    }
+
+    /// Builds kvlm with headers in the order git writes them.
+    pub fn to_kvlm(&self) -> Kvlm {
+        let mut kvlm = IndexMap::new();
+        kvlm.insert("tree".to_string(), vec![self.tree.clone().into_bytes()]);
+        if !self.parents.is_empty() {
+            let parents = self.parents.iter().map(|p| p.clone().into_bytes());
+            kvlm.insert("parent".to_string(), parents.collect());
+        }
+        kvlm.insert(
+            "author".to_string(),
+            vec![self.author.to_string().into_bytes()],
+        );
+        kvlm.insert(
+            "committer".to_string(),
+            vec![self.committer.to_string().into_bytes()],
+        );
+        if let Some(encoding) = &self.encoding {
+            kvlm.insert("encoding".to_string(), vec![encoding.clone().into_bytes()]);
+        }
+        for (k, v) in &self.extra_headers {
+            kvlm.entry(k.clone())
+                .or_insert_with(Vec::new)
+                .push(v.clone());
+        }
+        kvlm.insert("".to_string(), vec![self.message.clone()]);
+        kvlm
+    }
+
+    /// Message for display, bytes that are not UTF-8 are replaced.
+    pub fn message_text(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.message)
+    }
+
+    /// First line of the message.
+    pub fn summary(&self) -> String {
+        self.message_text()
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    /// Writes commit object, returns its SHA.
+    pub fn write(&self, repo: &RGitRepository) -> Result<String> {
+        let mut object = GitObject::new(repo, None, Some(GitObjectType::Commit))?;
+        object.kvlm_set(self.to_kvlm());
+        object.object_write(None)
+    }
+}
+
+/// Strips trailing whitespace of lines and leading and trailing blank lines,
+/// and collapses runs of blank lines, like git does with -m and -F messages.
+pub fn message_cleanup(message: &str) -> String {
+    let mut ret = String::new();
+    let mut blank = false;
+    for line in message.lines().map(str::trim_end) {
+        if line.is_empty() {
+            blank = !ret.is_empty();
+            continue;
+        }
+        if blank {
+            ret.push('\n');
+            blank = false;
+        }
+        ret += line;
+        ret.push('\n');
+    }
+    ret
+}
+
+/// Commits the index on top of HEAD and advances the current branch, or
+/// detached HEAD. With amend the HEAD commit is replaced, keeping its parents,
+/// author and, if no message is given, message. Returns the new commit.
+pub fn commit_index(
+    repo: &RGitRepository,
+    message: Option<String>,
+    amend: bool,
+    allow_empty: bool,
+) -> Result<(String, Commit)> {
+    let head = ref_resolve(repo, "HEAD")?;
+    let previous = match (&head, amend) {
+        (Some(sha), true) => Some(repo.object_read(sha)?.commit()?),
+        (None, true) => bail!("nothing to amend, there are no commits yet"),
+        (_, false) => None,
+    };
+    let parents = match &previous {
+        Some(previous) => previous.parents.clone(),
+        None => head.iter().cloned().collect(),
+    };
+
+    let tree = Index::read(repo)?.write_tree(repo)?;
+    if !amend && !allow_empty {
+        let parent_tree = match parents.first() {
+            Some(parent) => repo.object_read(parent)?.commit()?.tree,
+            None => EMPTY_TREE.to_string(),
+        };
+        if tree == parent_tree {
+            bail!("nothing to commit, use --allow-empty to commit anyway");
+        }
+    }
+
+    // a reused message is kept as stored, with its encoding.
+    let (message, encoding) = match (message, &previous) {
+        (Some(message), _) => (message_cleanup(&message).into_bytes(), None),
+        (None, Some(previous)) => (previous.message.clone(), previous.encoding.clone()),
+        (None, None) => bail!("no commit message given, use -m or -F"),
+    };
+    if message.is_empty() {
+        bail!("aborting commit due to empty commit message");
+    }
+
+    let committer = identity(repo, "COMMITTER")?;
+    let commit = Commit {
+        tree,
+        parents,
+        author: match &previous {
+            Some(previous) => previous.author.clone(),
+            None => identity(repo, "AUTHOR")?,
+        },
+        committer: committer.clone(),
+        encoding,
+        extra_headers: vec![],
+        message,
+    };
+    let sha = commit.write(repo)?;
+
+    let kind = match (amend, &head) {
+        (true, _) => " (amend)",
+        (false, None) => " (initial)",
+        (false, Some(_)) => "",
+    };
+    let reflog = format!("commit{}: {}", kind, commit.summary());
+    head_update(repo, head.as_deref(), &sha, &committer, &reflog)?;
+    Ok((sha, commit))
+}
+
+/// Writes annotated tag object pointing at object `sha` of any type, returns tag's SHA.
+pub fn tag_create(repo: &RGitRepository, name: &str, sha: &str, message: &str) -> Result<String> {
+    let target = repo.object_read(sha)?;
+    let target_type = target.object_type.context("object type is None")?;
+
+    let tagger = identity(repo, "COMMITTER")?;
+
+    let mut message = message.to_string();
+    if !message.ends_with('\n') {
+        message.push('\n');
+    }
+
+    let mut tag = GitObject::new(repo, None, Some(GitObjectType::Tag))?;
+    tag.kvlm_set(IndexMap::from([
+        ("object".to_string(), vec![sha.as_bytes().to_vec()]),
+        (
+            "type".to_string(),
+            vec![target_type.to_string().into_bytes()],
+        ),
+        ("tag".to_string(), vec![name.as_bytes().to_vec()]),
+        ("tagger".to_string(), vec![tagger.to_string().into_bytes()]),
+        ("".to_string(), vec![message.into_bytes()]),
+    ]));
+    tag.object_write(None)
+}
+
+impl<'a> GitObject<'a> {
+    /// Returns typed view of a commit object.
+    pub fn commit(&self) -> Result<Commit> {
+        if self.object_type != Some(GitObjectType::Commit) {
+            bail!("object is not a commit");
+        }
+        Commit::from_kvlm(self.kvlm().context("kvlm is empty")?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        approxidate, civil_from_days, commit_index, message_cleanup, parse_date, tag_create,
+        Commit, Signature,
+    };
+    use crate::{
+        object::{kvlm_parse, kvlm_serialize, GitObject, GitObjectType},
+        refs::{ref_create, ref_list},
+        testing::TempRepo,
+    };
+    use std::fs;
+
+    #[test]
+    fn test_signature_parse() {
+        let sig: Signature = "Thibault Polge <thibault@thb.lt> 1527025023 -0130"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            sig,
+            Signature {
+                name: "Thibault Polge".into(),
+                email: "thibault@thb.lt".into(),
+                time: 1527025023,
+                tz_offset: -90,
+            }
+        );
+        assert_eq!(
+            sig.to_string(),
+            "Thibault Polge <thibault@thb.lt> 1527025023 -0130"
+        );
+    }
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(parse_date("1527025023 -0130").unwrap(), (1527025023, -90));
+        assert_eq!(parse_date("@1527025023").unwrap(), (1527025023, 0));
+        assert_eq!(
+            parse_date("Thu, 07 Apr 2005 22:13:13 +0200").unwrap(),
+            (1112904793, 120)
+        );
+        assert_eq!(
+            parse_date("2005-04-07T22:13:13+02:00").unwrap(),
+            (1112904793, 120)
+        );
+        assert_eq!(parse_date("2005-04-07 20:13:13Z").unwrap(), (1112904793, 0));
+        assert!(parse_date("yesterday").is_err());
+
+        let now = 1112904793;
+        assert_eq!(approxidate("2 weeks ago", now).unwrap(), now - 14 * 86400);
+        assert_eq!(approxidate("1.hour.ago", now).unwrap(), now - 3600);
+        assert_eq!(approxidate("yesterday", now).unwrap(), now - 86400);
+        assert_eq!(approxidate("3 months ago", now).unwrap(), 1105128793);
+        assert_eq!(approxidate("2005-01-07", now).unwrap(), 1105128793);
+        assert_eq!(approxidate("2005-04-07T22:13:13+02:00", now).unwrap(), now);
+        assert!(approxidate("2 fortnights ago", now).is_err());
+
+        assert_eq!(civil_from_days(1112904793 / 86400), (2005, 4, 7));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+    }
+
+    #[test]
+    fn test_message_cleanup() {
+        assert_eq!(
+            message_cleanup("\n\n  summary  \n\n\n\nbody\t\n\n"),
+            "  summary\n\nbody\n"
+        );
+        assert_eq!(message_cleanup(" \n\n"), "");
+    }
+
+    #[test]
+    fn test_commit_round_trip() {
+        let content = concat!(
+            "tree 29ff16c9c14e2652b22f8b78bb08a5a07930c147\n",
+            "parent 206941306e8a8af65b66eaaaea388a7ae24d49a0\n",
+            "parent 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n",
+            "author Żaneta Kowalska <zk@example.com> 1527025023 +0200\n",
+            "committer Thibault Polge <thibault@thb.lt> 1527025044 +0200\n",
+            "encoding ISO-8859-2\n",
+            "gpgsig -----BEGIN PGP SIGNATURE-----\n",
+            " \n",
+            " iQIzBAABCAAdFiEExwXquOM8bWb4Q2zVGxM2FxoLkGQFAlsEjZQACgkQGxM2FxoL\n",
+            " =lgTX\n",
+            " -----END PGP SIGNATURE-----\n",
+            "\n",
+            "Merge branch 'draft'\n",
+        );
+        let kvlm = kvlm_parse(content.as_bytes(), None, None).unwrap();
+        let commit = Commit::from_kvlm(&kvlm).unwrap();
+
+        assert_eq!(commit.parents.len(), 2);
+        assert_eq!(commit.author.name, "Żaneta Kowalska");
+        assert_eq!(commit.committer.time, 1527025044);
+        assert_eq!(commit.encoding.as_deref(), Some("ISO-8859-2"));
+        assert_eq!(commit.extra_headers[0].0, "gpgsig");
+        assert_eq!(commit.summary(), "Merge branch 'draft'");
+
+        assert_eq!(kvlm_serialize(&commit.to_kvlm()), content.as_bytes());
+    }
+
+    #[test]
+    fn test_amend_latin1() {
+        let repo = TempRepo::new("amend-latin1");
+        let raw = format!(
+            "tree {}\nauthor J\u{f6}rg <j@x> 1 +0000\ncommitter J <j@x> 1 +0000\n",
+            repo.tree(&[])
+        );
+        let mut raw = raw.into_bytes();
+        raw.extend_from_slice(b"encoding ISO-8859-1\n\nGr\xfc\xdfe\n");
+        let object = GitObject::new(&repo, Some(raw), Some(GitObjectType::Commit)).unwrap();
+        let sha = object.object_write(None).unwrap();
+        ref_create(&repo, "refs/heads/master", &sha).unwrap();
+
+        let (amended, _) = commit_index(&repo, None, true, false).unwrap();
+        let commit = repo.object_read(&amended).unwrap().commit().unwrap();
+        assert_eq!(commit.message, b"Gr\xfc\xdfe\n");
+        assert_eq!(commit.encoding.as_deref(), Some("ISO-8859-1"));
+        assert_eq!(commit.author.name, "J\u{f6}rg");
+    }
+
+    #[test]
+    fn test_tag_create() {
+        let repo = TempRepo::new("tag");
+        let blob = repo.blob("x");
+        let tree = repo.tree(&[]);
+        let commit = repo.commit(&tree).write();
+        let tag = tag_create(&repo, "v1", &commit, "release").unwrap();
+        let tag_of_tag = tag_create(&repo, "v2", &tag, "again\n").unwrap();
+        let blob_tag = tag_create(&repo, "data", &blob, "blob").unwrap();
+        ref_create(&repo, "refs/tags/v1", &tag).unwrap();
+        ref_create(&repo, "refs/tags/nested/v2", &tag_of_tag).unwrap();
+        ref_create(&repo, "refs/tags/data", &blob_tag).unwrap();
+        fs::write(
+            repo.dir().join(".git/packed-refs"),
+            format!("{} refs/tags/light\n", commit),
+        )
+        .unwrap();
+
+        let raw = |sha: &str| repo.object_read_raw(sha).unwrap();
+        let (tag_type, tag_data) = raw(&tag);
+        let (_, tag_of_tag_data) = raw(&tag_of_tag);
+        let (_, blob_tag_data) = raw(&blob_tag);
+        assert_eq!(tag_type, GitObjectType::Tag);
+        let kvlm = kvlm_parse(&tag_data, None, None).unwrap();
+        assert_eq!(kvlm["object"][0], commit.as_bytes());
+        assert_eq!(kvlm["type"], [b"commit"]);
+        assert_eq!(kvlm["tag"], [b"v1"]);
+        let tagger = String::from_utf8(kvlm["tagger"][0].clone()).unwrap();
+        assert!(tagger.parse::<Signature>().is_ok());
+        // the message gets its final newline.
+        assert_eq!(kvlm[""], [b"release\n"]);
+        assert_eq!(kvlm_serialize(&kvlm), tag_data);
+        let tag_of_tag_data = String::from_utf8(tag_of_tag_data).unwrap();
+        assert!(tag_of_tag_data.starts_with(&format!("object {}\ntype tag\ntag v2\n", tag)));
+        assert!(tag_of_tag_data.ends_with("\n\nagain\n"));
+        assert!(String::from_utf8(blob_tag_data)
+            .unwrap()
+            .contains("\ntype blob\n"));
+
+        let find =
+            |name: &str, fmt: Option<&str>| repo.object_find(name, fmt.map(Into::into), None);
+        assert_eq!(find("nested/v2^{}", None).unwrap(), commit);
+        assert_eq!(find("nested/v2^{tag}", None).unwrap(), tag_of_tag);
+        assert_eq!(find("nested/v2", Some("commit")).unwrap(), commit);
+        assert_eq!(find("v1^{tree}", None).unwrap(), tree);
+        assert_eq!(find("data^{}", None).unwrap(), blob);
+        assert_eq!(find("light^{}", None).unwrap(), commit);
+        assert!(find("data^{commit}", None).is_err());
+        let tags: Vec<String> = ref_list(&repo, "refs/tags").unwrap().into_keys().collect();
+        assert_eq!(
+            tags,
+            [
+                "refs/tags/data",
+                "refs/tags/light",
+                "refs/tags/nested/v2",
+                "refs/tags/v1"
+            ]
+        );
+    }
+}
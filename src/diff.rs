@@ -0,0 +1,1574 @@
+use anyhow::bail;
+use clap::clap_derive::ArgEnum;
+use regex::bytes::Regex;
+use std::{collections::HashMap, str::FromStr};
+
+use crate::{
+    color::{paint, BG_RED, CYAN, GREEN, RED, RESET},
+    Result,
+};
+
+/// A run of changed lines: `old_len` lines of old starting at `old_start` are
+/// replaced by `new_len` lines of new starting at `new_start`. One of the
+/// lengths is 0 for pure insertions and deletions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+}
+
+/// How lines to remove and add are chosen.
+#[derive(Debug, Clone, Copy, ArgEnum, PartialEq, Eq)]
+pub enum Algorithm {
+    /// Myers' algorithm, giving up on the shortest script for very different
+    /// inputs.
+    #[clap(alias = "default")]
+    Myers,
+    /// Myers' algorithm, always producing the shortest script.
+    Minimal,
+    /// Anchors the diff on lines that occur once on both sides.
+    Patience,
+    /// Anchors the diff on runs of the rarest common lines.
+    Histogram,
+}
+
+impl FromStr for Algorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "myers" | "default" => Ok(Self::Myers),
+            "minimal" => Ok(Self::Minimal),
+            "patience" => Ok(Self::Patience),
+            "histogram" => Ok(Self::Histogram),
+            s => bail!("unknown diff algorithm {}", s),
+        }
+    }
+}
+
+/// How a word diff marks removed and added words.
+#[derive(Debug, Clone, Copy, ArgEnum, PartialEq, Eq)]
+pub enum WordDiff {
+    /// Words in "[-removed-]" and "{+added+}" brackets.
+    Plain,
+    /// Words painted red and green only.
+    Color,
+    /// Line-based, for scripts: a piece per line with its prefix, "~" for
+    /// newlines.
+    Porcelain,
+}
+
+/// Settings of a content diff.
+#[derive(Debug, Clone)]
+pub struct DiffOptions {
+    pub algorithm: Algorithm,
+    /// Slide groups of added or removed lines to where blank lines and
+    /// indentation suggest a block boundary, as git does by default.
+    pub indent_heuristic: bool,
+    /// Unchanged lines shown around changes.
+    pub context: usize,
+    /// Paint hunks with ANSI colors.
+    pub color: bool,
+    /// Show changed words within lines instead of changed lines.
+    pub word_diff: Option<WordDiff>,
+    /// What a word is for the word diff, runs of non-whitespace if None.
+    pub word_regex: Option<Regex>,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        Self {
+            algorithm: Algorithm::Myers,
+            indent_heuristic: true,
+            context: 3,
+            color: false,
+            word_diff: None,
+            word_regex: None,
+        }
+    }
+}
+
+/// Splits content into lines, each keeping its "\n", so the last one lacks it
+/// if the content does not end with a newline.
+pub fn lines(data: &[u8]) -> Vec<&[u8]> {
+    data.split_inclusive(|&b| b == b'\n').collect()
+}
+
+/// Diffs content line by line, returns hunks of changed lines in order.
+pub fn diff_lines(old: &[u8], new: &[u8], options: &DiffOptions) -> Vec<Hunk> {
+    let (old, new) = (lines(old), lines(new));
+    diff_items(&old, &new, options.algorithm, options.indent_heuristic)
+}
+
+/// Diffs sequences of lines or words, returns hunks of changed items in order.
+fn diff_items(old: &[&[u8]], new: &[&[u8]], algorithm: Algorithm, heuristic: bool) -> Vec<Hunk> {
+    let (old_ids, new_ids) = intern(old, new);
+    let mut old_changed = vec![false; old.len()];
+    let mut new_changed = vec![false; new.len()];
+    match algorithm {
+        Algorithm::Myers => myers(
+            &old_ids,
+            &new_ids,
+            &mut old_changed,
+            &mut new_changed,
+            false,
+        ),
+        Algorithm::Minimal => myers(&old_ids, &new_ids, &mut old_changed, &mut new_changed, true),
+        Algorithm::Patience => patience(&old_ids, &new_ids, &mut old_changed, &mut new_changed),
+        Algorithm::Histogram => histogram(&old_ids, &new_ids, &mut old_changed, &mut new_changed),
+    }
+    compact(old, &old_ids, &mut old_changed, &new_changed, heuristic);
+    compact(new, &new_ids, &mut new_changed, &old_changed, heuristic);
+    hunks(&old_changed, &new_changed)
+}
+
+/// Unified diff of two contents with `options.context` unchanged lines around
+/// each change, as the "@@" hunks of a patch. Changes closer than twice the
+/// context share a hunk. Empty when contents are equal.
+pub fn unified(old: &[u8], new: &[u8], options: &DiffOptions) -> Vec<u8> {
+    let changes = diff_lines(old, new, options);
+    let context = options.context;
+    let mut writer = HunkWriter::new(old, new, options);
+    let (old, new) = (lines(old), lines(new));
+
+    let mut i = 0;
+    while i < changes.len() {
+        let mut j = i + 1;
+        while j < changes.len()
+            && changes[j].old_start - (changes[j - 1].old_start + changes[j - 1].old_len)
+                <= 2 * context
+        {
+            j += 1;
+        }
+        let (first, last) = (changes[i], changes[j - 1]);
+        let before = context.min(first.old_start).min(first.new_start);
+        let (old_end, new_end) = (last.old_start + last.old_len, last.new_start + last.new_len);
+        let after = context.min(old.len() - old_end).min(new.len() - new_end);
+        let (old_lo, new_lo) = (first.old_start - before, first.new_start - before);
+        let (old_hi, new_hi) = (old_end + after, new_end + after);
+
+        writer.header(
+            (old_lo, old_hi - old_lo),
+            (new_lo, new_hi - new_lo),
+            function_name(&old[..old_lo]),
+        );
+        let mut pos = old_lo;
+        for change in &changes[i..j] {
+            for line in &old[pos..change.old_start] {
+                writer.line(b' ', line);
+            }
+            for line in &old[change.old_start..change.old_start + change.old_len] {
+                writer.line(b'-', line);
+            }
+            for line in &new[change.new_start..change.new_start + change.new_len] {
+                writer.line(b'+', line);
+            }
+            pos = change.old_start + change.old_len;
+        }
+        for line in &old[pos..old_hi] {
+            writer.line(b' ', line);
+        }
+        i = j;
+    }
+    writer.finish()
+}
+
+/// Writes the lines of hunks, painted if options say so, or with changed
+/// lines gathered and shown word by word in a word diff.
+struct HunkWriter<'a> {
+    out: Vec<u8>,
+    options: &'a DiffOptions,
+    /// Removed and added text waiting for the word diff.
+    words: (Vec<u8>, Vec<u8>),
+    /// Line numbers in old and new, counted the way git does.
+    lno: (usize, usize),
+    /// Where blank lines at the end of old and new start, when new has more
+    /// of them; added ones past that are whitespace errors. 0 otherwise.
+    blank_at_eof: (usize, usize),
+}
+
+impl<'a> HunkWriter<'a> {
+    fn new(old: &[u8], new: &[u8], options: &'a DiffOptions) -> Self {
+        let (old_blank, new_blank) = (trailing_blank(old), trailing_blank(new));
+        let blank_at_eof = match new_blank > old_blank {
+            true => (
+                lines(old).len() - old_blank + 1,
+                lines(new).len() - new_blank + 1,
+            ),
+            false => (0, 0),
+        };
+        Self {
+            out: vec![],
+            options,
+            words: (vec![], vec![]),
+            lno: (0, 0),
+            blank_at_eof,
+        }
+    }
+
+    /// Writes the "@@" line of a hunk covering (start, len) of old and new.
+    fn header(&mut self, old: (usize, usize), new: (usize, usize), name: Option<&[u8]>) {
+        self.words_flush();
+        let (old, new) = (hunk_range(old.0, old.1), hunk_range(new.0, new.1));
+        let start = |range: &str| range.split(',').next().unwrap().parse().unwrap();
+        self.lno = (start(&old), start(&new));
+
+        let color = self.options.color;
+        paint(
+            &mut self.out,
+            color,
+            CYAN,
+            format!("@@ -{} +{} @@", old, new).as_bytes(),
+        );
+        if let Some(name) = name {
+            paint(&mut self.out, color, "", b" ");
+            paint(&mut self.out, color, "", name);
+        }
+        self.out.push(b'\n');
+    }
+
+    /// Writes a line with its prefix, ' ', '-' or '+'.
+    fn line(&mut self, prefix: u8, line: &[u8]) {
+        match prefix {
+            b'-' => self.lno.0 += 1,
+            b'+' => self.lno.1 += 1,
+            _ => self.lno = (self.lno.0 + 1, self.lno.1 + 1),
+        }
+        if let Some(mode) = self.options.word_diff {
+            let text = match prefix {
+                b'-' => &mut self.words.0,
+                b'+' => &mut self.words.1,
+                _ => {
+                    self.words_flush();
+                    let line = line.strip_suffix(b"\n").unwrap_or(line);
+                    match mode {
+                        WordDiff::Porcelain => {
+                            self.line_paint("", Some(b' '), line);
+                            self.out.extend_from_slice(b"\n~\n");
+                        }
+                        _ => {
+                            self.line_paint("", None, line);
+                            self.out.push(b'\n');
+                        }
+                    }
+                    return;
+                }
+            };
+            // a missing final newline still ends the text, its marker is dropped.
+            text.extend_from_slice(line);
+            if !line.ends_with(b"\n") {
+                text.push(b'\n');
+            }
+            return;
+        }
+
+        let (content, newline) = match line.strip_suffix(b"\n") {
+            Some(content) => (content, true),
+            None => (line, false),
+        };
+        match prefix {
+            b'+' if !self.options.color => self.line_paint("", Some(b'+'), content),
+            b'+' if self.blank_at_eof_added(content) => {
+                self.line_paint(BG_RED, Some(b'+'), content)
+            }
+            b'+' => {
+                self.line_paint(GREEN, Some(b'+'), b"");
+                whitespace_paint(&mut self.out, content);
+            }
+            b'-' => self.line_paint(RED, Some(b'-'), content),
+            _ => self.line_paint("", Some(b' '), content),
+        }
+        self.out.push(b'\n');
+        if !newline {
+            self.line_paint("", Some(b'\\'), b" No newline at end of file");
+            self.out.push(b'\n');
+        }
+    }
+
+    /// Writes prefix and line in color, keeping a final "\r" out of it.
+    fn line_paint(&mut self, color: &str, prefix: Option<u8>, line: &[u8]) {
+        let (line, cr) = match line.strip_suffix(b"\r") {
+            Some(line) if self.options.color => (line, true),
+            _ => (line, false),
+        };
+        if self.options.color && (prefix.is_some() || !line.is_empty()) {
+            self.out.extend_from_slice(color.as_bytes());
+            self.out.extend(prefix);
+            self.out.extend_from_slice(line);
+            self.out.extend_from_slice(RESET.as_bytes());
+        } else {
+            self.out.extend(prefix);
+            self.out.extend_from_slice(line);
+        }
+        if cr {
+            self.out.push(b'\r');
+        }
+    }
+
+    /// Tells if an added line is one of the blank lines new adds at its end.
+    fn blank_at_eof_added(&self, line: &[u8]) -> bool {
+        let (old, new) = self.blank_at_eof;
+        old != 0 && old <= self.lno.0 && new <= self.lno.1 && line.iter().all(|&b| is_space(b))
+    }
+
+    /// Writes the removed and added text gathered so far as a word diff:
+    /// added text with removed and added words marked where they differ.
+    fn words_flush(&mut self) {
+        let (minus, plus) = std::mem::take(&mut self.words);
+        let mode = match self.options.word_diff {
+            Some(mode) if !minus.is_empty() || !plus.is_empty() => mode,
+            _ => return,
+        };
+        let color = |code| match self.options.color {
+            true => code,
+            false => "",
+        };
+        let (old, new, ctx, newline) = match mode {
+            WordDiff::Plain => (("[-", "-]"), ("{+", "+}"), ("", ""), "\n"),
+            WordDiff::Color => (("", ""), ("", ""), ("", ""), "\n"),
+            WordDiff::Porcelain => (("-", "\n"), ("+", "\n"), (" ", "\n"), "~\n"),
+        };
+        let (old, new, ctx) = ((color(RED), old), (color(GREEN), new), ("", ctx));
+        let out = &mut self.out;
+        if plus.is_empty() {
+            words_write(out, old, newline, &minus);
+            return;
+        }
+
+        let regex = self.options.word_regex.as_ref();
+        let (minus_words, plus_words) = (words(&minus, regex), words(&plus, regex));
+        fn tokens<'t>(text: &'t [u8], words: &[(usize, usize)]) -> Vec<&'t [u8]> {
+            words
+                .iter()
+                .map(|&(begin, end)| &text[begin..end])
+                .collect()
+        }
+        // words are diffed as git does, without the line options.
+        let changes = diff_items(
+            &tokens(&minus, &minus_words),
+            &tokens(&plus, &plus_words),
+            Algorithm::Myers,
+            false,
+        );
+        // an empty range sits right after the word before it.
+        let range = |words: &[(usize, usize)], start: usize, len: usize| match len {
+            0 if start == 0 => (0, 0),
+            0 => (words[start - 1].1, words[start - 1].1),
+            _ => (words[start].0, words[start + len - 1].1),
+        };
+        let mut pos = 0;
+        for change in changes {
+            let (minus_begin, minus_end) = range(&minus_words, change.old_start, change.old_len);
+            let (plus_begin, plus_end) = range(&plus_words, change.new_start, change.new_len);
+            words_write(out, ctx, newline, &plus[pos..plus_begin]);
+            words_write(out, old, newline, &minus[minus_begin..minus_end]);
+            words_write(out, new, newline, &plus[plus_begin..plus_end]);
+            pos = plus_end;
+        }
+        words_write(out, ctx, newline, &plus[pos..]);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.words_flush();
+        self.out
+    }
+}
+
+/// Writes text of a word diff in a (color, (prefix, suffix)) style, each
+/// line of it on its own and newlines as the mode shows them.
+fn words_write(
+    out: &mut Vec<u8>,
+    (color, (prefix, suffix)): (&str, (&str, &str)),
+    newline: &str,
+    text: &[u8],
+) {
+    let mut pieces = text.split(|&b| b == b'\n').peekable();
+    while let Some(piece) = pieces.next() {
+        if !piece.is_empty() {
+            out.extend_from_slice(color.as_bytes());
+            out.extend_from_slice(prefix.as_bytes());
+            out.extend_from_slice(piece);
+            out.extend_from_slice(suffix.as_bytes());
+            if !color.is_empty() {
+                out.extend_from_slice(RESET.as_bytes());
+            }
+        }
+        if pieces.peek().is_some() {
+            out.extend_from_slice(newline.as_bytes());
+        }
+    }
+}
+
+/// Byte ranges of the words of text: matches of regex cut at a newline,
+/// or runs of non-whitespace without one.
+fn words(text: &[u8], regex: Option<&Regex>) -> Vec<(usize, usize)> {
+    let mut ret = vec![];
+    let mut i = 0;
+    while i < text.len() {
+        let (begin, end) = match regex {
+            Some(regex) => match regex.find_at(text, i) {
+                Some(m) => {
+                    let newline = text[m.start()..m.end()].iter().position(|&b| b == b'\n');
+                    (m.start(), newline.map_or(m.end(), |n| m.start() + n))
+                }
+                None => break,
+            },
+            None => match text[i..].iter().position(|&b| !is_space(b)) {
+                Some(n) => {
+                    let end = text[i + n..].iter().position(|&b| is_space(b));
+                    (i + n, end.map_or(text.len(), |end| i + n + end))
+                }
+                None => break,
+            },
+        };
+        if begin == end {
+            i = begin + 1;
+            continue;
+        }
+        ret.push((begin, end));
+        i = end;
+    }
+    ret
+}
+
+/// Writes an added line, painting whitespace errors: spaces before a tab in
+/// the indent and whitespace at the end of the line.
+fn whitespace_paint(out: &mut Vec<u8>, line: &[u8]) {
+    let trailing = line
+        .iter()
+        .rposition(|&b| !is_space(b))
+        .map_or(0, |i| i + 1);
+    let mut written = 0;
+    for i in 0..trailing {
+        match line[i] {
+            b' ' => continue,
+            b'\t' if written < i => {
+                paint(out, true, BG_RED, &line[written..i]);
+                out.push(b'\t');
+            }
+            b'\t' => out.push(b'\t'),
+            _ => break,
+        }
+        written = i + 1;
+    }
+    if trailing > written {
+        paint(out, true, GREEN, &line[written..trailing]);
+    }
+    if trailing < line.len() {
+        paint(out, true, BG_RED, &line[trailing..]);
+    }
+}
+
+/// Whitespace as git's ctype sees it.
+fn is_space(b: u8) -> bool {
+    matches!(b, b' ' | b'\t' | b'\n' | b'\r')
+}
+
+/// Number of blank lines at the end of content, counted the way git does,
+/// which never counts the first line.
+fn trailing_blank(data: &[u8]) -> usize {
+    let mut end = data.len() as isize - 1 - data.ends_with(b"\n") as isize;
+    let mut ret = 0;
+    while 0 < end {
+        let start = data[..=end as usize]
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(-1, |i| i as isize);
+        if !data[(start + 1) as usize..(end + 1) as usize]
+            .iter()
+            .all(|&b| is_space(b))
+        {
+            break;
+        }
+        ret += 1;
+        end = start - 1;
+    }
+    ret
+}
+
+/// Tells if content looks binary the way git decides it: a NUL byte among
+/// the first 8000 bytes.
+pub fn is_binary(data: &[u8]) -> bool {
+    data.iter().take(8000).any(|&b| b == 0)
+}
+
+/// "start,len" of a hunk header, 1-based. Length 1 is left out, and an empty
+/// range names the line before it.
+fn hunk_range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, len),
+    }
+}
+
+/// Last line before a hunk that starts like a definition, with a letter, "_"
+/// or "$", cut to 80 bytes without trailing whitespace, as git's default.
+fn function_name<'a>(before: &[&'a [u8]]) -> Option<&'a [u8]> {
+    let line = before
+        .iter()
+        .rev()
+        .find(|line| matches!(line.first(), Some(c) if c.is_ascii_alphabetic() || *c == b'_' || *c == b'$'))?;
+    let line = &line[..line.len().min(80)];
+    let end = line
+        .iter()
+        .rposition(|c| !c.is_ascii_whitespace())
+        .map_or(0, |i| i + 1);
+    Some(&line[..end])
+}
+
+/// Maps equal items of both sequences to equal numbers, so the diff compares
+/// integers instead of whole lines.
+pub fn intern<T: std::hash::Hash + Eq>(old: &[T], new: &[T]) -> (Vec<u32>, Vec<u32>) {
+    let mut ids: HashMap<&T, u32> = HashMap::new();
+    let mut id = |item| {
+        let next = ids.len() as u32;
+        *ids.entry(item).or_insert(next)
+    };
+    let old = old.iter().map(&mut id).collect();
+    let new = new.iter().map(&mut id).collect();
+    (old, new)
+}
+
+/// Marks lines of an edit script between old and new with Myers' O(ND)
+/// algorithm the way git's xdiff runs it, so equally short scripts come out
+/// the same: lines without a match on the other side, and frequent lines among
+/// them, are marked up front, the rest is split at middle snakes of the edit
+/// graph, in linear space and from a stack instead of recursion. Unless
+/// minimal, costly splits are only approximate.
+fn myers(
+    old: &[u32],
+    new: &[u32],
+    old_changed: &mut [bool],
+    new_changed: &mut [bool],
+    minimal: bool,
+) {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut counts: HashMap<u32, (usize, usize)> = HashMap::new();
+    for id in old {
+        counts.entry(*id).or_default().0 += 1;
+    }
+    for id in new {
+        counts.entry(*id).or_default().1 += 1;
+    }
+    // lines left to diff and their positions, the others are changed.
+    let kept = |lines: &[u32], changed: &mut [bool], matches: &dyn Fn(u32) -> usize| {
+        let lines = &lines[prefix..lines.len() - suffix];
+        let limit = bogosqrt(lines.len() + prefix + suffix).min(1024);
+        let kinds: Vec<Matches> = lines
+            .iter()
+            .map(|id| match matches(*id) {
+                0 => Matches::None,
+                n if n >= limit => Matches::Many,
+                _ => Matches::Some,
+            })
+            .collect();
+        let mut ret = (vec![], vec![]);
+        for (i, id) in lines.iter().enumerate() {
+            match kinds[i] {
+                Matches::Some => {}
+                Matches::Many if !among_unmatched(&kinds, i) => {}
+                _ => {
+                    changed[prefix + i] = true;
+                    continue;
+                }
+            }
+            ret.0.push(*id);
+            ret.1.push(prefix + i);
+        }
+        ret
+    };
+    let (old, old_index) = kept(old, old_changed, &|id| counts[&id].1);
+    let (new, new_index) = kept(new, new_changed, &|id| counts[&id].0);
+
+    let mut vf = V::new(new.len(), old.len());
+    let mut vb = V::new(new.len(), old.len());
+    let max_cost = bogosqrt(old.len() + new.len() + 3).max(256) as isize;
+    let mut stack = vec![(0, old.len(), 0, new.len(), minimal)];
+    while let Some((mut old_lo, mut old_hi, mut new_lo, mut new_hi, minimal)) = stack.pop() {
+        while old_lo < old_hi && new_lo < new_hi && old[old_lo] == new[new_lo] {
+            old_lo += 1;
+            new_lo += 1;
+        }
+        while old_lo < old_hi && new_lo < new_hi && old[old_hi - 1] == new[new_hi - 1] {
+            old_hi -= 1;
+            new_hi -= 1;
+        }
+
+        if old_lo == old_hi || new_lo == new_hi {
+            for i in old_lo..old_hi {
+                old_changed[old_index[i]] = true;
+            }
+            for j in new_lo..new_hi {
+                new_changed[new_index[j]] = true;
+            }
+            continue;
+        }
+        let (x, y, minimal_lo, minimal_hi) = middle_snake(
+            (&old, old_lo, old_hi),
+            (&new, new_lo, new_hi),
+            &mut vf,
+            &mut vb,
+            minimal,
+            max_cost,
+        );
+        stack.push((x, old_hi, y, new_hi, minimal_hi));
+        stack.push((old_lo, x, new_lo, y, minimal_lo));
+    }
+}
+
+/// How often a line occurs on the other side, for xdiff's discarding of lines
+/// before the diff.
+#[derive(Clone, Copy, PartialEq)]
+enum Matches {
+    None,
+    Some,
+    Many,
+}
+
+/// Integer square root approximation of xdiff.
+fn bogosqrt(mut n: usize) -> usize {
+    let mut ret = 1;
+    while n > 0 {
+        ret <<= 1;
+        n >>= 2;
+    }
+    ret
+}
+
+/// Tells if a line with many matches sits among lines without match, within a
+/// window of 100 lines, so xdiff discards it as well.
+fn among_unmatched(kinds: &[Matches], i: usize) -> bool {
+    let (lo, hi) = (i.saturating_sub(100), (i + 100).min(kinds.len() - 1));
+    let run = |range: &mut dyn Iterator<Item = usize>| {
+        let (mut none, mut many) = (0, 1);
+        for j in range {
+            match kinds[j] {
+                Matches::None => none += 1,
+                Matches::Many => many += 1,
+                Matches::Some => break,
+            }
+        }
+        (none, many)
+    };
+    let (none_before, many_before) = run(&mut (lo..i).rev());
+    if none_before == 0 {
+        return false;
+    }
+    let (none_after, many_after) = run(&mut (i + 1..=hi));
+    if none_after == 0 {
+        return false;
+    }
+    let (none, many) = (none_before + none_after, many_before + many_after);
+    many * 4 < many + none
+}
+
+/// Marks changed lines with the patience diff: lines occurring once on both
+/// sides anchor the diff along their longest common subsequence, and the gaps
+/// between anchors are diffed the same way. Gaps without such lines fall back
+/// to Myers.
+fn patience(old: &[u32], new: &[u32], old_changed: &mut [bool], new_changed: &mut [bool]) {
+    if old.is_empty() || new.is_empty() {
+        old_changed.fill(true);
+        new_changed.fill(true);
+        return;
+    }
+    // occurrences of each line in old and new, and where it was seen last.
+    let mut counts: HashMap<u32, (usize, usize, usize, usize)> = HashMap::new();
+    for (i, id) in old.iter().enumerate() {
+        let count = counts.entry(*id).or_default();
+        count.0 += 1;
+        count.2 = i;
+    }
+    let mut common = false;
+    for (j, id) in new.iter().enumerate() {
+        if let Some(count) = counts.get_mut(id) {
+            count.1 += 1;
+            count.3 = j;
+            common = true;
+        }
+    }
+    if !common {
+        old_changed.fill(true);
+        new_changed.fill(true);
+        return;
+    }
+
+    // patience sorting of unique lines, in old order, by their new position:
+    // each pile top links to the top of the pile left of it when placed.
+    let unique: Vec<(usize, usize)> = old
+        .iter()
+        .filter_map(|id| match counts[id] {
+            (1, 1, i, j) => Some((i, j)),
+            _ => None,
+        })
+        .collect();
+    let mut tops: Vec<usize> = vec![];
+    let mut links: Vec<Option<usize>> = vec![None; unique.len()];
+    for (u, &(_, j)) in unique.iter().enumerate() {
+        let pile = tops.partition_point(|&top| unique[top].1 < j);
+        links[u] = pile.checked_sub(1).map(|p| tops[p]);
+        match pile == tops.len() {
+            true => tops.push(u),
+            false => tops[pile] = u,
+        }
+    }
+    let mut anchors = vec![];
+    let mut top = tops.last().copied();
+    while let Some(u) = top {
+        anchors.push(unique[u]);
+        top = links[u];
+    }
+    if anchors.is_empty() {
+        return myers(old, new, old_changed, new_changed, false);
+    }
+    anchors.reverse();
+
+    let (mut i, mut j) = (0, 0);
+    for anchor in anchors.iter().map(Some).chain([None]) {
+        let (mut end_i, mut end_j) = anchor.copied().unwrap_or((old.len(), new.len()));
+        if anchor.is_some() {
+            while end_i > i && end_j > j && old[end_i - 1] == new[end_j - 1] {
+                end_i -= 1;
+                end_j -= 1;
+            }
+        }
+        while i < end_i && j < end_j && old[i] == new[j] {
+            i += 1;
+            j += 1;
+        }
+        if i < end_i || j < end_j {
+            patience(
+                &old[i..end_i],
+                &new[j..end_j],
+                &mut old_changed[i..end_i],
+                &mut new_changed[j..end_j],
+            );
+        }
+        if let Some(&(anchor_i, anchor_j)) = anchor {
+            i = anchor_i + 1;
+            j = anchor_j + 1;
+        }
+    }
+}
+
+/// Common lines occurring more often than this in old are not used as anchors
+/// of the histogram diff.
+const HISTOGRAM_MAX_COUNT: usize = 64;
+
+/// Longest common run of lines found by the histogram diff, as ranges of old
+/// and new.
+enum Run {
+    Common(usize, usize, usize, usize),
+    /// No line is common to both sides.
+    Disjoint,
+    /// All common lines occur too often to be anchors.
+    Frequent,
+}
+
+/// Marks changed lines with git's histogram diff, a refinement of patience:
+/// the common run of lines whose rarest line occurs least often in old splits
+/// both sides, and what comes before and after it is diffed the same way.
+/// Like xdiff, every split scans what is left again, so inputs with many
+/// short common runs take quadratic time.
+fn histogram(old: &[u32], new: &[u32], old_changed: &mut [bool], new_changed: &mut [bool]) {
+    // ids are dense, so one table indexed by id serves every split.
+    let ids = old.iter().chain(new).max().map_or(0, |&id| id as usize + 1);
+    let mut records = vec![(0, 0); ids];
+    histogram_split(old, new, old_changed, new_changed, &mut records);
+}
+
+fn histogram_split(
+    old: &[u32],
+    new: &[u32],
+    old_changed: &mut [bool],
+    new_changed: &mut [bool],
+    records: &mut [(usize, usize)],
+) {
+    let (mut old_lo, mut new_lo) = (0, 0);
+    loop {
+        let (old, new) = (&old[old_lo..], &new[new_lo..]);
+        let (old_changed, new_changed) = (&mut old_changed[old_lo..], &mut new_changed[new_lo..]);
+        if old.is_empty() || new.is_empty() {
+            old_changed.fill(true);
+            new_changed.fill(true);
+            return;
+        }
+        match histogram_run(old, new, records) {
+            Run::Common(old_start, old_end, new_start, new_end) => {
+                histogram_split(
+                    &old[..old_start],
+                    &new[..new_start],
+                    &mut old_changed[..old_start],
+                    &mut new_changed[..new_start],
+                    records,
+                );
+                old_lo += old_end;
+                new_lo += new_end;
+            }
+            Run::Disjoint => {
+                old_changed.fill(true);
+                new_changed.fill(true);
+                return;
+            }
+            Run::Frequent => return myers(old, new, old_changed, new_changed, false),
+        }
+    }
+}
+
+/// Finds the split of the histogram diff: every occurrence in old of each
+/// line of new is grown to a common run, and a run wins if it is longer than
+/// the best so far or its rarest line is rarer.
+fn histogram_run(old: &[u32], new: &[u32], records: &mut [(usize, usize)]) -> Run {
+    // first position and count of each line in old, by id, and the next
+    // position of the same line after each one.
+    let mut next = vec![None; old.len()];
+    for (i, &id) in old.iter().enumerate().rev() {
+        let record = &mut records[id as usize];
+        if record.1 > 0 {
+            next[i] = Some(record.0);
+        }
+        record.0 = i;
+        record.1 += 1;
+    }
+    let count = |i: usize| records[old[i] as usize].1;
+
+    let mut best: Option<(usize, usize, usize, usize)> = None;
+    let mut best_count = HISTOGRAM_MAX_COUNT + 1;
+    let mut common = false;
+    let mut j = 0;
+    while j < new.len() {
+        let mut j_next = j + 1;
+        let (first, line_count) = records[new[j] as usize];
+        if line_count > 0 {
+            common = true;
+            let mut occurrence = (line_count <= best_count).then_some(first);
+            while let Some(i) = occurrence {
+                let (mut old_start, mut new_start, mut old_end, mut new_end) = (i, j, i + 1, j + 1);
+                let mut run_count = line_count;
+                while old_start > 0 && new_start > 0 && old[old_start - 1] == new[new_start - 1] {
+                    old_start -= 1;
+                    new_start -= 1;
+                    if run_count > 1 {
+                        run_count = run_count.min(count(old_start));
+                    }
+                }
+                while old_end < old.len() && new_end < new.len() && old[old_end] == new[new_end] {
+                    if run_count > 1 {
+                        run_count = run_count.min(count(old_end));
+                    }
+                    old_end += 1;
+                    new_end += 1;
+                }
+                j_next = j_next.max(new_end);
+                let best_len = best.map_or(1, |(start, end, ..)| end - start);
+                if best_len < old_end - old_start || run_count < best_count {
+                    best = Some((old_start, old_end, new_start, new_end));
+                    best_count = run_count;
+                }
+
+                // the next occurrence of the line not inside this run.
+                occurrence = next[i];
+                while let Some(k) = occurrence.filter(|&k| k < old_end) {
+                    occurrence = next[k];
+                }
+            }
+        }
+        j = j_next;
+    }
+
+    for &id in old {
+        records[id as usize] = (0, 0);
+    }
+    match best {
+        _ if common && best_count > HISTOGRAM_MAX_COUNT => Run::Frequent,
+        Some((old_start, old_end, new_start, new_end)) => {
+            Run::Common(old_start, old_end, new_start, new_end)
+        }
+        None => Run::Disjoint,
+    }
+}
+
+/// Group of consecutive changed lines of one side, `start..end`, or the empty
+/// group before line `start`. Groups sit between any two unchanged lines.
+#[derive(Clone, Copy)]
+struct Group {
+    start: usize,
+    end: usize,
+}
+
+impl Group {
+    fn first(changed: &[bool]) -> Self {
+        let mut group = Group { start: 0, end: 0 };
+        group.extend(changed);
+        group
+    }
+
+    fn extend(&mut self, changed: &[bool]) {
+        while self.end < changed.len() && changed[self.end] {
+            self.end += 1;
+        }
+        while self.start > 0 && changed[self.start - 1] {
+            self.start -= 1;
+        }
+    }
+
+    /// Moves to the group after the next unchanged line, false at the end.
+    fn next(&mut self, changed: &[bool]) -> bool {
+        if self.end == changed.len() {
+            return false;
+        }
+        self.start = self.end + 1;
+        self.end = self.start;
+        self.extend(changed);
+        true
+    }
+
+    /// Moves to the group before the previous unchanged line, false at the
+    /// start.
+    fn previous(&mut self, changed: &[bool]) -> bool {
+        if self.start == 0 {
+            return false;
+        }
+        self.end = self.start - 1;
+        self.start = self.end;
+        self.extend(changed);
+        true
+    }
+
+    /// Slides the group one line down if its first line equals the line after
+    /// it, merging with a group it runs into.
+    fn slide_down(&mut self, ids: &[u32], changed: &mut [bool]) -> bool {
+        if self.end == ids.len() || ids[self.start] != ids[self.end] {
+            return false;
+        }
+        changed[self.start] = false;
+        changed[self.end] = true;
+        self.start += 1;
+        self.end += 1;
+        self.extend(changed);
+        true
+    }
+
+    /// Slides the group one line up if its last line equals the line before
+    /// it, merging with a group it runs into.
+    fn slide_up(&mut self, ids: &[u32], changed: &mut [bool]) -> bool {
+        if self.start == 0 || ids[self.start - 1] != ids[self.end - 1] {
+            return false;
+        }
+        changed[self.start - 1] = true;
+        changed[self.end - 1] = false;
+        self.start -= 1;
+        self.end -= 1;
+        self.extend(changed);
+        true
+    }
+}
+
+/// Slides groups of changed lines of one side where equal lines allow it, as
+/// git does: groups that can touch merge, a group lines up with a change of
+/// the other side if it can, and otherwise sits as low as possible or, with
+/// the indent heuristic, at the best scoring split.
+fn compact(lines: &[&[u8]], ids: &[u32], changed: &mut [bool], other: &[bool], heuristic: bool) {
+    let mut group = Group::first(changed);
+    let mut other_group = Group::first(other);
+    loop {
+        if group.start < group.end {
+            // slide up and down as far as possible until merging stops.
+            let mut size;
+            let mut earliest_end;
+            let mut end_matching_other;
+            loop {
+                size = group.end - group.start;
+                end_matching_other = None;
+                while group.slide_up(ids, changed) {
+                    other_group.previous(other);
+                }
+                earliest_end = group.end;
+                if other_group.start < other_group.end {
+                    end_matching_other = Some(group.end);
+                }
+                while group.slide_down(ids, changed) {
+                    other_group.next(other);
+                    if other_group.start < other_group.end {
+                        end_matching_other = Some(group.end);
+                    }
+                }
+                if size == group.end - group.start {
+                    break;
+                }
+            }
+
+            let target = match end_matching_other {
+                _ if group.end == earliest_end => None,
+                Some(end) => Some(end),
+                None if heuristic => {
+                    let lowest = earliest_end.max(group.end.saturating_sub(size + 1));
+                    let lowest = lowest.max(group.end.saturating_sub(INDENT_MAX_SLIDING));
+                    let score = |end: usize| {
+                        let (indent, penalty) = split_score(lines, end);
+                        let (start_indent, start_penalty) = split_score(lines, end - size);
+                        (indent + start_indent, penalty + start_penalty)
+                    };
+                    // later ends win ties.
+                    let mut best: Option<(usize, (i32, i32))> = None;
+                    for end in lowest..=group.end {
+                        let (indent, penalty) = score(end);
+                        let better = best.map_or(true, |(_, (best_indent, best_penalty))| {
+                            INDENT_WEIGHT * indent.cmp(&best_indent) as i32 + penalty - best_penalty
+                                <= 0
+                        });
+                        if better {
+                            best = Some((end, (indent, penalty)));
+                        }
+                    }
+                    best.map(|(end, _)| end)
+                }
+                None => None,
+            };
+            if let Some(end) = target {
+                while group.end > end {
+                    group.slide_up(ids, changed);
+                    other_group.previous(other);
+                }
+            }
+        }
+
+        if !group.next(changed) {
+            break;
+        }
+        other_group.next(other);
+    }
+}
+
+/// Indentation beyond this counts the same.
+const MAX_INDENT: i32 = 200;
+/// Blank lines beyond this count the same.
+const MAX_BLANKS: i32 = 20;
+/// How far the indent heuristic slides a group at most.
+const INDENT_MAX_SLIDING: usize = 100;
+/// Weight of the indentation of splits against their penalties.
+const INDENT_WEIGHT: i32 = 60;
+
+/// Width of the leading whitespace of a line, tabs to multiples of 8, or None
+/// for a blank line.
+fn indent(line: &[u8]) -> Option<i32> {
+    let mut ret = 0;
+    for c in line {
+        match c {
+            b' ' => ret += 1,
+            b'\t' => ret += 8 - ret % 8,
+            b'\n' | b'\r' | 0x0b | 0x0c => {}
+            _ => return Some(ret),
+        }
+        if ret >= MAX_INDENT {
+            return Some(MAX_INDENT);
+        }
+    }
+    None
+}
+
+/// Effective indent and penalty of splitting lines before line `split`, as
+/// git's indent heuristic scores them: lower is a better place to start or end
+/// a group of changes. The weights are git's, tuned on a corpus of code.
+fn split_score(lines: &[&[u8]], split: usize) -> (i32, i32) {
+    let end_of_file = split >= lines.len();
+    let line_indent = lines.get(split).and_then(|line| indent(line));
+
+    // blank lines and indent of the nearest non-blank line above the split,
+    // and below the line after it.
+    let nearest = |lines: &mut dyn Iterator<Item = &&[u8]>| {
+        let mut blanks = 0;
+        for line in lines {
+            if let Some(indent) = indent(line) {
+                return (blanks, Some(indent));
+            }
+            blanks += 1;
+            if blanks == MAX_BLANKS {
+                return (blanks, Some(0));
+            }
+        }
+        (blanks, None)
+    };
+    let (pre_blank, pre_indent) = nearest(&mut lines[..split.min(lines.len())].iter().rev());
+    let (post_blank, post_indent) = nearest(&mut lines.iter().skip(split + 1));
+
+    let mut penalty = 0;
+    if pre_indent.is_none() && pre_blank == 0 {
+        penalty += 1;
+    }
+    if end_of_file {
+        penalty += 21;
+    }
+    // blank lines after the split include the line right after it.
+    let post_blank = match line_indent {
+        None => 1 + post_blank,
+        Some(_) => 0,
+    };
+    let total_blank = pre_blank + post_blank;
+    penalty += -30 * total_blank + 6 * post_blank;
+
+    let indent = line_indent.or(post_indent);
+    let any_blanks = total_blank != 0;
+    match (indent, pre_indent) {
+        (Some(indent), Some(pre_indent)) if indent > pre_indent => {
+            penalty += if any_blanks { 10 } else { -4 };
+        }
+        (Some(indent), Some(pre_indent)) if indent < pre_indent => {
+            // a block starts if the next line is indented more, else it ends.
+            penalty += match post_indent.map_or(false, |post| post > indent) {
+                true if any_blanks => 17,
+                true => 24,
+                false if any_blanks => 17,
+                false => 23,
+            };
+        }
+        _ => {}
+    }
+    // the effective indent is -1 at the end of the file.
+    (indent.unwrap_or(-1), penalty)
+}
+
+/// Builds hunks from per-line change marks of both sides. Unchanged lines of
+/// old and new pair up in order, so runs of marks between them form hunks.
+pub fn hunks(old_changed: &[bool], new_changed: &[bool]) -> Vec<Hunk> {
+    let (mut i, mut j) = (0, 0);
+    let mut ret = vec![];
+    while i < old_changed.len() || j < new_changed.len() {
+        let old_run = old_changed.get(i) == Some(&true);
+        let new_run = new_changed.get(j) == Some(&true);
+        if !old_run && !new_run {
+            i += 1;
+            j += 1;
+            continue;
+        }
+        let (old_start, new_start) = (i, j);
+        while old_changed.get(i) == Some(&true) {
+            i += 1;
+        }
+        while new_changed.get(j) == Some(&true) {
+            j += 1;
+        }
+        ret.push(Hunk {
+            old_start,
+            old_len: i - old_start,
+            new_start,
+            new_len: j - new_start,
+        });
+    }
+    ret
+}
+
+/// Furthest reaching x of each diagonal k = x - y, indexed from -lo - 1 to
+/// hi + 1.
+struct V {
+    offset: isize,
+    v: Vec<isize>,
+}
+
+impl V {
+    fn new(lo: usize, hi: usize) -> Self {
+        Self {
+            offset: lo as isize + 1,
+            v: vec![0; lo + hi + 3],
+        }
+    }
+}
+
+impl std::ops::Index<isize> for V {
+    type Output = isize;
+
+    fn index(&self, k: isize) -> &isize {
+        &self.v[(k + self.offset) as usize]
+    }
+}
+
+impl std::ops::IndexMut<isize> for V {
+    fn index_mut(&mut self, k: isize) -> &mut isize {
+        &mut self.v[(k + self.offset) as usize]
+    }
+}
+
+/// Snakes longer than this make a split worth taking early.
+const SNAKE_MIN: isize = 20;
+/// Cost after which good snakes are taken as splits.
+const SNAKE_MIN_COST: isize = 256;
+
+/// Finds the middle snake of the edit graph between `lo..hi` ranges of both
+/// sides by running the search from both ends until the paths overlap, as
+/// xdiff does. Returns the point splitting the edit script in two, and whether
+/// each half must be diffed minimally. Both ranges must be non-empty and
+/// differ at both ends. Unless minimal, a long snake is taken once the cost is
+/// high, and past max_cost the furthest reaching path is, so the script may
+/// be longer than the shortest one.
+fn middle_snake(
+    (old, off1, lim1): (&[u32], usize, usize),
+    (new, off2, lim2): (&[u32], usize, usize),
+    vf: &mut V,
+    vb: &mut V,
+    minimal: bool,
+    max_cost: isize,
+) -> (usize, usize, bool, bool) {
+    let (off1, lim1, off2, lim2) = (off1 as isize, lim1 as isize, off2 as isize, lim2 as isize);
+    let eq = |x: isize, y: isize| old[x as usize] == new[y as usize];
+    let split = |x: isize, y: isize, minimal_lo, minimal_hi| {
+        (x as usize, y as usize, minimal_lo, minimal_hi)
+    };
+    let (dmin, dmax) = (off1 - lim2, lim1 - off2);
+    let (fmid, bmid) = (off1 - off2, lim1 - lim2);
+    let odd = (fmid - bmid) & 1 != 0;
+    let (mut fmin, mut fmax, mut bmin, mut bmax) = (fmid, fmid, bmid, bmid);
+    vf[fmid] = off1;
+    vb[bmid] = lim1;
+
+    let mut cost = 0;
+    loop {
+        cost += 1;
+        let mut got_snake = false;
+
+        // diagonals reach one further each step, or one less at the borders.
+        if fmin > dmin {
+            fmin -= 1;
+            vf[fmin - 1] = -1;
+        } else {
+            fmin += 1;
+        }
+        if fmax < dmax {
+            fmax += 1;
+            vf[fmax + 1] = -1;
+        } else {
+            fmax -= 1;
+        }
+        for d in (fmin..=fmax).rev().step_by(2) {
+            let mut x = match vf[d - 1] >= vf[d + 1] {
+                true => vf[d - 1] + 1,
+                false => vf[d + 1],
+            };
+            let x0 = x;
+            let mut y = x - d;
+            while x < lim1 && y < lim2 && eq(x, y) {
+                x += 1;
+                y += 1;
+            }
+            got_snake |= x - x0 > SNAKE_MIN;
+            vf[d] = x;
+            if odd && bmin <= d && d <= bmax && vb[d] <= x {
+                return split(x, y, true, true);
+            }
+        }
+
+        if bmin > dmin {
+            bmin -= 1;
+            vb[bmin - 1] = isize::MAX;
+        } else {
+            bmin += 1;
+        }
+        if bmax < dmax {
+            bmax += 1;
+            vb[bmax + 1] = isize::MAX;
+        } else {
+            bmax -= 1;
+        }
+        for d in (bmin..=bmax).rev().step_by(2) {
+            let mut x = match vb[d - 1] < vb[d + 1] {
+                true => vb[d - 1],
+                false => vb[d + 1] - 1,
+            };
+            let x0 = x;
+            let mut y = x - d;
+            while x > off1 && y > off2 && eq(x - 1, y - 1) {
+                x -= 1;
+                y -= 1;
+            }
+            got_snake |= x0 - x > SNAKE_MIN;
+            vb[d] = x;
+            if !odd && fmin <= d && d <= fmax && x <= vf[d] {
+                return split(x, y, true, true);
+            }
+        }
+
+        if minimal {
+            continue;
+        }
+        // take a path far from the start and close to the middle diagonal
+        // that ends in a long snake.
+        if got_snake && cost > SNAKE_MIN_COST {
+            let mut best = (0, 0, 0);
+            for d in (fmin..=fmax).rev().step_by(2) {
+                let (x, y) = (vf[d], vf[d] - d);
+                let v = (x - off1) + (y - off2) - (d - fmid).abs();
+                if v > 4 * cost
+                    && v > best.0
+                    && off1 + SNAKE_MIN <= x
+                    && x < lim1
+                    && off2 + SNAKE_MIN <= y
+                    && y < lim2
+                    && (1..=SNAKE_MIN).all(|k| eq(x - k, y - k))
+                {
+                    best = (v, x, y);
+                }
+            }
+            if best.0 > 0 {
+                return split(best.1, best.2, true, false);
+            }
+
+            for d in (bmin..=bmax).rev().step_by(2) {
+                let (x, y) = (vb[d], vb[d] - d);
+                let v = (lim1 - x) + (lim2 - y) - (d - bmid).abs();
+                if v > 4 * cost
+                    && v > best.0
+                    && off1 < x
+                    && x <= lim1 - SNAKE_MIN
+                    && off2 < y
+                    && y <= lim2 - SNAKE_MIN
+                    && (0..SNAKE_MIN).all(|k| eq(x + k, y + k))
+                {
+                    best = (v, x, y);
+                }
+            }
+            if best.0 > 0 {
+                return split(best.1, best.2, false, true);
+            }
+        }
+
+        // give up and split where either search got furthest.
+        if cost >= max_cost {
+            let mut forward = (-1, -1);
+            for d in (fmin..=fmax).rev().step_by(2) {
+                let mut x = vf[d].min(lim1);
+                let mut y = x - d;
+                if lim2 < y {
+                    x = lim2 + d;
+                    y = lim2;
+                }
+                if forward.0 < x + y {
+                    forward = (x + y, x);
+                }
+            }
+            let mut backward = (isize::MAX, isize::MAX);
+            for d in (bmin..=bmax).rev().step_by(2) {
+                let mut x = vb[d].max(off1);
+                let mut y = x - d;
+                if y < off2 {
+                    x = off2 + d;
+                    y = off2;
+                }
+                if x + y < backward.0 {
+                    backward = (x + y, x);
+                }
+            }
+            return match (lim1 + lim2) - backward.0 < forward.0 - (off1 + off2) {
+                true => split(forward.1, forward.0 - forward.1, true, false),
+                false => split(backward.1, backward.0 - backward.1, false, true),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        diff_lines, hunks, intern, lines, myers, unified, Algorithm, DiffOptions, Hunk, Regex,
+        WordDiff,
+    };
+
+    /// Rebuilds new from old and hunks.
+    fn patch<'a>(old: &[&'a [u8]], new: &[&'a [u8]], hunks: &[Hunk]) -> Vec<&'a [u8]> {
+        let mut ret = vec![];
+        let mut pos = 0;
+        for h in hunks {
+            ret.extend_from_slice(&old[pos..h.old_start]);
+            ret.extend_from_slice(&new[h.new_start..h.new_start + h.new_len]);
+            pos = h.old_start + h.old_len;
+        }
+        ret.extend_from_slice(&old[pos..]);
+        ret
+    }
+
+    #[test]
+    fn test_myers() {
+        let diff = |a: &[u8], b: &[u8]| {
+            let (a, b) = intern(a, b);
+            let (mut a_changed, mut b_changed) = (vec![false; a.len()], vec![false; b.len()]);
+            myers(&a, &b, &mut a_changed, &mut b_changed, true);
+            hunks(&a_changed, &b_changed)
+        };
+        let edits: usize = diff(b"ABCABBA", b"CBABAC")
+            .iter()
+            .map(|h| h.old_len + h.new_len)
+            .sum();
+        assert_eq!(edits, 5);
+
+        assert_eq!(diff(b"", b"abc").len(), 1);
+        assert!(diff(b"same", b"same").is_empty());
+    }
+
+    #[test]
+    fn test_diff_lines() {
+        let old = b"a\nb\nc\nd\n".as_slice();
+        let new = b"a\nc\nd\ne".as_slice();
+        assert_eq!(
+            diff_lines(old, new, &DiffOptions::default()),
+            vec![
+                Hunk {
+                    old_start: 1,
+                    old_len: 1,
+                    new_start: 1,
+                    new_len: 0
+                },
+                Hunk {
+                    old_start: 4,
+                    old_len: 0,
+                    new_start: 3,
+                    new_len: 1
+                },
+            ]
+        );
+
+        // long inputs must not overflow the stack or need quadratic memory.
+        let old: Vec<u8> = (0..50000)
+            .flat_map(|i| format!("line {}\n", i % 1000).into_bytes())
+            .collect();
+        let new: Vec<u8> = (0..50000)
+            .flat_map(|i| format!("line {}\n", (i * 7) % 1003).into_bytes())
+            .collect();
+        let hunks = diff_lines(&old, &new, &DiffOptions::default());
+        let (old, new) = (lines(&old), lines(&new));
+        assert_eq!(patch(&old, &new, &hunks), new);
+    }
+
+    #[test]
+    fn test_algorithms() {
+        let fact = "int fact(int n)\n{\n    if (n > 1)\n    {\n        return fact(n - 1) * n;\n    }\n    return 1;\n}\n";
+        let fib = "int fib(int n)\n{\n    if (n > 2)\n    {\n        return fib(n - 1) + fib(n - 2);\n    }\n    return 1;\n}\n";
+        let old = format!("{}\nint main()\n{{\n    return fact(10);\n}}\n", fact);
+        let new = format!("{}\n{}", fib, fact);
+        let diff = |algorithm| {
+            let options = DiffOptions {
+                algorithm,
+                ..Default::default()
+            };
+            diff_lines(old.as_bytes(), new.as_bytes(), &options)
+        };
+        // myers pairs up braces of different functions.
+        assert_eq!(diff(Algorithm::Myers).len(), 5);
+        let moved = vec![
+            Hunk {
+                old_start: 0,
+                old_len: 0,
+                new_start: 0,
+                new_len: 9,
+            },
+            Hunk {
+                old_start: 8,
+                old_len: 5,
+                new_start: 17,
+                new_len: 0,
+            },
+        ];
+        assert_eq!(diff(Algorithm::Patience), moved);
+        assert_eq!(diff(Algorithm::Histogram), moved);
+
+        let old: Vec<u8> = (0..5000)
+            .flat_map(|i| format!("line {}\n", i % 100).into_bytes())
+            .collect();
+        let new: Vec<u8> = (0..5000)
+            .flat_map(|i| format!("line {}\n", (i * 7) % 103).into_bytes())
+            .collect();
+        for algorithm in [
+            Algorithm::Minimal,
+            Algorithm::Patience,
+            Algorithm::Histogram,
+        ] {
+            let options = DiffOptions {
+                algorithm,
+                ..Default::default()
+            };
+            let hunks = diff_lines(&old, &new, &options);
+            assert_eq!(patch(&lines(&old), &lines(&new), &hunks), lines(&new));
+        }
+    }
+
+    #[test]
+    fn test_indent_heuristic() {
+        // the new block goes in front of the equal one instead of into it.
+        let old = b"{\n    x();\n".as_slice();
+        let new = b"{\n    }\n\n{\n    x();\n".as_slice();
+        let added = |start| {
+            vec![Hunk {
+                old_start: start,
+                old_len: 0,
+                new_start: start,
+                new_len: 3,
+            }]
+        };
+        assert_eq!(diff_lines(old, new, &DiffOptions::default()), added(0));
+        let options = DiffOptions {
+            indent_heuristic: false,
+            ..Default::default()
+        };
+        assert_eq!(diff_lines(old, new, &options), added(1));
+    }
+
+    #[test]
+    fn test_unified() {
+        let old = b"fn main() {\n    a();\n    b();\n    c();\n}\n".as_slice();
+        let new = b"fn main() {\n    a();\n    c();\n}".as_slice();
+        let options = DiffOptions {
+            context: 1,
+            ..Default::default()
+        };
+        assert_eq!(
+            String::from_utf8(unified(old, new, &options)).unwrap(),
+            "@@ -2,4 +2,3 @@ fn main() {\n     a();\n-    b();\n     c();\n-}\n+}\n\\ No newline at end of file\n"
+        );
+        assert!(unified(old, old, &options).is_empty());
+    }
+
+    #[test]
+    fn test_word_diff() {
+        let old = b"the quick fox\njumps\nthe lazy dog\n".as_slice();
+        let new = b"the slow fox\njumps\na lazy cat\nmore\n".as_slice();
+        let options = |word_diff| DiffOptions {
+            word_diff: Some(word_diff),
+            ..Default::default()
+        };
+        assert_eq!(
+            String::from_utf8(unified(old, new, &options(WordDiff::Plain))).unwrap(),
+            "@@ -1,3 +1,4 @@\nthe [-quick-]{+slow+} fox\njumps\n[-the-]{+a+} lazy [-dog-]{+cat+}\n{+more+}\n"
+        );
+        assert_eq!(
+            String::from_utf8(unified(old, new, &options(WordDiff::Porcelain))).unwrap(),
+            "@@ -1,3 +1,4 @@\n the \n-quick\n+slow\n  fox\n~\n jumps\n~\n-the\n+a\n  lazy \n-dog\n+cat\n~\n+more\n~\n"
+        );
+        let options = DiffOptions {
+            word_regex: Some(Regex::new("[a-z]").unwrap()),
+            ..options(WordDiff::Plain)
+        };
+        assert_eq!(
+            String::from_utf8(unified(b"cat\n", b"cut\n", &options)).unwrap(),
+            "@@ -1 +1 @@\nc[-a-]{+u+}t\n"
+        );
+    }
+
+    #[test]
+    fn test_color() {
+        let options = DiffOptions {
+            color: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            String::from_utf8(unified(b"a\n", b" \tb \n\n\n", &options)).unwrap(),
+            "\x1b[36m@@ -1 +1,3 @@\x1b[m\n\x1b[31m-a\x1b[m\n\x1b[32m+\x1b[m\x1b[41m \x1b[m\t\x1b[32mb\x1b[m\x1b[41m \x1b[m\n\x1b[41m+\x1b[m\n\x1b[41m+\x1b[m\n"
+        );
+    }
+}
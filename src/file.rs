@@ -1,50 +1,63 @@
-use anyhow::{bail, Ok};
+use anyhow::{bail, Context, Ok};
 
-use crate::Result;
+use crate::{
+    index::{file_mode, file_read},
+    leaf::{tree_write, GitTreeLeaf},
+    object::{GitObject, GitObjectType},
+    repository::RGitRepository,
+    Result,
+};
 use std::{
-    cmp::max,
+    ffi::OsStr,
     fs::{self, DirEntry, Metadata},
-    path::PathBuf,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
     rc::Rc,
 };
 
 #[derive(Debug, PartialEq)]
-/// Indicates if a file is normal file with a content or directory.
-enum FileType {
+/// Indicates if a file is normal file with a content, symlink or directory.
+pub(crate) enum FileType {
     File,
+    Symlink,
     Directory,
 }
 
+/// Node of a directory tree. Content is not read while walking, only the path
+/// is kept, so callers can compare stat data first and read files that changed.
 #[derive(Debug, PartialEq)]
-struct FileNode {
-    name: String,
-    file_type: FileType,
-    content: Vec<u8>,
-    children: Option<Vec<FileNode>>,
+pub(crate) struct FileNode {
+    pub name: String,
+    pub file_type: FileType,
+    pub path: PathBuf,
+    pub children: Option<Vec<FileNode>>,
 }
 
 /// Custom type for predicate that will skip files. Rc for clonability.
-type SkipPredicate = Rc<dyn Fn(&DirEntry, &PathBuf, &Metadata) -> bool>;
+pub(crate) type SkipPredicate = Rc<dyn Fn(&DirEntry, &PathBuf, &Metadata) -> bool>;
 
 impl FileNode {
+    #[cfg(test)]
     fn new(directory_path: &str) -> Result<Self> {
+        let directory_path = Path::new(directory_path);
         Self::traverse_and_build(
             directory_path,
             vec![
-                ignore_by_file_name("target".into()),
-                ignore_dir_starting_with_dot(),
+                ignore_by_file_name(".git".into()),
+                Rc::new(crate::ignore::Ignore::new(directory_path)).skip_predicate(),
             ],
         )
     }
 
-    fn traverse_and_build(
-        directory_path: &str,
+    /// Builds tree of directory, children are sorted by name.
+    pub(crate) fn traverse_and_build(
+        directory_path: &Path,
         skip_predicates: Vec<SkipPredicate>,
     ) -> Result<Self> {
         let mut root = Self {
-            name: directory_path.into(),
+            name: directory_path.to_string_lossy().into(),
             file_type: FileType::Directory,
-            content: vec![],
+            path: directory_path.into(),
             children: None,
         };
         let mut children = vec![];
@@ -52,95 +65,150 @@ impl FileNode {
         traverse_directory(directory_path, |entry, path, metadata| {
             for sk in &skip_predicates {
                 if sk(&entry, &path, &metadata) {
-                    println!("skipped {:?}", entry);
+                    debug!("skipped {:?}", entry);
 
                     return Ok(());
                 }
             }
-            println!("{:?}", entry);
+            debug!("{:?}", entry);
             if metadata.is_dir() {
-                children.push(Self::traverse_and_build(
-                    path.to_str().unwrap(),
-                    skip_predicates.clone(),
-                )?);
-            }
-            if metadata.is_file() {
-                let content = fs::read(path)?;
-                children.push(Self {
-                    name: entry.file_name().to_str().unwrap().into(),
-                    file_type: FileType::File,
-                    content: content,
-                    children: None,
-                });
+                children.push(Self::traverse_and_build(&path, skip_predicates.clone())?);
+                return Ok(());
             }
+            let file_type = match metadata.file_type().is_symlink() {
+                true => FileType::Symlink,
+                false if metadata.is_file() => FileType::File,
+                // sockets, fifos and devices cannot be stored.
+                false => return Ok(()),
+            };
+            children.push(Self {
+                name: entry.file_name().to_string_lossy().into(),
+                file_type,
+                path,
+                children: None,
+            });
             Ok(())
         })?;
 
         if !children.is_empty() {
+            children.sort_by(|a, b| a.path.cmp(&b.path));
             root.children = Some(children);
         }
         Ok(root)
     }
-}
 
-/// Returned SkipPredicate will skip directiory that starts with "."
-fn ignore_dir_starting_with_dot() -> SkipPredicate {
-    Rc::new(
-        move |entry: &DirEntry, _: &PathBuf, metadata: &Metadata| -> bool {
-            metadata.is_dir() && entry.file_name().to_str().unwrap().starts_with(".")
-        },
-    )
+    /// Reads content of the file, for symlinks it is the link target.
+    pub(crate) fn content(&self) -> Result<Vec<u8>> {
+        if self.file_type == FileType::Directory {
+            bail!("{} is a directory", self.path.display());
+        }
+        file_read(&self.path)
+    }
+
+    /// Writes blobs of files and trees of directories under the node, returns
+    /// SHA of its tree. Empty directories are left out, git cannot store them.
+    pub(crate) fn write_tree(&self, repo: &RGitRepository, filemode: bool) -> Result<String> {
+        let mut leaves = vec![];
+        for child in self.children.iter().flatten() {
+            let name = child
+                .path
+                .file_name()
+                .context("path without file name")?
+                .as_bytes()
+                .to_vec();
+            let (mode, sha) = match child.file_type {
+                FileType::Directory if child.files().is_empty() => continue,
+                FileType::Directory => (0o40000, child.write_tree(repo, filemode)?),
+                FileType::File | FileType::Symlink => {
+                    let mode = file_mode(&fs::symlink_metadata(&child.path)?, filemode);
+                    let blob =
+                        GitObject::new(repo, Some(child.content()?), Some(GitObjectType::Blob))?;
+                    (mode, blob.object_write(None)?)
+                }
+            };
+            leaves.push(GitTreeLeaf::new(format!("{:o}", mode), name, sha));
+        }
+        tree_write(repo, leaves)
+    }
+
+    /// Returns files and symlinks of the tree, in depth-first order.
+    pub(crate) fn files(&self) -> Vec<&FileNode> {
+        match &self.children {
+            Some(children) => children.iter().flat_map(|c| c.files()).collect(),
+            None if self.file_type == FileType::Directory => vec![],
+            None => vec![self],
+        }
+    }
 }
 
 /// Returned SkipPredicate will skip files with given file name.
-fn ignore_by_file_name(file_name: String) -> SkipPredicate {
+pub(crate) fn ignore_by_file_name(file_name: String) -> SkipPredicate {
     Rc::new(move |entry: &DirEntry, _: &PathBuf, _: &Metadata| -> bool {
-        entry.file_name().to_str().unwrap().eq(&file_name)
+        entry.file_name() == file_name.as_str()
     })
 }
 
-/// Function takes cursor and goes through whole directory.
-fn traverse_directory<F>(path: &str, mut cursor: F) -> Result<()>
+/// Joins path components with "/", the separator used for paths in git objects.
+pub(crate) fn slash_path(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Path of a tree or index entry, whose bytes need not be UTF-8, for file
+/// system calls.
+pub(crate) fn bytes_path(path: &[u8]) -> &Path {
+    Path::new(OsStr::from_bytes(path))
+}
+
+/// Joins path components with "/" like slash_path, keeping bytes that are not
+/// UTF-8.
+pub(crate) fn slash_path_bytes(path: &Path) -> Vec<u8> {
+    path.components()
+        .map(|c| c.as_os_str().as_bytes())
+        .collect::<Vec<_>>()
+        .join(&b'/')
+}
+
+/// Lists files and symlinks under directory recursively, skipping entries matched by predicates.
+pub(crate) fn list_files(
+    directory_path: &Path,
+    skip_predicates: &[SkipPredicate],
+) -> Result<Vec<PathBuf>> {
+    let mut ret = vec![];
+    traverse_directory(directory_path, |entry, path, metadata| {
+        if skip_predicates
+            .iter()
+            .any(|sk| sk(&entry, &path, &metadata))
+        {
+            return Ok(());
+        }
+        if metadata.is_dir() {
+            ret.extend(list_files(&path, skip_predicates)?);
+        } else {
+            ret.push(path);
+        }
+        Ok(())
+    })?;
+    Ok(ret)
+}
+
+/// Function takes cursor and goes through whole directory. Symlinks are not followed.
+fn traverse_directory<F>(path: &Path, mut cursor: F) -> Result<()>
 where
     F: FnMut(DirEntry, PathBuf, Metadata) -> Result<()>,
 {
     for entry in fs::read_dir(path)? {
         let entry = entry?;
         let path = entry.path();
-        let metadata = fs::metadata(&path)?;
+        let metadata = fs::symlink_metadata(&path)?;
 
         cursor(entry, path, metadata)?;
     }
     Ok(())
 }
 
-/// The longest common subsequence (LCS) problem is the problem of finding the longest subsequence common to all sequences in a set of sequences.
-///
-/// https://en.wikipedia.org/wiki/Longest_common_subsequence_problem
-///
-/// L(ASDFG,BCDEG) = 1 + L(ASDF,BCDE)
-/// L(ASDFGI,BCDEG) = MAX(L(ASDFG,BCDEG),L(ASDFGI,BCDE))
-fn lcs(s1: &str, s2: &str, dp: &mut Vec<Vec<isize>>) -> usize {
-    let (m, n) = (s1.len(), s2.len());
-
-    if m == 0 || n == 0 {
-        return 0;
-    }
-
-    if dp[m - 1][n - 1] != -1 {
-        return dp[m - 1][n - 1] as usize;
-    }
-
-    if s1.chars().last().unwrap() == s2.chars().last().unwrap() {
-        let value = 1 + lcs(&s1[..m - 1], &s2[..n - 1], dp);
-        dp[m - 1][n - 1] = value as isize;
-        return value;
-    }
-    let value = max(lcs(s1, &s2[..n - 1], dp), lcs(&s1[..m - 1], s2, dp));
-    dp[m - 1][n - 1] = value as isize;
-    return value;
-}
-
 #[cfg(test)]
 mod tests {
     use super::{FileNode, FileType};
@@ -159,75 +227,76 @@ mod tests {
             FileNode {
                 name: "test_directory".into(),
                 file_type: FileType::Directory,
-                content: vec![],
+                path: "test_directory".into(),
                 children: Some(vec![
                     FileNode {
                         name: "4.txt".into(),
                         file_type: FileType::File,
-                        content: vec![],
+                        path: "test_directory/4.txt".into(),
                         children: None,
                     },
-                    FileNode {
-                        name: "test_directory/dir2".into(),
-                        file_type: FileType::Directory,
-                        content: vec![],
-                        children: Some(vec![FileNode {
-                            name: "ignore.txt".into(),
-                            file_type: FileType::File,
-                            content: vec![],
-                            children: None,
-                        }]),
-                    },
-                    FileNode {
-                        name: "test_directory/dir3".into(),
-                        file_type: FileType::Directory,
-                        content: vec![],
-                        children: Some(vec![FileNode {
-                            name: "31.txt".into(),
-                            file_type: FileType::File,
-                            content: vec![],
-                            children: None,
-                        }]),
-                    },
                     FileNode {
                         name: "test_directory/dir1".into(),
                         file_type: FileType::Directory,
-                        content: vec![],
+                        path: "test_directory/dir1".into(),
                         children: Some(vec![
+                            FileNode {
+                                name: "test_directory/dir1/dir11".into(),
+                                file_type: FileType::Directory,
+                                path: "test_directory/dir1/dir11".into(),
+                                children: Some(vec![FileNode {
+                                    name: "111.txt".into(),
+                                    file_type: FileType::File,
+                                    path: "test_directory/dir1/dir11/111.txt".into(),
+                                    children: None,
+                                }]),
+                            },
                             FileNode {
                                 name: "test_directory/dir1/dir12".into(),
                                 file_type: FileType::Directory,
-                                content: vec![],
+                                path: "test_directory/dir1/dir12".into(),
                                 children: Some(vec![
                                     FileNode {
                                         name: "121.txt".into(),
                                         file_type: FileType::File,
-                                        content: vec![],
+                                        path: "test_directory/dir1/dir12/121.txt".into(),
                                         children: None,
                                     },
                                     FileNode {
                                         name: "122.txt".into(),
                                         file_type: FileType::File,
-                                        content: vec![],
+                                        path: "test_directory/dir1/dir12/122.txt".into(),
                                         children: None,
                                     }
-                                ])
-                            },
-                            FileNode {
-                                name: "test_directory/dir1/dir11".into(),
-                                file_type: FileType::Directory,
-                                content: vec![],
-                                children: Some(vec![FileNode {
-                                    name: "111.txt".into(),
-                                    file_type: FileType::File,
-                                    content: vec![],
-                                    children: None,
-                                }])
-                            },
-                        ])
+                                ]),
+                            }
+                        ]),
+                    },
+                    FileNode {
+                        name: "test_directory/dir2".into(),
+                        file_type: FileType::Directory,
+                        path: "test_directory/dir2".into(),
+                        children: Some(vec![FileNode {
+                            name: "ignore.txt".into(),
+                            file_type: FileType::File,
+                            path: "test_directory/dir2/ignore.txt".into(),
+                            children: None,
+                        }]),
                     },
-                ])
+                    FileNode {
+                        name: "test_directory/dir3".into(),
+                        file_type: FileType::Directory,
+                        path: "test_directory/dir3".into(),
+                        children: Some(vec![FileNode {
+                            name: "31.txt".into(),
+                            file_type: FileType::File,
+                            path: "test_directory/dir3/31.txt".into(),
+                            children: None,
+                        }]),
+                    }
+                ]),
             }
-        )
+        );
+        assert_eq!(root.files().len(), 6);
     }
 }
@@ -0,0 +1,681 @@
+use crate::color::RESET;
+
+// colors of lines of history, each new one taking the next.
+const COLUMN_COLORS: [&str; 12] = [
+    "\x1b[31m",
+    "\x1b[32m",
+    "\x1b[33m",
+    "\x1b[34m",
+    "\x1b[35m",
+    "\x1b[36m",
+    "\x1b[1;31m",
+    "\x1b[1;32m",
+    "\x1b[1;33m",
+    "\x1b[1;34m",
+    "\x1b[1;35m",
+    "\x1b[1;36m",
+];
+// color index of lines drawn unpainted.
+const NO_COLOR: usize = COLUMN_COLORS.len();
+
+// edges from a merge to its parents, by layout of the merge.
+const MERGE_CHARS: [char; 3] = ['/', '|', '\\'];
+
+/// Which row of a commit the graph draws next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Lines of history going straight down, between commits.
+    Padding,
+    /// "..." standing for rows of the previous commit never drawn.
+    Skip,
+    /// Lines moving right to make room for the edges of an octopus merge.
+    PreCommit,
+    /// The row with the commit itself.
+    Commit,
+    /// Edges from a merge to its parents.
+    PostMerge,
+    /// Lines moving left, towards the column they end up in.
+    Collapsing,
+}
+
+/// A line of history: the commit it leads to next.
+#[derive(Debug, Clone)]
+struct Column {
+    commit: String,
+    color: usize,
+}
+
+/// A row of the graph, with its width on screen.
+struct Line {
+    buf: Vec<u8>,
+    width: usize,
+}
+
+impl Line {
+    fn push(&mut self, c: char, count: usize) {
+        for _ in 0..count {
+            self.buf.push(c as u8);
+        }
+        self.width += count;
+    }
+
+    fn column(&mut self, column: &Column, c: char) {
+        match column.color < NO_COLOR {
+            true => {
+                self.buf
+                    .extend_from_slice(COLUMN_COLORS[column.color].as_bytes());
+                self.push(c, 1);
+                self.buf.extend_from_slice(RESET.as_bytes());
+            }
+            false => self.push(c, 1),
+        }
+    }
+}
+
+/// Draws lines of history left of log output the way git does: a column per
+/// line, "*" for commits, edges from merges to their parents and lines
+/// joining once they lead to the same commit.
+///
+/// Indices are signed like positions on screen, with -1 for none.
+#[derive(Debug)]
+pub struct Graph {
+    color: bool,
+    commit: String,
+    /// Parents of the commit that are shown.
+    parents: Vec<String>,
+    /// Width of rows for the commit, so text right of them lines up.
+    width: i32,
+    /// Row of PreCommit being drawn.
+    expansion_row: i32,
+    state: State,
+    prev_state: State,
+    commit_index: i32,
+    prev_commit_index: i32,
+    /// Where a merge's first parent is: 0 left of it, 1 below it.
+    merge_layout: i32,
+    /// Columns added right of the commit by a merge.
+    edges_added: i32,
+    prev_edges_added: i32,
+    /// Lines of history going into the commit's row.
+    columns: Vec<Column>,
+    /// Lines of history going out of the commit's rows.
+    new_columns: Vec<Column>,
+    /// Per screen position of the current row, the new column the line
+    /// there goes to.
+    mapping: Vec<i32>,
+    /// Mapping of the previous row.
+    old_mapping: Vec<i32>,
+    mapping_size: usize,
+    default_color: usize,
+}
+
+impl Graph {
+    pub fn new(color: bool) -> Self {
+        Graph {
+            color,
+            commit: String::new(),
+            parents: vec![],
+            width: 0,
+            expansion_row: 0,
+            state: State::Padding,
+            prev_state: State::Padding,
+            commit_index: 0,
+            prev_commit_index: 0,
+            merge_layout: 0,
+            edges_added: 0,
+            prev_edges_added: 0,
+            columns: vec![],
+            new_columns: vec![],
+            mapping: vec![],
+            old_mapping: vec![],
+            mapping_size: 0,
+            // the first commit takes the first color.
+            default_color: NO_COLOR - 1,
+        }
+    }
+
+    /// Moves on to the next commit shown, given its parents that are shown.
+    pub fn update(&mut self, commit: &str, parents: &[String]) {
+        self.commit = commit.to_string();
+        self.parents = parents.to_vec();
+        self.prev_commit_index = self.commit_index;
+        self.columns_update();
+        self.expansion_row = 0;
+
+        // the previous commit not done drawing is cut short by "...".
+        self.state = match (self.state, self.needs_pre_commit_line()) {
+            (State::Padding, true) => State::PreCommit,
+            (State::Padding, false) => State::Commit,
+            _ => State::Skip,
+        };
+    }
+
+    /// Whether every row of the commit up to padding has been drawn.
+    pub fn is_commit_finished(&self) -> bool {
+        self.state == State::Padding
+    }
+
+    fn state_update(&mut self, state: State) {
+        self.prev_state = self.state;
+        self.state = state;
+    }
+
+    fn current_color(&self) -> usize {
+        match self.color {
+            true => self.default_color,
+            false => NO_COLOR,
+        }
+    }
+
+    fn color_increment(&mut self) {
+        self.default_color = (self.default_color + 1) % NO_COLOR;
+    }
+
+    fn commit_color(&self, commit: &str) -> usize {
+        self.columns
+            .iter()
+            .find(|column| column.commit == commit)
+            .map_or_else(|| self.current_color(), |column| column.color)
+    }
+
+    fn new_column_insert(&mut self, commit: &str, index: i32) {
+        let i = match self.new_columns.iter().position(|c| c.commit == commit) {
+            Some(i) => i as i32,
+            None => {
+                let color = self.commit_color(commit);
+                self.new_columns.push(Column {
+                    commit: commit.to_string(),
+                    color,
+                });
+                self.new_columns.len() as i32 - 1
+            }
+        };
+
+        let mapping_index;
+        if self.parents.len() > 1 && index > -1 && self.merge_layout == -1 {
+            // the first parent of a merge: its edges go left when that
+            // parent's line is left of the merge.
+            let distance = index - i;
+            let shift = match distance > 1 {
+                true => 2 * distance - 3,
+                false => 1,
+            };
+            self.merge_layout = match distance > 0 {
+                true => 0,
+                false => 1,
+            };
+            self.edges_added = self.parents.len() as i32 + self.merge_layout - 2;
+            mapping_index = self.width + (self.merge_layout - 1) * shift;
+            self.width += 2 * self.merge_layout;
+        } else if self.edges_added > 0 && i == self.mapping[(self.width - 2) as usize] {
+            // an edge of the merge joins the last line right away.
+            mapping_index = self.width - 2;
+            self.edges_added = -1;
+        } else {
+            mapping_index = self.width;
+            self.width += 2;
+        }
+        self.mapping[mapping_index as usize] = i;
+    }
+
+    fn columns_update(&mut self) {
+        std::mem::swap(&mut self.columns, &mut self.new_columns);
+        self.new_columns.clear();
+
+        let max_new_columns = self.columns.len() + self.parents.len();
+        if self.mapping.len() < 2 * max_new_columns {
+            self.mapping.resize(2 * max_new_columns, -1);
+            self.old_mapping.resize(2 * max_new_columns, -1);
+        }
+        self.mapping_size = 2 * max_new_columns;
+        self.mapping[..self.mapping_size].fill(-1);
+        self.width = 0;
+        self.prev_edges_added = self.edges_added;
+        self.edges_added = 0;
+
+        // lines keep their order, the commit's being replaced by lines to
+        // its parents. A commit no line leads to starts a new one at the end.
+        let mut seen_this = false;
+        let mut in_columns = true;
+        for i in 0..=self.columns.len() {
+            let commit = match self.columns.get(i) {
+                Some(column) => column.commit.clone(),
+                None if seen_this => break,
+                None => {
+                    in_columns = false;
+                    self.commit.clone()
+                }
+            };
+            if commit == self.commit {
+                seen_this = true;
+                self.commit_index = i as i32;
+                self.merge_layout = -1;
+                for parent in self.parents.clone() {
+                    if self.parents.len() > 1 || !in_columns {
+                        self.color_increment();
+                    }
+                    self.new_column_insert(&parent, i as i32);
+                }
+                // a commit without parents still takes up its column.
+                if self.parents.is_empty() {
+                    self.width += 2;
+                }
+            } else {
+                self.new_column_insert(&commit, -1);
+            }
+        }
+
+        while self.mapping_size > 1 && self.mapping[self.mapping_size - 1] < 0 {
+            self.mapping_size -= 1;
+        }
+    }
+
+    /// Parents of an octopus merge beyond the first two, drawn as dashes.
+    fn dashed_parents(&self) -> i32 {
+        self.parents.len() as i32 + self.merge_layout - 3
+    }
+
+    fn needs_pre_commit_line(&self) -> bool {
+        self.parents.len() >= 3
+            && self.commit_index < self.columns.len() as i32 - 1
+            && self.expansion_row < self.dashed_parents() * 2
+    }
+
+    /// Whether every line is in its column, or one right of it about to
+    /// move there with a "/".
+    fn is_mapping_correct(&self) -> bool {
+        self.mapping[..self.mapping_size]
+            .iter()
+            .enumerate()
+            .all(|(i, &target)| target < 0 || target == i as i32 / 2)
+    }
+
+    /// Next row of the graph, padded to the width of the commit's rows, and
+    /// whether it is the one of the commit.
+    fn next_line(&mut self) -> (Vec<u8>, bool) {
+        let mut line = Line {
+            buf: vec![],
+            width: 0,
+        };
+        let state = self.state;
+        match state {
+            State::Padding => self.padding_line_write(&mut line),
+            State::Skip => self.skip_line_write(&mut line),
+            State::PreCommit => self.pre_commit_line_write(&mut line),
+            State::Commit => self.commit_line_write(&mut line),
+            State::PostMerge => self.post_merge_line_write(&mut line),
+            State::Collapsing => self.collapsing_line_write(&mut line),
+        }
+        let width = self.width as usize;
+        if line.width < width {
+            line.push(' ', width - line.width);
+        }
+        (line.buf, state == State::Commit)
+    }
+
+    fn padding_line_write(&mut self, line: &mut Line) {
+        for column in &self.new_columns {
+            line.column(column, '|');
+            line.push(' ', 1);
+        }
+    }
+
+    fn skip_line_write(&mut self, line: &mut Line) {
+        line.push('.', 3);
+        match self.needs_pre_commit_line() {
+            true => self.state_update(State::PreCommit),
+            false => self.state_update(State::Commit),
+        }
+    }
+
+    fn pre_commit_line_write(&mut self, line: &mut Line) {
+        let mut seen_this = false;
+        for (i, column) in self.columns.iter().enumerate() {
+            if column.commit == self.commit {
+                seen_this = true;
+                line.column(column, '|');
+                line.push(' ', self.expansion_row as usize);
+            } else if seen_this && self.expansion_row == 0 {
+                // lines right of a merge drawn as "\" keep going that way.
+                match self.prev_state == State::PostMerge && self.prev_commit_index < i as i32 {
+                    true => line.column(column, '\\'),
+                    false => line.column(column, '|'),
+                }
+            } else if seen_this {
+                line.column(column, '\\');
+            } else {
+                line.column(column, '|');
+            }
+            line.push(' ', 1);
+        }
+
+        self.expansion_row += 1;
+        if !self.needs_pre_commit_line() {
+            self.state_update(State::Commit);
+        }
+    }
+
+    fn octopus_merge_write(&self, line: &mut Line) {
+        // dashes take the colors of the lines their parents end up in.
+        let dashed = self.dashed_parents();
+        for i in 0..dashed {
+            let position = ((self.commit_index + i + 2) * 2) as usize;
+            // an edge already joining a line left of it has no color here.
+            let column = match self.mapping.get(position) {
+                Some(&j) if j >= 0 => self.new_columns[j as usize].clone(),
+                _ => Column {
+                    commit: String::new(),
+                    color: NO_COLOR,
+                },
+            };
+            let column = &column;
+            line.column(column, '-');
+            match i == dashed - 1 {
+                true => line.column(column, '.'),
+                false => line.column(column, '-'),
+            }
+        }
+    }
+
+    fn commit_line_write(&mut self, line: &mut Line) {
+        let mut seen_this = false;
+        for i in 0..=self.columns.len() {
+            let column = match self.columns.get(i) {
+                Some(column) if column.commit != self.commit => column,
+                None if seen_this => break,
+                _ => {
+                    seen_this = true;
+                    line.push('*', 1);
+                    if self.parents.len() > 2 {
+                        self.octopus_merge_write(line);
+                    }
+                    line.push(' ', 1);
+                    continue;
+                }
+            };
+            let i = i as i32;
+            let c = if seen_this && self.edges_added > 1 {
+                '\\'
+            } else if seen_this && self.edges_added == 1 {
+                // lines right of the previous merge drawn as "\" keep going
+                // that way.
+                match self.prev_state == State::PostMerge
+                    && self.prev_edges_added > 0
+                    && self.prev_commit_index < i
+                {
+                    true => '\\',
+                    false => '|',
+                }
+            } else if self.prev_state == State::Collapsing
+                && self.old_mapping[(2 * i + 1) as usize] == i
+                && self.mapping[(2 * i) as usize] < i
+            {
+                '/'
+            } else {
+                '|'
+            };
+            line.column(column, c);
+            line.push(' ', 1);
+        }
+
+        if self.parents.len() > 1 {
+            self.state_update(State::PostMerge);
+        } else if self.is_mapping_correct() {
+            self.state_update(State::Padding);
+        } else {
+            self.state_update(State::Collapsing);
+        }
+    }
+
+    fn post_merge_line_write(&mut self, line: &mut Line) {
+        let mut seen_this = false;
+        // line of the first parent when left of the merge, its edge running
+        // along the bottom to the merge as "_".
+        let mut parent_column = None;
+        for i in 0..=self.columns.len() {
+            let column = match self.columns.get(i) {
+                Some(column) if column.commit != self.commit => column,
+                None if seen_this => break,
+                _ => {
+                    seen_this = true;
+                    let mut layout = self.merge_layout as usize;
+                    for (j, parent) in self.parents.iter().enumerate() {
+                        let column = self
+                            .new_columns
+                            .iter()
+                            .find(|column| column.commit == *parent)
+                            .expect("parent of merge has a column");
+                        line.column(column, MERGE_CHARS[layout]);
+                        if layout < 2 {
+                            layout += 1;
+                        } else if self.edges_added > 0 || j < self.parents.len() - 1 {
+                            line.push(' ', 1);
+                        }
+                    }
+                    if self.edges_added == 0 {
+                        line.push(' ', 1);
+                    }
+                    continue;
+                }
+            };
+            match seen_this {
+                true => {
+                    match self.edges_added > 0 {
+                        true => line.column(column, '\\'),
+                        false => line.column(column, '|'),
+                    }
+                    line.push(' ', 1);
+                }
+                false => {
+                    line.column(column, '|');
+                    // the first parent's "/" goes right after its line.
+                    if self.merge_layout != 0 || i as i32 != self.commit_index - 1 {
+                        match parent_column {
+                            Some(parent_column) => line.column(parent_column, '_'),
+                            None => line.push(' ', 1),
+                        }
+                    }
+                }
+            }
+            if column.commit == self.parents[0] {
+                parent_column = Some(column);
+            }
+        }
+
+        match self.is_mapping_correct() {
+            true => self.state_update(State::Padding),
+            false => self.state_update(State::Collapsing),
+        }
+    }
+
+    fn collapsing_line_write(&mut self, line: &mut Line) {
+        std::mem::swap(&mut self.mapping, &mut self.old_mapping);
+        self.mapping[..self.mapping_size].fill(-1);
+
+        // lines only ever move left, one position per row. Only one may
+        // cross others per row, drawn as a horizontal "_" run.
+        let mut horizontal_edge = -1;
+        let mut horizontal_edge_target = -1;
+        for i in 0..self.mapping_size {
+            let target = self.old_mapping[i];
+            if target < 0 {
+                continue;
+            }
+            let position = i as i32;
+            if target * 2 == position {
+                self.mapping[i] = target;
+            } else if self.mapping[i - 1] < 0 {
+                self.mapping[i - 1] = target;
+                if horizontal_edge == -1 {
+                    horizontal_edge = position;
+                    horizontal_edge_target = target;
+                    for j in (target * 2 + 3..position - 2).step_by(2) {
+                        self.mapping[j as usize] = target;
+                    }
+                }
+            } else if self.mapping[i - 1] == target {
+                // joins the line left of it, which leads to the same commit.
+            } else {
+                // crosses the line left of it, to the one left of that.
+                self.mapping[i - 2] = target;
+                if horizontal_edge == -1 {
+                    horizontal_edge = position - 1;
+                    horizontal_edge_target = target;
+                    for j in (target * 2 + 3..position - 2).step_by(2) {
+                        self.mapping[j as usize] = target;
+                    }
+                }
+            }
+        }
+        // the commit's row sees where lines ended up, to finish moves.
+        let size = self.mapping_size;
+        self.old_mapping[..size].copy_from_slice(&self.mapping[..size]);
+        if self.mapping[self.mapping_size - 1] < 0 {
+            self.mapping_size -= 1;
+        }
+
+        let mut used_horizontal = false;
+        for i in 0..self.mapping_size {
+            let target = self.mapping[i];
+            let position = i as i32;
+            if target < 0 {
+                line.push(' ', 1);
+                continue;
+            }
+            let column = &self.new_columns[target as usize];
+            if target * 2 == position {
+                line.column(column, '|');
+            } else if target == horizontal_edge_target && position != horizontal_edge - 1 {
+                // only the first segment of the run goes on to the next row.
+                if position != target * 2 + 3 {
+                    self.mapping[i] = -1;
+                }
+                used_horizontal = true;
+                line.column(column, '_');
+            } else {
+                if used_horizontal && position < horizontal_edge {
+                    self.mapping[i] = -1;
+                }
+                line.column(column, '/');
+            }
+        }
+
+        if self.is_mapping_correct() {
+            self.state_update(State::Padding);
+        }
+    }
+
+    /// Row leaving every line as it is, to put beside text between rows of
+    /// a commit.
+    fn padding_line(&mut self) -> Vec<u8> {
+        if self.state != State::Commit {
+            return self.next_line().0;
+        }
+        let mut line = Line {
+            buf: vec![],
+            width: 0,
+        };
+        for column in &self.columns {
+            line.column(column, '|');
+            match column.commit == self.commit && self.parents.len() > 2 {
+                true => line.push(' ', (self.parents.len() - 2) * 2),
+                false => line.push(' ', 1),
+            }
+        }
+        let width = self.width as usize;
+        if line.width < width {
+            line.push(' ', width - line.width);
+        }
+        self.prev_state = State::Padding;
+        line.buf
+    }
+
+    /// Writes rows up to and including the commit's, which text follows.
+    pub fn commit_show(&mut self, out: &mut Vec<u8>) {
+        if self.is_commit_finished() {
+            out.extend_from_slice(&self.padding_line());
+            return;
+        }
+        let mut shown_commit = false;
+        while !shown_commit && !self.is_commit_finished() {
+            let (line, commit_line) = self.next_line();
+            out.extend_from_slice(&line);
+            shown_commit = commit_line;
+            if !shown_commit {
+                out.push(b'\n');
+            }
+        }
+    }
+
+    /// Writes the next row, to put before a line of text.
+    pub fn line_show(&mut self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.next_line().0);
+    }
+
+    /// Writes a row leaving lines as they are, without a newline.
+    pub fn padding_show(&mut self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.padding_line());
+    }
+
+    /// Writes the commit's rows left to draw, separated by newlines.
+    fn remainder_show(&mut self, out: &mut Vec<u8>) {
+        while !self.is_commit_finished() {
+            out.extend_from_slice(&self.next_line().0);
+            if !self.is_commit_finished() {
+                out.push(b'\n');
+            }
+        }
+    }
+
+    /// Writes text of the commit after its first line, each line beside a
+    /// row, then rows left to draw.
+    pub fn message_show(&mut self, out: &mut Vec<u8>, text: &[u8]) {
+        let mut lines = text.split_inclusive(|&b| b == b'\n').peekable();
+        while let Some(line) = lines.next() {
+            out.extend_from_slice(line);
+            if line.ends_with(b"\n") && lines.peek().is_some() {
+                self.line_show(out);
+            }
+        }
+
+        if !self.is_commit_finished() {
+            let newline_terminated = text.ends_with(b"\n");
+            if !newline_terminated {
+                out.push(b'\n');
+            }
+            self.remainder_show(out);
+            if newline_terminated {
+                out.push(b'\n');
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Graph;
+
+    #[test]
+    fn test_graph() {
+        // a merge of a side branch, drawn as oneline log does.
+        let commits = [
+            ("m", vec!["a", "s"]),
+            ("s", vec!["b"]),
+            ("a", vec!["b"]),
+            ("b", vec![]),
+        ];
+        let mut graph = Graph::new(false);
+        let mut out = vec![];
+        for (commit, parents) in commits {
+            let parents: Vec<String> = parents.into_iter().map(String::from).collect();
+            graph.update(commit, &parents);
+            graph.commit_show(&mut out);
+            graph.message_show(&mut out, commit.as_bytes());
+            out.push(b'\n');
+        }
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "*   m\n|\\  \n| * s\n* | a\n|/  \n* b\n"
+        );
+    }
+}
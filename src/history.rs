@@ -0,0 +1,836 @@
+use anyhow::bail;
+use clap::clap_derive::ArgEnum;
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    color::{paint, parse_color, BLUE, GREEN, RED, RESET, YELLOW},
+    commit::{civil_from_days, format_tz_offset, Commit, Signature, MONTHS},
+    diff::DiffOptions,
+    graph::Graph,
+    object::GitObjectType,
+    patch::{patch_write, FilePair},
+    refs::{head_branch, ref_list, ref_resolve},
+    repository::RGitRepository,
+    Result,
+};
+
+/// How log prints a commit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Name and subject on one line.
+    Oneline,
+    /// Author and subject.
+    Short,
+    /// Author, date and message.
+    Medium,
+    /// Author, committer and message.
+    Full,
+    /// Author, committer, their dates and message.
+    Fuller,
+    /// Headers as stored and message.
+    Raw,
+    /// Graphviz digraph of parent edges instead of commits.
+    Dot,
+    /// Template with "%" placeholders. Terminated formats end each commit
+    /// with a newline, others put one between commits.
+    Format { template: String, terminated: bool },
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    /// Format names of --format, "format:" and "tformat:" templates, or a
+    /// bare template with a placeholder, which is terminated.
+    fn from_str(s: &str) -> Result<Self> {
+        let template = |template: &str, terminated| LogFormat::Format {
+            template: template.to_string(),
+            terminated,
+        };
+        Ok(match s {
+            "oneline" => LogFormat::Oneline,
+            "short" => LogFormat::Short,
+            "medium" => LogFormat::Medium,
+            "full" => LogFormat::Full,
+            "fuller" => LogFormat::Fuller,
+            "raw" => LogFormat::Raw,
+            "dot" => LogFormat::Dot,
+            s => match (s.strip_prefix("format:"), s.strip_prefix("tformat:")) {
+                (Some(s), _) => template(s, false),
+                (_, Some(s)) => template(s, true),
+                _ if s.contains('%') => template(s, true),
+                _ => bail!("invalid --pretty format: {}", s),
+            },
+        })
+    }
+}
+
+/// How log shows dates.
+#[derive(Debug, Clone, Copy, ArgEnum, PartialEq, Eq)]
+pub enum DateFormat {
+    /// "Thu Apr 7 15:13:13 2005 -0700"
+    Default,
+    /// "2005-04-07 15:13:13 -0700"
+    #[clap(alias = "iso8601")]
+    Iso,
+    /// "2005-04-07T15:13:13-07:00"
+    #[clap(alias = "iso8601-strict")]
+    IsoStrict,
+    /// "Thu, 7 Apr 2005 15:13:13 -0700"
+    #[clap(alias = "rfc2822")]
+    Rfc,
+    /// "2005-04-07"
+    Short,
+    /// "1112911993 -0700"
+    Raw,
+    /// "1112911993"
+    Unix,
+    /// "2 weeks ago"
+    Relative,
+}
+
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+
+impl DateFormat {
+    /// Date of signature in this format, in the timezone it was made in.
+    pub fn show(self, signature: &Signature) -> String {
+        let tz = format_tz_offset(signature.tz_offset);
+        let local = signature.time + signature.tz_offset as i64 * 60;
+        let days = local.div_euclid(86400);
+        let (year, month, day) = civil_from_days(days);
+        let seconds = local.rem_euclid(86400);
+        let time = format!(
+            "{:02}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        );
+        let weekday = WEEKDAYS[days.rem_euclid(7) as usize];
+        let month_name = MONTHS[month as usize - 1];
+        match self {
+            DateFormat::Default => {
+                format!(
+                    "{} {} {} {} {} {}",
+                    weekday, month_name, day, time, year, tz
+                )
+            }
+            DateFormat::Iso => format!("{:04}-{:02}-{:02} {} {}", year, month, day, time, tz),
+            DateFormat::IsoStrict => format!(
+                "{:04}-{:02}-{:02}T{}{}:{}",
+                year,
+                month,
+                day,
+                time,
+                &tz[..3],
+                &tz[3..]
+            ),
+            DateFormat::Rfc => {
+                format!(
+                    "{}, {} {} {} {} {}",
+                    weekday, day, month_name, year, time, tz
+                )
+            }
+            DateFormat::Short => format!("{:04}-{:02}-{:02}", year, month, day),
+            DateFormat::Raw => format!("{} {}", signature.time, tz),
+            DateFormat::Unix => signature.time.to_string(),
+            DateFormat::Relative => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_secs() as i64);
+                relative(now, signature.time)
+            }
+        }
+    }
+}
+
+/// How long before now time is, rounded the way git does.
+fn relative(now: i64, time: i64) -> String {
+    if now < time {
+        return "in the future".to_string();
+    }
+    let count = |n: i64, unit: &str| match n {
+        1 => format!("{} {}", n, unit),
+        n => format!("{} {}s", n, unit),
+    };
+    let mut diff = now - time;
+    if diff < 90 {
+        return count(diff, "second") + " ago";
+    }
+    diff = (diff + 30) / 60;
+    if diff < 90 {
+        return count(diff, "minute") + " ago";
+    }
+    diff = (diff + 30) / 60;
+    if diff < 36 {
+        return count(diff, "hour") + " ago";
+    }
+    diff = (diff + 12) / 24;
+    if diff < 14 {
+        return count(diff, "day") + " ago";
+    }
+    if diff < 70 {
+        return count((diff + 3) / 7, "week") + " ago";
+    }
+    if diff < 365 {
+        return count((diff + 15) / 30, "month") + " ago";
+    }
+    if diff < 1825 {
+        let months = (diff * 12 * 2 + 365) / (365 * 2);
+        return match months % 12 {
+            0 => count(months / 12, "year") + " ago",
+            n => format!("{}, {} ago", count(months / 12, "year"), count(n, "month")),
+        };
+    }
+    count((diff + 183) / 365, "year") + " ago"
+}
+
+/// Which names of references log shows beside commits.
+#[derive(Debug, Clone, Copy, ArgEnum, PartialEq, Eq)]
+pub enum Decorate {
+    /// Names without "refs/heads/", "refs/tags/" or "refs/remotes/".
+    Short,
+    /// Full reference names.
+    Full,
+    /// Short names when printing to a terminal, none otherwise.
+    Auto,
+    No,
+}
+
+impl FromStr for Decorate {
+    type Err = anyhow::Error;
+
+    /// Values of log.decorate config, where booleans mean short and no.
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s.to_lowercase().as_str() {
+            "short" | "true" | "yes" | "on" | "1" => Decorate::Short,
+            "full" => Decorate::Full,
+            "auto" => Decorate::Auto,
+            "no" | "false" | "off" | "0" => Decorate::No,
+            _ => bail!("bad log.decorate value '{}'", s),
+        })
+    }
+}
+
+/// Kinds of references commits are decorated with, each painted its own way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RefKind {
+    Head,
+    Branch,
+    Remote,
+    Tag,
+    Stash,
+}
+
+impl RefKind {
+    fn color(self) -> &'static str {
+        match self {
+            RefKind::Head => "\x1b[1;36m",
+            RefKind::Branch => "\x1b[1;32m",
+            RefKind::Remote => "\x1b[1;31m",
+            RefKind::Tag => "\x1b[1;33m",
+            RefKind::Stash => "\x1b[1;35m",
+        }
+    }
+}
+
+/// Names of the references pointing at objects, as --decorate shows them.
+#[derive(Debug, Clone, Default)]
+pub struct Decorations {
+    /// Kinds and full names of references per object, in the order found.
+    names: HashMap<String, Vec<(RefKind, String)>>,
+    /// Reference of the branch HEAD points at.
+    head_branch: Option<String>,
+    full: bool,
+}
+
+impl Decorations {
+    /// Reads branches, remote branches, tags, the stash and HEAD of repo.
+    /// Annotated tags also name the objects they point at.
+    pub fn load(repo: &RGitRepository, full: bool) -> Result<Self> {
+        let mut ret = Decorations {
+            full,
+            ..Decorations::default()
+        };
+        for (name, mut sha) in ref_list(repo, "refs")? {
+            let mut kind = match name.split('/').nth(1) {
+                Some("heads") => RefKind::Branch,
+                Some("remotes") => RefKind::Remote,
+                Some("tags") => RefKind::Tag,
+                _ if name == "refs/stash" => RefKind::Stash,
+                _ => continue,
+            };
+            loop {
+                ret.names
+                    .entry(sha.clone())
+                    .or_default()
+                    .push((kind, name.clone()));
+                let object = repo.object_read(&sha)?;
+                if object.object_type != Some(GitObjectType::Tag) {
+                    break;
+                }
+                sha = object.tag_target()?;
+                kind = RefKind::Tag;
+            }
+        }
+        if let Some(sha) = ref_resolve(repo, "HEAD")? {
+            ret.names
+                .entry(sha)
+                .or_default()
+                .push((RefKind::Head, "HEAD".into()));
+        }
+        ret.head_branch = head_branch(repo)?.map(|branch| format!("refs/heads/{}", branch));
+        Ok(ret)
+    }
+
+    fn name<'a>(&self, name: &'a str) -> &'a str {
+        match self.full {
+            true => name,
+            false => ["refs/heads/", "refs/tags/", "refs/remotes/"]
+                .iter()
+                .find_map(|prefix| name.strip_prefix(prefix))
+                .unwrap_or(name),
+        }
+    }
+
+    /// Names of the object between prefix and suffix, painted if color, or
+    /// nothing if it has none. HEAD comes first, as "HEAD -> branch" when
+    /// on a branch naming the object, then the others by name backwards.
+    pub fn show(&self, sha: &str, color: bool, prefix: &str, suffix: &str) -> String {
+        let names = match self.names.get(sha) {
+            Some(names) => names,
+            None => return String::new(),
+        };
+        let code = |code: &'static str| match color {
+            true => code,
+            false => "",
+        };
+        let current = match names.iter().any(|(kind, _)| *kind == RefKind::Head) {
+            true => names.iter().position(|(kind, name)| {
+                *kind == RefKind::Branch && Some(name) == self.head_branch.as_ref()
+            }),
+            false => None,
+        };
+
+        let mut ret = String::new();
+        let mut separator = prefix;
+        for (i, (kind, name)) in names.iter().enumerate().rev() {
+            if Some(i) == current {
+                continue;
+            }
+            ret += code(YELLOW);
+            ret += separator;
+            ret += code(RESET);
+            ret += code(kind.color());
+            if *kind == RefKind::Tag {
+                ret += "tag: ";
+            }
+            ret += self.name(name);
+            if let (RefKind::Head, Some(current)) = (kind, current) {
+                ret += " -> ";
+                ret += code(RESET);
+                ret += code(RefKind::Branch.color());
+                ret += self.name(&names[current].1);
+            }
+            ret += code(RESET);
+            separator = ", ";
+        }
+        ret += code(YELLOW);
+        ret += suffix;
+        ret += code(RESET);
+        ret
+    }
+}
+
+/// Settings of how log prints commits.
+#[derive(Debug, Clone)]
+pub struct LogOptions {
+    pub format: LogFormat,
+    /// Show commit names as shortest unique prefixes.
+    pub abbrev_commit: bool,
+    pub date: DateFormat,
+    pub color: bool,
+    /// Show names of references beside commits.
+    pub decorate: bool,
+    /// Names of references, for --decorate and "%d".
+    pub decorations: Decorations,
+    /// Draw lines of history left of commits.
+    pub graph: bool,
+    /// Show the patch of each commit, diffed with these settings.
+    pub patch: Option<DiffOptions>,
+}
+
+impl LogOptions {
+    /// Whether commits are separated by a newline rather than ended by one.
+    pub fn separated(&self) -> bool {
+        !matches!(
+            self.format,
+            LogFormat::Oneline
+                | LogFormat::Format {
+                    terminated: true,
+                    ..
+                }
+        )
+    }
+
+    /// Whether the format shows nothing of commits.
+    pub fn empty(&self) -> bool {
+        matches!(&self.format, LogFormat::Format { template, .. } if template.is_empty())
+    }
+}
+
+/// Prints commits one after another the way log does, beside the graph
+/// when drawing one.
+pub struct LogWriter<'a> {
+    repo: &'a RGitRepository,
+    options: &'a LogOptions,
+    graph: Option<Graph>,
+    shown_one: bool,
+    /// Whether text of the last commit did not end with a newline.
+    missing_newline: bool,
+}
+
+impl<'a> LogWriter<'a> {
+    pub fn new(repo: &'a RGitRepository, options: &'a LogOptions) -> Self {
+        LogWriter {
+            repo,
+            options,
+            graph: match options.graph {
+                true => Some(Graph::new(options.color)),
+                false => None,
+            },
+            shown_one: false,
+            missing_newline: false,
+        }
+    }
+
+    /// Moves the graph past a commit that is walked but not shown.
+    pub fn commit_skip(&mut self, sha: &str, parents: &[String]) {
+        if let Some(graph) = &mut self.graph {
+            graph.update(sha, parents);
+        }
+    }
+
+    /// Writes a commit after what separates it from the previous one: its
+    /// first line, its text, then patches of pairs when showing them.
+    /// Parents are those the graph draws lines to.
+    pub fn commit_write(
+        &mut self,
+        out: &mut Vec<u8>,
+        sha: &str,
+        commit: &Commit,
+        parents: &[String],
+        pairs: &[FilePair],
+    ) -> Result<()> {
+        let options = self.options;
+        if let Some(graph) = &mut self.graph {
+            graph.update(sha, parents);
+        }
+        // lines of the graph go on through the blank line.
+        let padding_show = |graph: &mut Option<Graph>, out: &mut Vec<u8>, missing_newline| {
+            if let (Some(graph), false) = (graph, missing_newline) {
+                graph.padding_show(out);
+            }
+        };
+        if self.shown_one && options.separated() {
+            padding_show(&mut self.graph, out, self.missing_newline);
+            out.push(b'\n');
+        }
+        self.shown_one = true;
+        if let Some(graph) = &mut self.graph {
+            graph.commit_show(out);
+        }
+
+        let name = match options.abbrev_commit {
+            true => self.repo.abbreviate(sha, 7)?,
+            false => sha.to_string(),
+        };
+        let decorations = match options.decorate {
+            true => options.decorations.show(sha, options.color, " (", ")"),
+            false => String::new(),
+        };
+        match options.format {
+            LogFormat::Format { .. } => {}
+            LogFormat::Oneline => {
+                paint(out, options.color, YELLOW, name.as_bytes());
+                out.extend_from_slice(decorations.as_bytes());
+                out.push(b' ');
+            }
+            _ => {
+                let header = format!("commit {}", name);
+                paint(out, options.color, YELLOW, header.as_bytes());
+                out.extend_from_slice(decorations.as_bytes());
+                out.push(b'\n');
+                if let Some(graph) = &mut self.graph {
+                    graph.line_show(out);
+                }
+            }
+        }
+
+        let text = commit_text(self.repo, sha, commit, options)?;
+        self.missing_newline = !text.ends_with('\n');
+        match &mut self.graph {
+            Some(graph) => graph.message_show(out, text.as_bytes()),
+            None => out.extend_from_slice(text.as_bytes()),
+        }
+        if !options.separated() && !options.empty() {
+            padding_show(&mut self.graph, out, self.missing_newline);
+            out.push(b'\n');
+        }
+
+        let patch = match &options.patch {
+            Some(patch) if !pairs.is_empty() => patch,
+            _ => return Ok(()),
+        };
+        if options.format != LogFormat::Oneline && !options.empty() {
+            padding_show(&mut self.graph, out, false);
+            out.push(b'\n');
+        }
+        let mut patches = vec![];
+        for pair in pairs {
+            patch_write(&mut patches, Some(self.repo), pair, patch)?;
+        }
+        match &mut self.graph {
+            Some(graph) => {
+                for line in patches.split_inclusive(|&b| b == b'\n') {
+                    graph.padding_show(out);
+                    out.extend_from_slice(line);
+                }
+            }
+            None => out.extend_from_slice(&patches),
+        }
+        Ok(())
+    }
+}
+
+/// Text log shows for a commit after its first line.
+fn commit_text(
+    repo: &RGitRepository,
+    sha: &str,
+    commit: &Commit,
+    options: &LogOptions,
+) -> Result<String> {
+    let format = &options.format;
+    match format {
+        LogFormat::Format { template, .. } => {
+            return format_expand(template, repo, sha, commit, options)
+        }
+        LogFormat::Oneline => return Ok(subject(&commit.message_text())),
+        _ => {}
+    }
+
+    let mut text = String::new();
+    if *format == LogFormat::Raw {
+        let (_, data) = repo.object_read_raw(sha)?;
+        let data = String::from_utf8_lossy(&data);
+        for line in data.lines().take_while(|line| !line.is_empty()) {
+            text += line;
+            text.push('\n');
+        }
+    } else {
+        if commit.parents.len() > 1 {
+            text += "Merge:";
+            for parent in &commit.parents {
+                text += &format!(" {}", repo.abbreviate(parent, 7)?);
+            }
+            text.push('\n');
+        }
+        let person = |role: &str, signature: &Signature| {
+            let padding = match format {
+                LogFormat::Fuller => "    ",
+                _ => "",
+            };
+            format!(
+                "{}: {}{} <{}>\n",
+                role, padding, signature.name, signature.email
+            )
+        };
+        text += &person("Author", &commit.author);
+        match format {
+            LogFormat::Medium => {
+                text += &format!("Date:   {}\n", options.date.show(&commit.author));
+            }
+            LogFormat::Fuller => {
+                text += &format!("AuthorDate: {}\n", options.date.show(&commit.author));
+            }
+            _ => {}
+        }
+        if matches!(format, LogFormat::Full | LogFormat::Fuller) {
+            text += &person("Commit", &commit.committer);
+        }
+        if *format == LogFormat::Fuller {
+            text += &format!("CommitDate: {}\n", options.date.show(&commit.committer));
+        }
+    }
+    text.push('\n');
+
+    // lines lose trailing whitespace, leading blank ones are dropped and
+    // short stops at the first blank one after the subject.
+    let expand_tabs = matches!(
+        format,
+        LogFormat::Medium | LogFormat::Full | LogFormat::Fuller
+    );
+    let mut first = true;
+    for line in commit.message_text().lines().map(|line| line.trim_end()) {
+        if line.is_empty() {
+            if first {
+                continue;
+            }
+            if *format == LogFormat::Short {
+                break;
+            }
+        }
+        first = false;
+        text += "    ";
+        match expand_tabs {
+            true => text += &tabs_expand(line),
+            false => text += line,
+        }
+        text.push('\n');
+    }
+    Ok(format!("{}\n", text.trim_end()))
+}
+
+/// Line with tabs replaced by spaces up to the next multiple of 8 columns.
+fn tabs_expand(line: &str) -> String {
+    let mut ret = String::new();
+    let mut column = 0;
+    for c in line.chars() {
+        match c {
+            '\t' => {
+                let spaces = 8 - column % 8;
+                ret += &" ".repeat(spaces);
+                column += spaces;
+            }
+            c => {
+                ret.push(c);
+                column += 1;
+            }
+        }
+    }
+    ret
+}
+
+/// First paragraph of a message on one line, the way "%s" shows it.
+pub fn subject(message: &str) -> String {
+    message
+        .lines()
+        .map(str::trim_end)
+        .skip_while(|line| line.is_empty())
+        .take_while(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Message after its subject and the blank lines following it.
+fn body(message: &str) -> &str {
+    // 0 before the subject, 1 in it, 2 in blank lines after it.
+    let mut state = 0;
+    let mut offset = 0;
+    for line in message.split_inclusive('\n') {
+        let blank = line.trim_end().is_empty();
+        state = match (state, blank) {
+            (0, true) => 0,
+            (0 | 1, false) => 1,
+            (1 | 2, true) => 2,
+            _ => return &message[offset..],
+        };
+        offset += line.len();
+    }
+    &message[offset..]
+}
+
+/// Expands the placeholders of a "format:" template for a commit. Unknown
+/// ones are kept as they are.
+fn format_expand(
+    template: &str,
+    repo: &RGitRepository,
+    sha: &str,
+    commit: &Commit,
+    options: &LogOptions,
+) -> Result<String> {
+    let mut ret = String::new();
+    let mut rest = template;
+    // after "%C(auto)", names are painted as log paints them.
+    let mut auto = false;
+    let auto_paint = |auto: bool, name: String| match auto {
+        true => format!("{}{}{}", YELLOW, name, RESET),
+        false => name,
+    };
+    while let Some(i) = rest.find('%') {
+        ret += &rest[..i];
+        rest = &rest[i + 1..];
+        let mut chars = rest.chars();
+        let (expansion, len): (String, usize) = match chars.next() {
+            Some('%') => ("%".into(), 1),
+            Some('n') => ("\n".into(), 1),
+            Some('H') => (auto_paint(auto, sha.into()), 1),
+            Some('h') => (auto_paint(auto, repo.abbreviate(sha, 7)?), 1),
+            Some('T') => (commit.tree.clone(), 1),
+            Some('t') => (repo.abbreviate(&commit.tree, 7)?, 1),
+            Some('P') => (commit.parents.join(" "), 1),
+            Some('p') => (
+                commit
+                    .parents
+                    .iter()
+                    .map(|parent| repo.abbreviate(parent, 7))
+                    .collect::<Result<Vec<_>>>()?
+                    .join(" "),
+                1,
+            ),
+            Some('s') => (subject(&commit.message_text()), 1),
+            Some('b') => (body(&commit.message_text()).to_string(), 1),
+            Some('B') => (commit.message_text().to_string(), 1),
+            Some('d') => (options.decorations.show(sha, auto, " (", ")"), 1),
+            Some('D') => (options.decorations.show(sha, auto, "", ""), 1),
+            Some(role @ ('a' | 'c')) => {
+                let signature = match role {
+                    'a' => &commit.author,
+                    _ => &commit.committer,
+                };
+                let show = |format: DateFormat| format.show(signature);
+                match chars.next() {
+                    Some('n' | 'N') => (signature.name.clone(), 2),
+                    Some('e' | 'E') => (signature.email.clone(), 2),
+                    Some('d') => (show(options.date), 2),
+                    Some('D') => (show(DateFormat::Rfc), 2),
+                    Some('t') => (show(DateFormat::Unix), 2),
+                    Some('i') => (show(DateFormat::Iso), 2),
+                    Some('I') => (show(DateFormat::IsoStrict), 2),
+                    Some('s') => (show(DateFormat::Short), 2),
+                    Some('r') => (show(DateFormat::Relative), 2),
+                    _ => ("%".into(), 0),
+                }
+            }
+            Some('x') => match rest
+                .get(1..3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                Some(byte) => ((byte as char).to_string(), 3),
+                None => ("%".into(), 0),
+            },
+            Some('C') => {
+                let (spec, len) = match rest[1..].strip_prefix('(') {
+                    Some(spec) => match spec.find(')') {
+                        Some(end) => (&spec[..end], end + 3),
+                        None => ("", 0),
+                    },
+                    None => ["red", "green", "blue", "reset"]
+                        .iter()
+                        .find(|name| rest[1..].starts_with(*name))
+                        .map_or(("", 0), |name| (*name, name.len() + 1)),
+                };
+                // "always," paints even when output is not.
+                let (spec, always) = match spec.strip_prefix("always,") {
+                    Some(spec) => (spec, true),
+                    None => (spec, false),
+                };
+                let code = match spec {
+                    "red" => RED.to_string(),
+                    "green" => GREEN.to_string(),
+                    "blue" => BLUE.to_string(),
+                    "reset" => RESET.to_string(),
+                    "auto" => {
+                        auto = options.color;
+                        match ret.is_empty() {
+                            true => String::new(),
+                            false => RESET.to_string(),
+                        }
+                    }
+                    spec => parse_color(spec)?,
+                };
+                match len {
+                    0 => ("%".into(), 0),
+                    _ if options.color || always => (code, len),
+                    _ => (String::new(), len),
+                }
+            }
+            _ => ("%".into(), 0),
+        };
+        ret += &expansion;
+        rest = &rest[len..];
+    }
+    ret += rest;
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        body, relative, subject, tabs_expand, DateFormat, Decorations, LogFormat, RefKind,
+    };
+    use crate::commit::Signature;
+
+    #[test]
+    fn test_date_format() {
+        let signature: Signature = "A <a@x> 1112911993 -0700".parse().unwrap();
+        let show = |format: DateFormat| format.show(&signature);
+        assert_eq!(show(DateFormat::Default), "Thu Apr 7 15:13:13 2005 -0700");
+        assert_eq!(show(DateFormat::Iso), "2005-04-07 15:13:13 -0700");
+        assert_eq!(show(DateFormat::IsoStrict), "2005-04-07T15:13:13-07:00");
+        assert_eq!(show(DateFormat::Rfc), "Thu, 7 Apr 2005 15:13:13 -0700");
+        assert_eq!(show(DateFormat::Short), "2005-04-07");
+        assert_eq!(show(DateFormat::Raw), "1112911993 -0700");
+
+        assert_eq!(relative(100, 40), "60 seconds ago");
+        assert_eq!(relative(86400 * 20, 0), "3 weeks ago");
+        assert_eq!(relative(86400 * 400, 0), "1 year, 1 month ago");
+        assert_eq!(relative(0, 1), "in the future");
+    }
+
+    #[test]
+    fn test_message_parts() {
+        let message = "\nsubject  \ncontinued\n\n\n\tbody\n\nmore\n";
+        assert_eq!(subject(message), "subject continued");
+        assert_eq!(body(message), "\tbody\n\nmore\n");
+        assert_eq!(body("subject\n"), "");
+        assert_eq!(tabs_expand("ab\tc\t"), "ab      c       ");
+    }
+
+    #[test]
+    fn test_decorations() {
+        let mut decorations = Decorations {
+            head_branch: Some("refs/heads/master".into()),
+            ..Decorations::default()
+        };
+        decorations.names.insert(
+            "c".into(),
+            vec![
+                (RefKind::Branch, "refs/heads/master".into()),
+                (RefKind::Remote, "refs/remotes/origin/master".into()),
+                (RefKind::Tag, "refs/tags/v1".into()),
+                (RefKind::Head, "HEAD".into()),
+            ],
+        );
+        assert_eq!(
+            decorations.show("c", false, " (", ")"),
+            " (HEAD -> master, tag: v1, origin/master)"
+        );
+        assert_eq!(decorations.show("d", false, " (", ")"), "");
+        assert!(decorations
+            .show("c", true, "", "")
+            .starts_with("\x1b[33m\x1b[m\x1b[1;36mHEAD -> \x1b[m\x1b[1;32mmaster"));
+    }
+
+    #[test]
+    fn test_log_format_parse() {
+        assert_eq!("fuller".parse::<LogFormat>().unwrap(), LogFormat::Fuller);
+        assert_eq!(
+            "format:%h %s".parse::<LogFormat>().unwrap(),
+            LogFormat::Format {
+                template: "%h %s".into(),
+                terminated: false
+            }
+        );
+        assert_eq!(
+            "%H".parse::<LogFormat>().unwrap(),
+            LogFormat::Format {
+                template: "%H".into(),
+                terminated: true
+            }
+        );
+        assert!("bogus".parse::<LogFormat>().is_err());
+    }
+}
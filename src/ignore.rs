@@ -0,0 +1,422 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    env, fs,
+    fs::{DirEntry, Metadata},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use crate::{
+    file::{slash_path, SkipPredicate},
+    repository::RGitRepository,
+    Result,
+};
+
+/// A single pattern line of a gitignore file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IgnorePattern {
+    /// Line as written in the file.
+    pub line: String,
+    /// File the pattern was read from, and its line number.
+    pub source: String,
+    pub line_number: usize,
+    /// Directory the pattern is relative to, "" for the work tree root.
+    base: String,
+    pattern: String,
+    negated: bool,
+    /// Pattern with a trailing slash only matches directories.
+    dir_only: bool,
+    /// Pattern with a slash is matched against the path relative to base,
+    /// otherwise against the file name at any depth.
+    anchored: bool,
+}
+
+impl IgnorePattern {
+    /// Parses a gitignore line, None for blank lines and comments.
+    pub fn parse(line: &str, base: &str, source: &str, line_number: usize) -> Option<Self> {
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        // trailing spaces are ignored unless escaped with a backslash.
+        let mut pattern = line;
+        while pattern.ends_with(' ') && !pattern.ends_with("\\ ") {
+            pattern = &pattern[..pattern.len() - 1];
+        }
+        let negated = pattern.starts_with('!');
+        // a backslash makes a leading "!" or "#" literal.
+        if negated || pattern.starts_with("\\!") || pattern.starts_with("\\#") {
+            pattern = &pattern[1..];
+        }
+        let dir_only = pattern.ends_with('/');
+        pattern = pattern.trim_end_matches('/');
+        let anchored = pattern.contains('/');
+        pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+        if pattern.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            line: line.to_string(),
+            source: source.to_string(),
+            line_number,
+            base: base.to_string(),
+            pattern: pattern.to_string(),
+            negated,
+            dir_only,
+            anchored,
+        })
+    }
+
+    /// Tells if pattern starts with "!", re-including what it matches.
+    pub fn is_negated(&self) -> bool {
+        self.negated
+    }
+
+    /// Tells if pattern matches path, relative to the work tree.
+    pub fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let relative = match self.base.as_str() {
+            "" => path,
+            base => match path.strip_prefix(base).and_then(|p| p.strip_prefix('/')) {
+                Some(relative) => relative,
+                None => return false,
+            },
+        };
+        let text = match self.anchored {
+            true => relative,
+            false => relative.rsplit('/').next().unwrap_or(relative),
+        };
+        wildmatch(self.pattern.as_bytes(), text.as_bytes())
+    }
+}
+
+/// Decides which paths of a work tree are ignored, from .gitignore files of
+/// every directory, .git/info/exclude and core.excludesFile. A .gitignore is
+/// read when a path in its directory is first checked.
+pub struct Ignore {
+    root: PathBuf,
+    /// Patterns of core.excludesFile and info/exclude, in increasing precedence.
+    global: Vec<IgnorePattern>,
+    per_dir: RefCell<HashMap<String, Rc<Vec<IgnorePattern>>>>,
+}
+
+impl Ignore {
+    /// Matcher using only .gitignore files under root.
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            global: vec![],
+            per_dir: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Matcher for the work tree of repo, with its exclude files.
+    pub fn for_repo(repo: &RGitRepository) -> Result<Self> {
+        let mut ret = Self::new(repo.work_tree());
+
+        let excludes_file = match repo.config_get("core", "excludesfile") {
+            Some(path) => match path.strip_prefix("~/") {
+                Some(rest) => env::var("HOME")
+                    .ok()
+                    .map(|home| Path::new(&home).join(rest)),
+                None => Some(PathBuf::from(path)),
+            },
+            None => match env::var("XDG_CONFIG_HOME") {
+                Ok(config) if !config.is_empty() => Some(Path::new(&config).join("git/ignore")),
+                _ => env::var("HOME")
+                    .ok()
+                    .map(|home| Path::new(&home).join(".config/git/ignore")),
+            },
+        };
+        if let Some(path) = excludes_file {
+            ret.global
+                .extend(Self::load(&path, "", &path.to_string_lossy())?);
+        }
+        ret.global.extend(Self::load(
+            &repo.repo_path(&["info", "exclude"]),
+            "",
+            ".git/info/exclude",
+        )?);
+        Ok(ret)
+    }
+
+    /// Reads patterns of a file, a missing file has none.
+    fn load(path: &Path, base: &str, source: &str) -> Result<Vec<IgnorePattern>> {
+        let content = match fs::read(path) {
+            Ok(content) => content,
+            Err(_) => return Ok(vec![]),
+        };
+        Ok(String::from_utf8_lossy(&content)
+            .lines()
+            .enumerate()
+            .filter_map(|(i, line)| IgnorePattern::parse(line, base, source, i + 1))
+            .collect())
+    }
+
+    fn dir_patterns(&self, dir: &str) -> Result<Rc<Vec<IgnorePattern>>> {
+        if let Some(patterns) = self.per_dir.borrow().get(dir) {
+            return Ok(patterns.clone());
+        }
+        let source = match dir {
+            "" => ".gitignore".to_string(),
+            dir => format!("{}/.gitignore", dir),
+        };
+        let patterns = Rc::new(Self::load(&self.root.join(&source), dir, &source)?);
+        self.per_dir
+            .borrow_mut()
+            .insert(dir.to_string(), patterns.clone());
+        Ok(patterns)
+    }
+
+    /// Returns the pattern that decides if path, relative to the work tree, is
+    /// ignored. It is the last matching one, or the one excluding a parent
+    /// directory, since files inside an excluded directory cannot be re-included.
+    pub fn pattern_for(&self, path: &str, is_dir: bool) -> Result<Option<IgnorePattern>> {
+        for (i, _) in path.match_indices('/') {
+            if let Some(pattern) = self.last_match(&path[..i], true)? {
+                if !pattern.negated {
+                    return Ok(Some(pattern));
+                }
+            }
+        }
+        self.last_match(path, is_dir)
+    }
+
+    /// Last matching pattern, deeper .gitignore files take precedence over
+    /// shallower ones and all of them over the exclude files.
+    fn last_match(&self, path: &str, is_dir: bool) -> Result<Option<IgnorePattern>> {
+        let mut dirs = vec![""];
+        dirs.extend(path.match_indices('/').map(|(i, _)| &path[..i]));
+        for dir in dirs.into_iter().rev() {
+            let patterns = self.dir_patterns(dir)?;
+            if let Some(pattern) = patterns.iter().rev().find(|p| p.matches(path, is_dir)) {
+                return Ok(Some(pattern.clone()));
+            }
+        }
+        Ok(self
+            .global
+            .iter()
+            .rev()
+            .find(|p| p.matches(path, is_dir))
+            .cloned())
+    }
+
+    pub fn is_ignored(&self, path: &str, is_dir: bool) -> Result<bool> {
+        Ok(self
+            .pattern_for(path, is_dir)?
+            .map_or(false, |p| !p.negated))
+    }
+
+    /// Returned SkipPredicate will skip ignored files and directories.
+    pub fn skip_predicate(self: Rc<Self>) -> SkipPredicate {
+        Rc::new(
+            move |_: &DirEntry, path: &PathBuf, metadata: &Metadata| -> bool {
+                match path.strip_prefix(&self.root) {
+                    Ok(relative) => self
+                        .is_ignored(&slash_path(relative), metadata.is_dir())
+                        .unwrap_or(false),
+                    Err(_) => false,
+                }
+            },
+        )
+    }
+}
+
+/// Matches text against a glob the way git matches paths: "*" and "?" do not
+/// match "/", "**" between slashes matches any number of directories, "[...]"
+/// is a character class and "\" escapes the next character.
+pub fn wildmatch(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    while p < pattern.len() {
+        match pattern[p] {
+            b'*' => {
+                let mut q = p;
+                while q < pattern.len() && pattern[q] == b'*' {
+                    q += 1;
+                }
+                let double = q - p >= 2
+                    && (p == 0 || pattern[p - 1] == b'/')
+                    && (q == pattern.len() || pattern[q] == b'/');
+                if double {
+                    if q == pattern.len() {
+                        return true;
+                    }
+                    // "**/" matches zero or more leading directories.
+                    let rest = &pattern[q + 1..];
+                    return wildmatch(rest, &text[t..])
+                        || (t..text.len())
+                            .any(|i| text[i] == b'/' && wildmatch(rest, &text[i + 1..]));
+                }
+                let rest = &pattern[q..];
+                for i in t..=text.len() {
+                    if wildmatch(rest, &text[i..]) {
+                        return true;
+                    }
+                    if i < text.len() && text[i] == b'/' {
+                        break;
+                    }
+                }
+                return false;
+            }
+            b'?' => {
+                if t >= text.len() || text[t] == b'/' {
+                    return false;
+                }
+            }
+            b'[' => match class_match(&pattern[p..], text.get(t).copied()) {
+                Some((len, matched)) => {
+                    if !matched {
+                        return false;
+                    }
+                    p += len;
+                    t += 1;
+                    continue;
+                }
+                // without closing bracket it is a literal.
+                None => {
+                    if text.get(t) != Some(&b'[') {
+                        return false;
+                    }
+                }
+            },
+            b'\\' if p + 1 < pattern.len() => {
+                p += 1;
+                if text.get(t) != Some(&pattern[p]) {
+                    return false;
+                }
+            }
+            c => {
+                if text.get(t) != Some(&c) {
+                    return false;
+                }
+            }
+        }
+        p += 1;
+        t += 1;
+    }
+    t == text.len()
+}
+
+/// Matches c against character class at the start of pattern. Returns length
+/// of the class and if it matched, None if the class is not terminated.
+fn class_match(pattern: &[u8], c: Option<u8>) -> Option<(usize, bool)> {
+    let mut i = 1;
+    let negated = matches!(pattern.get(i), Some(b'!') | Some(b'^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let pc = *pattern.get(i)?;
+        if pc == b']' && !first {
+            break;
+        }
+        first = false;
+
+        if pc == b'[' && pattern.get(i + 1) == Some(&b':') {
+            let end = pattern[i + 2..]
+                .windows(2)
+                .position(|w| w == b":]")
+                .map(|e| e + i + 2);
+            if let Some(end) = end {
+                matched |= c.map_or(false, |c| posix_class(&pattern[i + 2..end], c));
+                i = end + 2;
+                continue;
+            }
+        }
+
+        let mut lo = pc;
+        if lo == b'\\' {
+            i += 1;
+            lo = *pattern.get(i)?;
+        }
+        let mut hi = lo;
+        if pattern.get(i + 1) == Some(&b'-') && pattern.get(i + 2).map_or(false, |&h| h != b']') {
+            i += 2;
+            hi = pattern[i];
+            if hi == b'\\' {
+                i += 1;
+                hi = *pattern.get(i)?;
+            }
+        }
+        if c.map_or(false, |c| lo <= c && c <= hi) {
+            matched = true;
+        }
+        i += 1;
+    }
+
+    match c {
+        Some(c) if c != b'/' => Some((i + 1, matched != negated)),
+        _ => Some((i + 1, false)),
+    }
+}
+
+fn posix_class(name: &[u8], c: u8) -> bool {
+    match name {
+        b"alnum" => c.is_ascii_alphanumeric(),
+        b"alpha" => c.is_ascii_alphabetic(),
+        b"blank" => c == b' ' || c == b'\t',
+        b"cntrl" => c.is_ascii_control(),
+        b"digit" => c.is_ascii_digit(),
+        b"graph" => c.is_ascii_graphic(),
+        b"lower" => c.is_ascii_lowercase(),
+        b"print" => c.is_ascii_graphic() || c == b' ',
+        b"punct" => c.is_ascii_punctuation(),
+        b"space" => c.is_ascii_whitespace(),
+        b"upper" => c.is_ascii_uppercase(),
+        b"xdigit" => c.is_ascii_hexdigit(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{wildmatch, IgnorePattern};
+
+    #[test]
+    fn test_wildmatch() {
+        let m = |p: &str, t: &str| wildmatch(p.as_bytes(), t.as_bytes());
+        assert!(m("*.o", "main.o"));
+        assert!(!m("*.o", "src/main.o"));
+        assert!(m("**/foo", "foo"));
+        assert!(m("**/foo", "a/b/foo"));
+        assert!(m("a/**/b", "a/b"));
+        assert!(m("a/**/b", "a/x/y/b"));
+        assert!(m("abc/**", "abc/x/y"));
+        assert!(!m("abc/**", "abc"));
+        assert!(m("a?c", "abc"));
+        assert!(!m("a?c", "a/c"));
+        assert!(m("[a-c]x", "bx"));
+        assert!(!m("[!a-c]x", "bx"));
+        assert!(m("[[:digit:]]", "7"));
+        assert!(m("\\*", "*"));
+        assert!(!m("\\*", "a"));
+    }
+
+    #[test]
+    fn test_ignore_pattern() {
+        let parse = |line: &str, base: &str| IgnorePattern::parse(line, base, "", 1);
+        assert_eq!(parse("# comment", ""), None);
+        assert_eq!(parse("", ""), None);
+
+        let build = parse("build/", "").unwrap();
+        assert!(build.matches("build", true));
+        assert!(build.matches("src/build", true));
+        assert!(!build.matches("build", false));
+
+        let anchored = parse("/doc/*.txt", "sub").unwrap();
+        assert!(anchored.matches("sub/doc/a.txt", false));
+        assert!(!anchored.matches("doc/a.txt", false));
+        assert!(!anchored.matches("sub/x/doc/a.txt", false));
+
+        let negated = parse("!keep.log  ", "").unwrap();
+        assert!(negated.is_negated());
+        assert!(negated.matches("a/keep.log", false));
+    }
+}
@@ -0,0 +1,793 @@
+use anyhow::{bail, Context};
+use std::{
+    borrow::Cow,
+    fs::{self, Metadata},
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    path::Path,
+    rc::Rc,
+};
+
+use crate::{
+    file::{bytes_path, ignore_by_file_name, list_files, slash_path_bytes},
+    ignore::Ignore,
+    leaf::{tree_flatten, tree_write, GitTreeLeaf},
+    object::{hex_to_sha, sha1, sha_to_hex, GitObject, GitObjectType},
+    refs::ref_resolve,
+    repository::RGitRepository,
+    Result,
+};
+
+const INDEX_SIGNATURE: &[u8] = b"DIRC";
+
+/// Bits of the 16-bit entry flags.
+const FLAG_ASSUME_VALID: u16 = 0x8000;
+const FLAG_EXTENDED: u16 = 0x4000;
+const FLAG_STAGE_MASK: u16 = 0x3000;
+const FLAG_NAME_MASK: u16 = 0x0fff;
+
+/// Bits of the extended flags, only present in version 3.
+const EXT_SKIP_WORKTREE: u16 = 0x4000;
+const EXT_INTENT_TO_ADD: u16 = 0x2000;
+
+/// A single staged file, with the stat data used to tell if it changed on disk.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IndexEntry {
+    /// (seconds, nanoseconds) of the last metadata change.
+    pub ctime: (u32, u32),
+    /// (seconds, nanoseconds) of the last data change.
+    pub mtime: (u32, u32),
+    pub dev: u32,
+    pub ino: u32,
+    /// Object type (regular file, symlink or gitlink) and unix permissions, e.g. 0o100644.
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// File size, truncated to 32 bits.
+    pub size: u32,
+    pub sha: String,
+    pub assume_valid: bool,
+    /// Merge stage, 0 for normal entries and 1-3 for conflicts.
+    pub stage: u8,
+    pub skip_worktree: bool,
+    pub intent_to_add: bool,
+    /// Path relative to the work tree, bytes that need not be UTF-8.
+    pub path: Vec<u8>,
+}
+
+impl IndexEntry {
+    /// Builds stage 0 entry for a file with given blob and stat data.
+    pub fn from_metadata(path: Vec<u8>, sha: String, mode: u32, metadata: &Metadata) -> Self {
+        Self {
+            ctime: (metadata.ctime() as u32, metadata.ctime_nsec() as u32),
+            mtime: (metadata.mtime() as u32, metadata.mtime_nsec() as u32),
+            dev: metadata.dev() as u32,
+            ino: metadata.ino() as u32,
+            mode,
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            size: metadata.size() as u32,
+            sha,
+            path,
+            ..Default::default()
+        }
+    }
+
+    /// Tells if stat data still matches the file, so its content does not have to be hashed again.
+    pub fn stat_matches(&self, metadata: &Metadata) -> bool {
+        self.mtime == (metadata.mtime() as u32, metadata.mtime_nsec() as u32)
+            && self.ctime == (metadata.ctime() as u32, metadata.ctime_nsec() as u32)
+            && self.ino == metadata.ino() as u32
+            && self.size == metadata.size() as u32
+            && (self.mode & 0o170000) == file_mode(metadata, true) & 0o170000
+    }
+
+    /// Path for display, bytes that are not UTF-8 are replaced.
+    pub fn path_str(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.path)
+    }
+
+    fn is_extended(&self) -> bool {
+        self.skip_worktree || self.intent_to_add
+    }
+
+    fn flags(&self) -> u16 {
+        let mut flags = (self.path.len() as u16).min(FLAG_NAME_MASK);
+        flags |= ((self.stage as u16) << 12) & FLAG_STAGE_MASK;
+        if self.assume_valid {
+            flags |= FLAG_ASSUME_VALID;
+        }
+        if self.is_extended() {
+            flags |= FLAG_EXTENDED;
+        }
+        flags
+    }
+}
+
+/// The staging area, stored in .git/index in DIRC format version 2 or 3:
+/// header, entries sorted by path and stage, extensions and a SHA-1 checksum.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Index {
+    /// Version the index was read with. Written index is version 3 if any entry
+    /// needs extended flags, 2 otherwise.
+    pub version: u32,
+    pub entries: Vec<IndexEntry>,
+}
+
+impl Default for Index {
+    fn default() -> Self {
+        Self {
+            version: 2,
+            entries: vec![],
+        }
+    }
+}
+
+impl Index {
+    /// Reads .git/index, a missing file is an empty index.
+    pub fn read(repo: &RGitRepository) -> Result<Self> {
+        let path = repo.repo_path(&["index"]);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Self::parse(&fs::read(path)?)
+    }
+
+    /// Writes .git/index through index.lock, so readers never see half written file.
+    pub fn write(&self, repo: &RGitRepository) -> Result<()> {
+        let lock = repo.repo_path(&["index.lock"]);
+        fs::write(&lock, self.serialize())?;
+        fs::rename(lock, repo.repo_path(&["index"]))?;
+        Ok(())
+    }
+
+    pub fn parse(raw: &[u8]) -> Result<Self> {
+        if raw.len() < 12 + 20 || &raw[0..4] != INDEX_SIGNATURE {
+            bail!("index file is corrupt: bad signature");
+        }
+        let (content, checksum) = raw.split_at(raw.len() - 20);
+        if sha1(content) != checksum {
+            bail!("index file is corrupt: bad checksum");
+        }
+
+        let version = be_u32(&raw[4..8]);
+        if version != 2 && version != 3 {
+            bail!("unsupported index version {}", version);
+        }
+        let count = be_u32(&raw[8..12]) as usize;
+
+        let mut entries = Vec::with_capacity(count);
+        let mut pos = 12;
+        for _ in 0..count {
+            let start = pos;
+            let field = |i: usize| -> Result<u32> {
+                let at = start + i * 4;
+                Ok(be_u32(
+                    content
+                        .get(at..at + 4)
+                        .context("index entry is truncated")?,
+                ))
+            };
+            let sha = sha_to_hex(
+                content
+                    .get(start + 40..start + 60)
+                    .context("index entry is truncated")?,
+            );
+            let flags = be_u16(
+                content
+                    .get(start + 60..start + 62)
+                    .context("index entry is truncated")?,
+            );
+            pos = start + 62;
+
+            let mut extended = 0;
+            if flags & FLAG_EXTENDED != 0 {
+                if version < 3 {
+                    bail!("extended flags in version {} index", version);
+                }
+                extended = be_u16(content.get(pos..pos + 2).context("truncated flags")?);
+                pos += 2;
+            }
+
+            let name_end = content[pos..]
+                .iter()
+                .position(|&b| b == 0)
+                .map(|i| i + pos)
+                .context("index entry path is not terminated")?;
+            let path = content[pos..name_end].to_vec();
+            // entries are padded with 1-8 NULs to a multiple of 8 bytes.
+            pos = start + (name_end - start + 8) / 8 * 8;
+
+            entries.push(IndexEntry {
+                ctime: (field(0)?, field(1)?),
+                mtime: (field(2)?, field(3)?),
+                dev: field(4)?,
+                ino: field(5)?,
+                mode: field(6)?,
+                uid: field(7)?,
+                gid: field(8)?,
+                size: field(9)?,
+                sha,
+                assume_valid: flags & FLAG_ASSUME_VALID != 0,
+                stage: ((flags & FLAG_STAGE_MASK) >> 12) as u8,
+                skip_worktree: extended & EXT_SKIP_WORKTREE != 0,
+                intent_to_add: extended & EXT_INTENT_TO_ADD != 0,
+                path,
+            });
+        }
+
+        // extensions: 4 bytes signature, 4 bytes size and data. Optional ones
+        // start with an uppercase letter and are caches git rebuilds, so they
+        // are dropped; anything else changes the meaning of the entries.
+        while pos < content.len() {
+            let signature = content
+                .get(pos..pos + 4)
+                .context("index extension is truncated")?;
+            let size = be_u32(
+                content
+                    .get(pos + 4..pos + 8)
+                    .context("index extension is truncated")?,
+            ) as usize;
+            if !signature[0].is_ascii_uppercase() {
+                bail!(
+                    "unsupported index extension {}",
+                    String::from_utf8_lossy(signature)
+                );
+            }
+            pos += 8 + size;
+        }
+
+        Ok(Self { version, entries })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let version: u32 = match self.entries.iter().any(IndexEntry::is_extended) {
+            true => 3,
+            false => 2,
+        };
+
+        let mut entries = self.entries.iter().collect::<Vec<_>>();
+        entries.sort_by(|a, b| (&a.path, a.stage).cmp(&(&b.path, b.stage)));
+
+        let mut ret = INDEX_SIGNATURE.to_vec();
+        ret.extend_from_slice(&version.to_be_bytes());
+        ret.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+
+        for e in entries {
+            let start = ret.len();
+            for field in [
+                e.ctime.0, e.ctime.1, e.mtime.0, e.mtime.1, e.dev, e.ino, e.mode, e.uid, e.gid,
+                e.size,
+            ] {
+                ret.extend_from_slice(&field.to_be_bytes());
+            }
+            ret.extend(hex_to_sha(&e.sha).expect("index entry with invalid sha"));
+            ret.extend_from_slice(&e.flags().to_be_bytes());
+            if e.is_extended() {
+                let mut extended = 0u16;
+                if e.skip_worktree {
+                    extended |= EXT_SKIP_WORKTREE;
+                }
+                if e.intent_to_add {
+                    extended |= EXT_INTENT_TO_ADD;
+                }
+                ret.extend_from_slice(&extended.to_be_bytes());
+            }
+            ret.extend_from_slice(&e.path);
+            let len = ret.len() - start;
+            ret.resize(start + (len + 8) / 8 * 8, 0);
+        }
+
+        let checksum = sha1(&ret);
+        ret.extend(checksum);
+        ret
+    }
+
+    /// Returns stage 0 entry for the path.
+    pub fn entry(&self, path: &[u8]) -> Option<&IndexEntry> {
+        self.entries.iter().find(|e| e.path == path && e.stage == 0)
+    }
+
+    /// Writes tree objects of the staged content, returns SHA of the root tree.
+    /// Entries added with intent to add are left out.
+    pub fn write_tree(&self, repo: &RGitRepository) -> Result<String> {
+        if let Some(e) = self.entries.iter().find(|e| e.stage > 0) {
+            bail!("cannot write tree, {} is unmerged", e.path_str());
+        }
+        let mut entries: Vec<&IndexEntry> =
+            self.entries.iter().filter(|e| !e.intent_to_add).collect();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        write_tree_dir(repo, &entries, b"")
+    }
+
+    /// Hashes file at fs_path into a blob and stages it as path, replacing
+    /// conflict stages and entries it collides with as file or directory.
+    /// Files whose stat data did not change are not hashed again.
+    pub fn add_file(
+        &mut self,
+        repo: &RGitRepository,
+        fs_path: &Path,
+        path: Vec<u8>,
+        filemode: bool,
+    ) -> Result<()> {
+        let metadata = fs::symlink_metadata(fs_path)?;
+        let existing = self.entry(&path).cloned();
+        if let Some(e) = &existing {
+            if e.stat_matches(&metadata) {
+                return Ok(());
+            }
+        }
+
+        let mut mode = file_mode(&metadata, filemode);
+        if let Some(e) = &existing {
+            // without core.filemode the executable bit on disk is not trusted.
+            if !filemode && mode == 0o100644 && e.mode & 0o170000 == 0o100000 {
+                mode = e.mode;
+            }
+        }
+        let sha = GitObject::new(repo, Some(file_read(fs_path)?), Some(GitObjectType::Blob))?
+            .object_write(None)?;
+
+        self.entries
+            .retain(|e| !path_matches(&e.path, &path) && !path_matches(&path, &e.path));
+        self.entries
+            .push(IndexEntry::from_metadata(path, sha, mode, &metadata));
+        Ok(())
+    }
+
+    /// Removes entries for path, and everything under it if it is a directory.
+    /// Returns removed entries.
+    pub fn remove(&mut self, path: &str) -> Vec<IndexEntry> {
+        let (removed, kept) = self
+            .entries
+            .drain(..)
+            .partition(|e| path_matches(&e.path, path.as_bytes()));
+        self.entries = kept;
+        removed
+    }
+}
+
+/// Writes tree of entries under prefix, entries must be sorted by path so
+/// each subdirectory is a contiguous run.
+fn write_tree_dir(repo: &RGitRepository, entries: &[&IndexEntry], prefix: &[u8]) -> Result<String> {
+    let mut leaves = vec![];
+    let mut i = 0;
+    while i < entries.len() {
+        let name = &entries[i].path[prefix.len()..];
+        match name.iter().position(|&b| b == b'/') {
+            None => {
+                let e = entries[i];
+                leaves.push(GitTreeLeaf::new(
+                    format!("{:o}", e.mode),
+                    name.to_vec(),
+                    e.sha.clone(),
+                ));
+                i += 1;
+            }
+            Some(slash) => {
+                let dir_prefix = &entries[i].path[..prefix.len() + slash + 1];
+                let len = entries[i..]
+                    .iter()
+                    .take_while(|e| e.path.starts_with(dir_prefix))
+                    .count();
+                let sha = write_tree_dir(repo, &entries[i..i + len], dir_prefix)?;
+                leaves.push(GitTreeLeaf::new(
+                    "40000".into(),
+                    name[..slash].to_vec(),
+                    sha,
+                ));
+                i += len;
+            }
+        }
+    }
+    tree_write(repo, leaves)
+}
+
+/// Removes leading directories of a removed file left empty, like git does.
+pub(crate) fn dir_prune(repo: &RGitRepository, path: &Path) {
+    let mut dir = path.parent();
+    while let Some(d) = dir {
+        if d == repo.work_tree() || fs::remove_dir(d).is_err() {
+            break;
+        }
+        dir = d.parent();
+    }
+}
+
+/// Tells if entry path equals pathspec or is inside pathspec directory, "" matches everything.
+pub(crate) fn path_matches(entry: &[u8], pathspec: &[u8]) -> bool {
+    pathspec.is_empty()
+        || entry == pathspec
+        || (entry.starts_with(pathspec) && entry[pathspec.len()] == b'/')
+}
+
+/// Index mode of a file: symlink, executable or regular file.
+pub fn file_mode(metadata: &Metadata, filemode: bool) -> u32 {
+    if metadata.file_type().is_symlink() {
+        0o120000
+    } else if filemode && metadata.mode() & 0o111 != 0 {
+        0o100755
+    } else {
+        0o100644
+    }
+}
+
+/// Reads blob content of a file, for symlinks it is the link target.
+pub fn file_read(path: &Path) -> Result<Vec<u8>> {
+    let metadata = fs::symlink_metadata(path)?;
+    if metadata.file_type().is_symlink() {
+        return Ok(fs::read_link(path)?.as_os_str().as_bytes().to_vec());
+    }
+    Ok(fs::read(path)?)
+}
+
+/// Tells if core.filemode allows trusting the executable bit.
+pub fn filemode(repo: &RGitRepository) -> bool {
+    repo.config_get("core", "filemode")
+        .map_or(true, |v| v == "true")
+}
+
+/// Stages paths given relative to current directory. Directories are added
+/// recursively, skipping ignored files, and paths gone from disk are removed.
+pub fn add(repo: &RGitRepository, paths: &[String]) -> Result<()> {
+    let mut index = Index::read(repo)?;
+    let filemode = filemode(repo);
+    let ignore = Rc::new(Ignore::for_repo(repo)?);
+    let skip = vec![
+        ignore_by_file_name(".git".into()),
+        ignore.clone().skip_predicate(),
+    ];
+    let work_tree = repo.work_tree().to_path_buf();
+
+    for path in paths {
+        let fs_path = Path::new(path);
+        let relative = repo.relative_path(fs_path)?;
+
+        let metadata = match fs::symlink_metadata(fs_path) {
+            Ok(metadata) => metadata,
+            Err(_) => {
+                if index.remove(&relative).is_empty() {
+                    bail!("pathspec '{}' did not match any files", path);
+                }
+                continue;
+            }
+        };
+
+        let tracked: Vec<Vec<u8>> = index
+            .entries
+            .iter()
+            .filter(|e| e.stage == 0 && path_matches(&e.path, relative.as_bytes()))
+            .map(|e| e.path.clone())
+            .collect();
+        if tracked.is_empty()
+            && !relative.is_empty()
+            && ignore.is_ignored(&relative, metadata.is_dir())?
+        {
+            bail!("path '{}' is ignored by one of your .gitignore files", path);
+        }
+
+        if !metadata.is_dir() {
+            index.add_file(repo, fs_path, relative.into_bytes(), filemode)?;
+            continue;
+        }
+
+        let dir = work_tree.join(&relative);
+        for file in list_files(&dir, &skip)? {
+            let file_relative = slash_path_bytes(file.strip_prefix(&work_tree)?);
+            index.add_file(repo, &file, file_relative, filemode)?;
+        }
+        // tracked files are updated even if ignored, and deleted ones removed.
+        for path in tracked {
+            let fs_path = work_tree.join(bytes_path(&path));
+            match fs::symlink_metadata(&fs_path) {
+                Ok(metadata) if !metadata.is_dir() => {
+                    index.add_file(repo, &fs_path, path, filemode)?
+                }
+                _ => index.entries.retain(|e| e.path != path),
+            }
+        }
+    }
+
+    index.write(repo)
+}
+
+/// Removes paths given relative to current directory from the index and, unless
+/// cached, from the work tree. Unless forced, files whose content would be lost
+/// are kept: changes staged in the index, or not added yet when not cached.
+/// Returns removed paths.
+pub fn rm(
+    repo: &RGitRepository,
+    paths: &[String],
+    cached: bool,
+    recursive: bool,
+    force: bool,
+) -> Result<Vec<Vec<u8>>> {
+    let mut index = Index::read(repo)?;
+
+    let mut removed = vec![];
+    for path in paths {
+        let relative = repo.relative_path(Path::new(path))?;
+        let entries = index.remove(&relative);
+        if entries.is_empty() {
+            bail!("pathspec '{}' did not match any files", path);
+        }
+        if !recursive && entries.iter().any(|e| e.path != relative.as_bytes()) {
+            bail!("not removing '{}' recursively without -r", path);
+        }
+        removed.extend(entries);
+    }
+
+    if !force {
+        let head_tree = match ref_resolve(repo, "HEAD")? {
+            Some(sha) => tree_flatten(repo, &repo.object_find(&sha, Some("tree".into()), None)?)?,
+            None => Default::default(),
+        };
+        for e in &removed {
+            let fs_path = repo.work_tree().join(bytes_path(&e.path));
+            // a file already gone from the work tree loses nothing.
+            if fs::symlink_metadata(&fs_path).is_err() {
+                continue;
+            }
+            let local_changes =
+                GitObject::new(repo, Some(file_read(&fs_path)?), Some(GitObjectType::Blob))?
+                    .object_write(Some(false))?
+                    != e.sha;
+            let staged_changes = match head_tree.get(&e.path) {
+                Some(leaf) => leaf.mode_bits()? != e.mode || leaf.sha != e.sha,
+                None => true,
+            };
+            match (local_changes, staged_changes) {
+                // an intent-to-add entry holds no content to lose.
+                (true, true) if !cached || !e.intent_to_add => bail!(
+                    "'{}' has staged content different from both the file and the HEAD, use -f to force removal",
+                    e.path_str()
+                ),
+                (false, true) if !cached => bail!(
+                    "'{}' has changes staged in the index, use --cached to keep the file or -f to force removal",
+                    e.path_str()
+                ),
+                (true, false) if !cached => bail!(
+                    "'{}' has local modifications, use --cached to keep the file or -f to force removal",
+                    e.path_str()
+                ),
+                _ => {}
+            }
+        }
+    }
+
+    if !cached {
+        for e in &removed {
+            let fs_path = repo.work_tree().join(bytes_path(&e.path));
+            if fs::symlink_metadata(&fs_path).is_ok() {
+                fs::remove_file(&fs_path)?;
+            }
+            dir_prune(repo, &fs_path);
+        }
+    }
+
+    index.write(repo)?;
+    Ok(removed.into_iter().map(|e| e.path).collect())
+}
+
+fn be_u32(b: &[u8]) -> u32 {
+    u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+}
+
+fn be_u16(b: &[u8]) -> u16 {
+    u16::from_be_bytes([b[0], b[1]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{add, rm, Index, IndexEntry};
+    use crate::{
+        file::bytes_path,
+        leaf::tree_flatten,
+        patch::blob_sha,
+        refs::ref_create,
+        status::Status,
+        testing::{entry, TempRepo},
+    };
+    use std::fs;
+
+    #[test]
+    fn test_index_round_trip() {
+        let entry = IndexEntry {
+            ctime: (1657000000, 123),
+            mtime: (1657000001, 456),
+            dev: 2049,
+            ino: 1234,
+            mode: 0o100644,
+            uid: 1000,
+            gid: 1000,
+            size: 6,
+            sha: "ce013625030ba8dba906f756967f9e9ca394464a".into(),
+            path: "src/main.rs".into(),
+            ..Default::default()
+        };
+        let index = Index {
+            version: 3,
+            entries: vec![
+                IndexEntry {
+                    path: "README".into(),
+                    skip_worktree: true,
+                    ..entry.clone()
+                },
+                IndexEntry {
+                    // conflicting entries are sorted by stage.
+                    stage: 2,
+                    ..entry.clone()
+                },
+                IndexEntry {
+                    stage: 1,
+                    ..entry.clone()
+                },
+            ],
+        };
+
+        let raw = index.serialize();
+        assert_eq!(&raw[4..8], &3u32.to_be_bytes());
+        let parsed = Index::parse(&raw).unwrap();
+        assert_eq!(parsed.entries[0], index.entries[0]);
+        assert_eq!(parsed.entries[1], index.entries[2]);
+        assert_eq!(parsed.entries[2], index.entries[1]);
+
+        let mut corrupted = raw.clone();
+        corrupted[20] ^= 1;
+        assert!(Index::parse(&corrupted).is_err());
+    }
+
+    #[test]
+    fn test_write_tree() {
+        let repo = TempRepo::new("write-tree");
+        let entry = |path: &str, mode: u32| IndexEntry {
+            mode,
+            ..entry(path, "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391", 0)
+        };
+        let index = Index {
+            version: 2,
+            entries: vec![
+                entry("a-b", 0o100644),
+                entry("a/b/x", 0o100644),
+                entry("a/l", 0o120000),
+                entry("run", 0o100755),
+            ],
+        };
+        assert_eq!(
+            index.write_tree(&repo).unwrap(),
+            "bc4eb10cf61121439f5925583effe29848dcb5ed"
+        );
+    }
+
+    #[test]
+    fn test_add_rm() {
+        let repo = TempRepo::new("add-rm");
+        let path = |path: &str| vec![repo.path(path)];
+        let paths = || -> Vec<String> {
+            let index = Index::read(&repo).unwrap();
+            index
+                .entries
+                .into_iter()
+                .map(|e| String::from_utf8(e.path).unwrap())
+                .collect()
+        };
+        for file in ["a.txt", "d/x", "d/y"] {
+            repo.write(file, file);
+        }
+
+        add(&repo, &path("a.txt")).unwrap();
+        assert_eq!(paths(), ["a.txt"]);
+        // unchanged stat data skips hashing, so a wrong SHA stays.
+        let mut index = Index::read(&repo).unwrap();
+        index.entries[0].sha = blob_sha(b"other");
+        index.write(&repo).unwrap();
+        add(&repo, &path("a.txt")).unwrap();
+        assert_eq!(
+            Index::read(&repo).unwrap().entries[0].sha,
+            blob_sha(b"other")
+        );
+        let mut index = Index::read(&repo).unwrap();
+        index.entries[0].mtime = (0, 0);
+        index.write(&repo).unwrap();
+        add(&repo, &path("a.txt")).unwrap();
+        assert_eq!(
+            Index::read(&repo).unwrap().entries[0].sha,
+            blob_sha(b"a.txt")
+        );
+
+        add(&repo, &path("d")).unwrap();
+        assert_eq!(paths(), ["a.txt", "d/x", "d/y"]);
+        fs::remove_file(repo.dir().join("d/y")).unwrap();
+        add(&repo, &path("d")).unwrap();
+        assert_eq!(paths(), ["a.txt", "d/x"]);
+        assert!(add(&repo, &path("nothing")).is_err());
+
+        assert_eq!(
+            rm(&repo, &path("a.txt"), true, false, false).unwrap(),
+            [b"a.txt"]
+        );
+        assert!(repo.dir().join("a.txt").exists());
+        assert_eq!(paths(), ["d/x"]);
+        assert!(rm(&repo, &path("d"), false, false, false).is_err());
+        // d/x is only staged until committed.
+        assert!(rm(&repo, &path("d"), false, true, false).is_err());
+        let tree = Index::read(&repo).unwrap().write_tree(&repo).unwrap();
+        let commit = repo.commit(&tree).write();
+        ref_create(&repo, "refs/heads/master", &commit).unwrap();
+        assert_eq!(rm(&repo, &path("d"), false, true, false).unwrap(), [b"d/x"]);
+        assert!(!repo.dir().join("d").exists());
+        assert!(rm(&repo, &path("nothing"), false, false, false).is_err());
+        add(&repo, &path("a.txt")).unwrap();
+        repo.write("a.txt", "changed");
+        assert!(rm(&repo, &path("a.txt"), false, false, false).is_err());
+        assert_eq!(
+            rm(&repo, &path("a.txt"), false, false, true).unwrap(),
+            [b"a.txt"]
+        );
+        assert!(!repo.dir().join("a.txt").exists());
+    }
+
+    #[test]
+    fn test_rm_staged() {
+        let repo = TempRepo::new("rm-staged");
+        let path = |path: &str| vec![repo.path(path)];
+        let commit = repo
+            .commit(&repo.tree(&[("a.txt", "a"), ("b.txt", "b")]))
+            .write();
+        ref_create(&repo, "refs/heads/master", &commit).unwrap();
+        repo.write("a.txt", "a");
+        repo.write("b.txt", "b");
+        add(&repo, &path("a.txt")).unwrap();
+        add(&repo, &path("b.txt")).unwrap();
+
+        // index differs from HEAD only.
+        repo.write("a.txt", "staged");
+        add(&repo, &path("a.txt")).unwrap();
+        let err = rm(&repo, &path("a.txt"), false, false, false).unwrap_err();
+        assert!(err.to_string().contains("has changes staged in the index"));
+        // index differs from both HEAD and the file, --cached would lose it.
+        repo.write("a.txt", "local");
+        let err = rm(&repo, &path("a.txt"), true, false, false).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("different from both the file and the HEAD"));
+        assert_eq!(
+            rm(&repo, &path("a.txt"), true, false, true).unwrap(),
+            [b"a.txt"]
+        );
+        assert_eq!(repo.read("a.txt").unwrap(), "local");
+        // work tree differs from the index only, kept by --cached.
+        repo.write("b.txt", "local");
+        assert!(rm(&repo, &path("b.txt"), false, false, false).is_err());
+        assert_eq!(
+            rm(&repo, &path("b.txt"), true, false, false).unwrap(),
+            [b"b.txt"]
+        );
+        assert!(Index::read(&repo).unwrap().entries.is_empty());
+    }
+
+    #[test]
+    fn test_latin1_path() {
+        let repo = TempRepo::new("latin1-path");
+        let name = b"d/caf\xe9";
+        repo.write("d/x", "x");
+        fs::write(repo.dir().join(bytes_path(name)), "caf").unwrap();
+
+        add(&repo, &[repo.path("d")]).unwrap();
+        let index = Index::read(&repo).unwrap();
+        assert_eq!(index.entries[0].path, name);
+        assert_eq!(Index::parse(&index.serialize()).unwrap(), index);
+        let tree = index.write_tree(&repo).unwrap();
+        let leaves = tree_flatten(&repo, &tree).unwrap();
+        assert_eq!(leaves[name.as_slice()].sha, blob_sha(b"caf"));
+        let commit = repo.commit(&tree).write();
+        ref_create(&repo, "refs/heads/master", &commit).unwrap();
+        let status = Status::read(&repo).unwrap();
+        assert!(status.changes.is_empty() && status.untracked.is_empty());
+
+        assert_eq!(
+            rm(&repo, &[repo.path("d")], false, true, false).unwrap(),
+            [name.as_slice(), b"d/x"]
+        );
+        assert!(!repo.dir().join("d").exists());
+    }
+}
@@ -1,46 +1,101 @@
 use crate::{
-    object::{GitObject, GitObjectType},
+    object::{hex_to_sha, sha_to_hex, GitObject, GitObjectType},
+    repository::RGitRepository,
     Result,
 };
-use anyhow::{bail, Context, Ok};
+use anyhow::{bail, Context};
+use indexmap::IndexMap;
+use std::{borrow::Cow, cmp::Ordering, str::from_utf8};
 
-#[derive(Debug, Default)]
-struct GitTreeLeaf {
-    mode: String,
-    path: String,
-    sha: String,
+/// A single entry of a tree object: file mode, path relative to the tree
+/// and the SHA of the blob or subtree it points to. Paths are bytes, git does
+/// not require them to be UTF-8.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct GitTreeLeaf {
+    pub mode: String,
+    pub path: Vec<u8>,
+    pub sha: String,
 }
 
 impl GitTreeLeaf {
-    fn new(mode: String, path: String, sha: String) -> Self {
+    pub fn new(mode: String, path: Vec<u8>, sha: String) -> Self {
         Self { mode, path, sha }
     }
 
-    fn tree_parse_one(raw: &str, start: Option<usize>) -> Result<(usize, Self)> {
+    /// Path for display, bytes that are not UTF-8 are replaced.
+    pub fn path_str(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.path)
+    }
+
+    /// Tells if leaf points to a subtree. Git writes directory mode as "40000",
+    /// without the leading zero.
+    pub fn is_tree(&self) -> bool {
+        self.mode == "40000" || self.mode == "040000"
+    }
+
+    /// Mode as a number, like 0o100644.
+    pub fn mode_bits(&self) -> Result<u32> {
+        u32::from_str_radix(&self.mode, 8)
+            .with_context(|| format!("bad mode {} of {}", self.mode, self.path_str()))
+    }
+
+    /// Parses one entry starting at `start`, returns position of the next entry and the leaf.
+    ///
+    /// [mode] space [path] 0x00 [sha-1 in binary]
+    fn tree_parse_one(raw: &[u8], start: Option<usize>) -> Result<(usize, Self)> {
         let start = start.unwrap_or(0);
         // find the space terminator of the mode.
-        let x = raw.find(" ").context("space not found")?;
-        assert!((x - start) == 5 || (x - start) == 6);
+        let x = raw[start..]
+            .iter()
+            .position(|&b| b == b' ')
+            .map(|i| i + start)
+            .context("space not found")?;
+        if x - start != 5 && x - start != 6 {
+            bail!("malformed tree entry: bad mode length {}", x - start);
+        }
 
         // read the mode.
-        let mode = &raw[start..x];
+        let mode = from_utf8(&raw[start..x]).context("mode is not valid utf-8")?;
 
         // find the NULL terminator of the path;
-        let y = mode[x..].find(char::from(0)).context("0x00 not found")?;
+        let y = raw[x..]
+            .iter()
+            .position(|&b| b == 0)
+            .map(|i| i + x)
+            .context("0x00 not found")?;
         // and read the path.
-        let path = &raw[x + 1..y];
+        let path = raw[x + 1..y].to_vec();
 
         // read the SHA and convert to an hex string
-        let sha = format!(
-            "{:x}",
-            isize::from_be_bytes(raw[y + 1..y + 21].as_bytes().try_into()?)
-        );
+        if raw.len() < y + 21 {
+            bail!(
+                "malformed tree entry: truncated sha for {}",
+                String::from_utf8_lossy(&path)
+            );
+        }
+        let sha = sha_to_hex(&raw[y + 1..y + 21]);
+
+        Ok((y + 21, Self::new(mode.into(), path, sha)))
+    }
 
-        Ok((y + 21, Self::new(mode.into(), path.into(), sha)))
+    /// Key used by git to order tree entries: directories sort as if their
+    /// name had a trailing slash.
+    fn sort_key(&self) -> Vec<u8> {
+        let mut key = self.path.clone();
+        if self.is_tree() {
+            key.push(b'/');
+        }
+        key
     }
 }
 
-fn tree_parse(raw: &str) -> Result<Vec<GitTreeLeaf>> {
+/// Compares leaves the same way git does when writing a tree.
+pub fn tree_leaf_cmp(a: &GitTreeLeaf, b: &GitTreeLeaf) -> Ordering {
+    a.sort_key().cmp(&b.sort_key())
+}
+
+/// Parses whole tree object into its leaves.
+pub fn tree_parse(raw: &[u8]) -> Result<Vec<GitTreeLeaf>> {
     let mut pos: usize = 0;
     let max = raw.len();
     let mut ret = vec![];
@@ -53,8 +108,106 @@ fn tree_parse(raw: &str) -> Result<Vec<GitTreeLeaf>> {
     Ok(ret)
 }
 
-fn tree_serializer(obj: GitObject) {
-    assert!(obj.object_type.unwrap() == GitObjectType::Tree);
+/// Serializes leaves back to tree object format, sorted in git's order.
+pub fn tree_serialize(leaves: &[GitTreeLeaf]) -> Result<Vec<u8>> {
+    let mut leaves = leaves.to_vec();
+    leaves.sort_by(tree_leaf_cmp);
+
+    let mut ret = vec![];
+    for leaf in leaves {
+        ret.extend_from_slice(leaf.mode.as_bytes());
+        ret.push(b' ');
+        ret.extend_from_slice(&leaf.path);
+        ret.push(0);
+        ret.extend_from_slice(&hex_to_sha(&leaf.sha)?);
+    }
+    Ok(ret)
+}
 
-    let ret = 
+/// Writes tree object with given leaves, returns its SHA.
+pub fn tree_write(repo: &RGitRepository, leaves: Vec<GitTreeLeaf>) -> Result<String> {
+    let mut tree = GitObject::new(repo, None, Some(GitObjectType::Tree))?;
+    tree.tree = Some(leaves);
+    tree.object_write(None)
+}
+
+/// Reads tree recursively into its blobs, symlinks and gitlinks keyed by full
+/// path. Returned leaves carry the full path too.
+pub fn tree_flatten(repo: &RGitRepository, sha: &str) -> Result<IndexMap<Vec<u8>, GitTreeLeaf>> {
+    let mut ret = IndexMap::new();
+    let mut stack = vec![(sha.to_string(), vec![])];
+    while let Some((sha, prefix)) = stack.pop() {
+        let tree = repo
+            .object_read(&sha)?
+            .tree
+            .with_context(|| format!("{} is not a tree", sha))?;
+        for leaf in tree {
+            let path = [prefix.as_slice(), &leaf.path].concat();
+            if leaf.is_tree() {
+                stack.push((leaf.sha, [path.as_slice(), b"/"].concat()));
+            } else {
+                ret.insert(path.clone(), GitTreeLeaf { path, ..leaf });
+            }
+        }
+    }
+    ret.sort_keys();
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{tree_parse, tree_serialize, GitTreeLeaf};
+    use crate::object::hex_to_sha;
+
+    fn raw_entry(mode: &str, path: &[u8], sha: &str) -> Vec<u8> {
+        let mut ret = format!("{} ", mode).into_bytes();
+        ret.extend_from_slice(path);
+        ret.push(0);
+        ret.extend(hex_to_sha(sha).unwrap());
+        ret
+    }
+
+    #[test]
+    fn test_tree_parse_and_serialize() {
+        let blob = "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391";
+        let tree = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+        // "foo" directory goes after "foo.txt" because it is compared as "foo/".
+        let raw = [
+            raw_entry("100644", b"foo.txt", blob),
+            raw_entry("40000", b"foo", tree),
+            raw_entry("100755", b"run", blob),
+        ]
+        .concat();
+
+        let leaves = tree_parse(&raw).unwrap();
+        assert_eq!(
+            leaves,
+            vec![
+                GitTreeLeaf::new("100644".into(), "foo.txt".into(), blob.into()),
+                GitTreeLeaf::new("40000".into(), "foo".into(), tree.into()),
+                GitTreeLeaf::new("100755".into(), "run".into(), blob.into()),
+            ]
+        );
+
+        let mut shuffled = leaves.clone();
+        shuffled.reverse();
+        assert_eq!(tree_serialize(&shuffled).unwrap(), raw);
+    }
+
+    #[test]
+    fn test_tree_parse_latin1_path() {
+        let blob = "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391";
+        let raw = raw_entry("100644", b"caf\xe9", blob);
+        let leaves = tree_parse(&raw).unwrap();
+        assert_eq!(leaves[0].path, b"caf\xe9");
+        assert_eq!(leaves[0].path_str(), "caf\u{fffd}");
+        assert_eq!(tree_serialize(&leaves).unwrap(), raw);
+    }
+
+    #[test]
+    fn test_tree_parse_truncated() {
+        let mut raw = raw_entry("100644", b"a", "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391");
+        raw.truncate(raw.len() - 1);
+        assert!(tree_parse(&raw).is_err());
+    }
 }
@@ -4,11 +4,30 @@ use clap::Parser;
 #[macro_use]
 extern crate log;
 
+mod checkout;
 mod cli;
+mod color;
+mod commit;
+mod diff;
 mod file;
+mod graph;
+mod history;
+mod ignore;
+mod index;
 mod leaf;
 mod object;
+mod pack;
+mod patch;
+mod pickaxe;
+mod refs;
+mod repack;
 mod repository;
+mod revparse;
+mod revwalk;
+mod status;
+#[cfg(test)]
+mod testing;
+mod treediff;
 
 pub type Result<T> = std::result::Result<T, anyhow::Error>;
 
@@ -9,6 +9,7 @@ use std::fs;
 use std::io::Write;
 use std::str::{from_utf8, FromStr};
 
+use crate::leaf::{tree_parse, tree_serialize, GitTreeLeaf};
 use crate::repository::RGitRepository;
 use crate::Result;
 
@@ -63,17 +64,22 @@ impl GitObjectType {
 /// then null (0x00) (the null byte), then the contents of the object.
 pub struct GitObject<'a> {
     repo: &'a RGitRepository,
-    data: Option<String>,
+    data: Option<Vec<u8>>,
     pub object_type: Option<GitObjectType>,
 
     /// object specific fields.
-    pub kvlm: Option<IndexMap<String, Vec<String>>>,
+    kvlm: Option<Kvlm>,
+    pub tree: Option<Vec<GitTreeLeaf>>,
 }
 
+/// Headers and message of a commit or tag, values are bytes as stored, in no
+/// particular encoding. The message has the empty key.
+pub(crate) type Kvlm = IndexMap<String, Vec<Vec<u8>>>;
+
 impl<'a> GitObject<'a> {
     pub fn new(
         repo: &'a RGitRepository,
-        data: Option<String>,
+        data: Option<Vec<u8>>,
         object_type: Option<GitObjectType>,
     ) -> Result<Self> {
         let mut go = Self {
@@ -81,63 +87,61 @@ impl<'a> GitObject<'a> {
             data: data.clone(),
             object_type,
             kvlm: None,
+            tree: None,
         };
 
         if let Some(data) = data {
-            go.deserialize(data);
+            go.deserialize(data)?;
         }
         Ok(go)
     }
 
-    pub fn object_read(raw: String, repo: &'a RGitRepository) -> Result<Self> {
-        // read objet type
-
-        let x = raw.find(" ").context("space not found")?;
-        let fmt = &raw[0..x];
-
-        // read and validate object size
-        let y = raw[x..].find(char::from(0)).expect("0x00 not found");
-        debug!(
-            "GitObject::object_read - x: {}, y:{}, raw: {}",
-            x,
-            y,
-            &raw[x + 1..x + y]
-        );
-        let size: usize = raw[x + 1..x + y].parse()?;
-
-        debug!("GitObject: size: {}, raw.lem: {}", raw.len(), size);
-        if size != raw.len() - y - x - 1 {
-            bail!("malformed object {}: bad length", size);
-        }
-
-        Self::new(
-            repo,
-            Some(raw[x + y + 1..].to_string()),
-            Some(GitObjectType::from_str(fmt)?),
-        )
-    }
-
-    pub fn serialize(&self) -> String {
-        match &self.object_type.as_ref().unwrap() {
-            GitObjectType::Commit => match &self.kvlm {
-                Some(kvlm) => kvlm_serialize(kvlm),
-                None => "kvlm is not set".to_string(),
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        match &self.object_type.as_ref().context("object type is None")? {
+            // objects read from bytes keep them, unless kvlm is replaced.
+            GitObjectType::Commit | GitObjectType::Tag => match (&self.data, &self.kvlm) {
+                (Some(data), _) => Ok(data.clone()),
+                (None, Some(kvlm)) => Ok(kvlm_serialize(kvlm)),
+                (None, None) => bail!("kvlm is not set"),
             },
-            GitObjectType::Tree => todo!(),
-            GitObjectType::Tag => todo!(),
-            GitObjectType::Blob => self.data.as_ref().expect("git blob has empty data").into(),
+            GitObjectType::Tree => tree_serialize(self.tree.as_ref().context("tree is not set")?),
+            GitObjectType::Blob => self.data.clone().context("git blob has empty data"),
         }
     }
 
-    pub fn deserialize(&mut self, data: String) {
+    pub fn deserialize(&mut self, data: Vec<u8>) -> Result<()> {
         match self.object_type.as_ref().unwrap() {
-            GitObjectType::Commit => {
-                self.kvlm = Some(kvlm_parse(data, None, None).expect("failed to kvlm parse"))
+            GitObjectType::Commit | GitObjectType::Tag => {
+                self.kvlm = Some(kvlm_parse(&data, None, None).context("failed to kvlm parse")?)
+            }
+            GitObjectType::Tree => {
+                self.tree = Some(tree_parse(&data).context("failed to tree parse")?)
             }
-            GitObjectType::Tree => todo!(),
-            GitObjectType::Tag => todo!(),
             GitObjectType::Blob => self.data = Some(data),
         }
+        Ok(())
+    }
+
+    /// Headers and message of a commit or tag.
+    pub fn kvlm(&self) -> Option<&Kvlm> {
+        self.kvlm.as_ref()
+    }
+
+    /// Replaces headers and message of a commit or tag, the object is then
+    /// serialized from them rather than the bytes it was read from.
+    pub fn kvlm_set(&mut self, kvlm: Kvlm) {
+        self.kvlm = Some(kvlm);
+        self.data = None;
+    }
+
+    /// SHA of the object a tag points at.
+    pub fn tag_target(&self) -> Result<String> {
+        let kvlm = self.kvlm.as_ref().context("kvlm is empty")?;
+        let object = kvlm.get("object").and_then(|o| o.first());
+        let object = object.context("tag has no object header")?;
+        Ok(from_utf8(object)
+            .context("tag object is not valid utf-8")?
+            .to_string())
     }
 
     /// Writing an object is reading it in reverse: we compute the hash, insert the header, zlib-compress
@@ -146,19 +150,20 @@ impl<'a> GitObject<'a> {
     pub fn object_write(&self, actually_write: Option<bool>) -> Result<String> {
         let actually_write = actually_write.unwrap_or(true);
 
-        let data = self.serialize();
+        let data = self.serialize()?;
         // add header
-        let result = format!(
-            "{} {}{}{}",
+        let mut result = format!(
+            "{} {}{}",
             self.object_type.as_ref().unwrap().fmt(),
             data.len(),
             char::from(0),
-            data
-        );
+        )
+        .into_bytes();
+        result.extend_from_slice(&data);
 
         // compute hash
         let mut hasher = Sha1::new();
-        hasher.input_str(&result);
+        hasher.input(&result);
         let sha = hasher.result_str();
 
         if actually_write {
@@ -168,7 +173,7 @@ impl<'a> GitObject<'a> {
                 .context("could not create path for object")?;
 
             let mut e = ZlibEncoder::new(vec![], Compression::default());
-            e.write_all(result.as_bytes())?;
+            e.write_all(&result)?;
             let compressed = e.finish()?;
 
             fs::write(path, compressed)?;
@@ -178,16 +183,71 @@ impl<'a> GitObject<'a> {
     }
 }
 
-fn kvlm_parse(
-    raw: String,
-    start: Option<usize>,
-    dct: Option<IndexMap<String, Vec<String>>>,
-) -> Result<IndexMap<String, Vec<String>>> {
+/// Splits raw loose object into its type and content, validating the header.
+pub fn object_parse(raw: &[u8]) -> Result<(GitObjectType, Vec<u8>)> {
+    // read objet type
+    let x = raw
+        .iter()
+        .position(|&b| b == b' ')
+        .context("space not found")?;
+    let fmt = from_utf8(&raw[0..x]).context("object type is not valid utf-8")?;
+
+    // read and validate object size
+    let y = raw[x..]
+        .iter()
+        .position(|&b| b == 0)
+        .context("0x00 not found")?;
+    let size = from_utf8(&raw[x + 1..x + y]).context("object size is not valid utf-8")?;
+    debug!("object_parse - x: {}, y:{}, size: {}", x, y, size);
+    let size: usize = size.parse()?;
+
+    debug!("object_parse: size: {}, raw.len: {}", size, raw.len());
+    if size != raw.len() - y - x - 1 {
+        bail!("malformed object {}: bad length", size);
+    }
+
+    Ok((GitObjectType::from_str(fmt)?, raw[x + y + 1..].to_vec()))
+}
+
+/// Computes binary SHA-1 of data.
+pub fn sha1(data: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha1::new();
+    hasher.input(data);
+    let mut ret = vec![0; 20];
+    hasher.result(&mut ret);
+    ret
+}
+
+/// Formats binary SHA-1 as lowercase hex string.
+pub fn sha_to_hex(sha: &[u8]) -> String {
+    sha.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Converts hex string SHA-1 into its 20 bytes binary form.
+pub fn hex_to_sha(hex: &str) -> Result<Vec<u8>> {
+    if hex.len() != 40 || !hex.is_ascii() {
+        bail!("invalid sha {}: expected 40 hex digits", hex);
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16).with_context(|| format!("invalid sha {}", hex))
+        })
+        .collect()
+}
+
+pub(crate) fn kvlm_parse(raw: &[u8], start: Option<usize>, dct: Option<Kvlm>) -> Result<Kvlm> {
     let start = start.unwrap_or_default();
     let mut dct = dct.unwrap_or_default();
 
-    let spc = raw[start..].find(' ').map(|i| i + start);
-    let nl = raw[start..].find("\n").map(|i| i + start);
+    let find = |from: usize, byte: u8| {
+        raw[from..]
+            .iter()
+            .position(|&b| b == byte)
+            .map(|i| i + from)
+    };
+    let spc = find(start, b' ');
+    let nl = find(start, b'\n');
 
     // If space appears before newline, we have a keyword.
     //
@@ -202,57 +262,70 @@ fn kvlm_parse(
     if spc.is_none() || (spc.is_some() && nl.is_some() && (nl.unwrap() < spc.unwrap())) {
         // assert!(nl.unwrap() == start);
 
-        dct.insert("".into(), vec![raw[start + 1..].into()]);
+        dct.insert(
+            "".into(),
+            vec![raw.get(start + 1..).unwrap_or_default().to_vec()],
+        );
         return Ok(dct);
     }
 
     let spc = spc.unwrap();
 
     // Recursive case - we read a key-value pair and recurse for the next.
-    let key = &raw[start..spc];
+    let key = from_utf8(&raw[start..spc]).context("header name is not valid utf-8")?;
 
     // Find the end of the value. Continuation lines begin with a
     // space, so we loop until we find a "\n" not followed by a space.
     let mut end = start;
 
     loop {
-        match raw[end + 1..].find("\n").map(|i| i + end + 1) {
+        match find(end + 1, b'\n') {
             Some(v) => end = v,
-            None => break,
+            None => bail!("header {} is not terminated by a newline", key),
         }
 
-        if !raw
-            .chars()
-            .nth(end + 1)
-            .unwrap_or_default()
-            .eq(&char::from_u32(32).unwrap())
-        {
+        if raw.get(end + 1) != Some(&b' ') {
             break;
         }
     }
 
     // Grab the value. Also, drop the leading space on continuation lines.
-    let value = raw[spc + 1..end].replace("\n ", "\n");
+    let mut value = vec![];
+    for (i, &b) in raw[spc + 1..end].iter().enumerate() {
+        if b != b' ' || raw[spc + i] != b'\n' {
+            value.push(b);
+        }
+    }
 
     dct.entry(key.to_owned()).or_insert(vec![]).push(value);
 
     kvlm_parse(raw, Some(end + 1), Some(dct))
 }
 
-fn kvlm_serialize(kvlm: &IndexMap<String, Vec<String>>) -> String {
-    let mut ret: String = String::from("");
+pub(crate) fn kvlm_serialize(kvlm: &Kvlm) -> Vec<u8> {
+    let mut ret = vec![];
 
     for (k, v) in kvlm {
-        if k == "" {
+        if k.is_empty() {
             continue;
         }
-        v.iter().for_each(|val| {
-            ret += format!("{} {}\n", &k, val.replace("\n", "\n ")).as_str();
-            // ret += &k + " " + &(val.replace("\n", "\n ")) + "\n";
-        })
+        for val in v {
+            ret.extend_from_slice(k.as_bytes());
+            ret.push(b' ');
+            for &b in val {
+                ret.push(b);
+                if b == b'\n' {
+                    ret.push(b' ');
+                }
+            }
+            ret.push(b'\n');
+        }
     }
 
-    ret += format!("\n{}", kvlm[""][0]).as_str();
+    ret.push(b'\n');
+    if let Some(message) = kvlm.get("").and_then(|m| m.first()) {
+        ret.extend_from_slice(message);
+    }
     ret
 }
 
@@ -260,7 +333,66 @@ fn kvlm_serialize(kvlm: &IndexMap<String, Vec<String>>) -> String {
 mod tests {
     use indexmap::IndexMap;
 
-    use super::kvlm_parse;
+    use super::{kvlm_parse, GitObject, GitObjectType};
+    use crate::leaf::GitTreeLeaf;
+    use crate::testing::TempRepo;
+
+    #[test]
+    fn test_deserialize_latin1() {
+        let repo = TempRepo::new("latin1");
+        let raw = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+author J\xf6rg <j@x> 1 +0000\n\
+committer J\xf6rg <j@x> 1 +0000\n\
+encoding ISO-8859-1\n\
+\n\
+Gr\xfc\xdfe\n";
+        let object =
+            GitObject::new(&repo, Some(raw.to_vec()), Some(GitObjectType::Commit)).unwrap();
+        let commit = object.commit().unwrap();
+        assert_eq!(commit.author.name, "J\u{fffd}rg");
+        assert_eq!(commit.encoding.as_deref(), Some("ISO-8859-1"));
+        assert_eq!(commit.message, b"Gr\xfc\xdfe\n");
+        // the object still hashes and prints as stored.
+        assert_eq!(object.serialize().unwrap(), raw);
+        // and is rebuilt from kvlm once it is replaced.
+        let mut object = object;
+        let mut kvlm = object.kvlm().unwrap().clone();
+        kvlm[""] = vec![b"Gr\xfc\xdf Gott\n".to_vec()];
+        object.kvlm_set(kvlm);
+        let data = object.serialize().unwrap();
+        assert!(data.ends_with(b"\n\nGr\xfc\xdf Gott\n"));
+        assert!(data.starts_with(&raw[..raw.len() - 6]));
+    }
+
+    #[test]
+    fn test_serialize_bad_tree() {
+        let repo = TempRepo::new("bad-tree");
+        let mut object = GitObject::new(&repo, None, Some(GitObjectType::Tree)).unwrap();
+        object.tree = Some(vec![GitTreeLeaf {
+            mode: "100644".into(),
+            path: "f".into(),
+            sha: "not a sha".into(),
+        }]);
+        assert!(object.serialize().is_err());
+    }
+
+    #[test]
+    fn test_tag_kvlm() {
+        let content = "object 206941306e8a8af65b66eaaaea388a7ae24d49a0
+type commit
+tag v1.0
+tagger Thibault Polge <thibault@thb.lt> 1527025044 +0200
+
+Release 1.0
+with notes
+";
+        let kvlm = kvlm_parse(content.as_bytes(), None, None).unwrap();
+        let keys: Vec<&str> = kvlm.keys().map(String::as_str).collect();
+        assert_eq!(keys, ["object", "type", "tag", "tagger", ""]);
+        assert_eq!(kvlm["tag"], [b"v1.0"]);
+        assert_eq!(kvlm[""], [b"Release 1.0\nwith notes\n"]);
+        assert_eq!(super::kvlm_serialize(&kvlm), content.as_bytes());
+    }
 
     #[test]
     fn test_kvlm_parse() {
@@ -271,37 +403,25 @@ committer Thibault Polge <thibault@thb.lt> 1527025044 +0200
 
 Create first draft";
 
-        let values = kvlm_parse(content.to_string(), None, None);
+        let values = kvlm_parse(content.as_bytes(), None, None);
         let wanted = IndexMap::from([
             (
                 String::from("tree"),
-                vec![String::from("29ff16c9c14e2652b22f8b78bb08a5a07930c147")],
+                vec![b"29ff16c9c14e2652b22f8b78bb08a5a07930c147".to_vec()],
             ),
             (
                 String::from("parent"),
-                vec![String::from("206941306e8a8af65b66eaaaea388a7ae24d49a0")],
+                vec![b"206941306e8a8af65b66eaaaea388a7ae24d49a0".to_vec()],
             ),
             (
                 String::from("author"),
-                vec![
-                    String::from("Thibault"),
-                    String::from("Polge"),
-                    String::from("<thibault@thb.lt>"),
-                    String::from("1527025023"),
-                    String::from("+0200"),
-                ],
+                vec![b"Thibault Polge <thibault@thb.lt> 1527025023 +0200".to_vec()],
             ),
             (
                 String::from("committer"),
-                vec![
-                    String::from("Thibault"),
-                    String::from("Polge"),
-                    String::from("<thibault@thb.lt>"),
-                    String::from("1527025044"),
-                    String::from("+0200"),
-                ],
+                vec![b"Thibault Polge <thibault@thb.lt> 1527025044 +0200".to_vec()],
             ),
-            (String::from(""), vec![String::from("Create first draft")]),
+            (String::from(""), vec![b"Create first draft".to_vec()]),
         ]);
         assert_eq!(values.unwrap(), wanted);
     }
@@ -0,0 +1,889 @@
+use anyhow::{bail, Context};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression, Crc};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    fs::{self, File},
+    io::{BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use crate::{
+    object::{hex_to_sha, sha1, sha_to_hex, GitObjectType},
+    repository::RGitRepository,
+    Result,
+};
+
+const IDX_MAGIC: &[u8] = b"\xfftOc";
+
+/// Types of entries stored in a packfile, 5 is reserved.
+const OBJ_COMMIT: u8 = 1;
+const OBJ_TREE: u8 = 2;
+const OBJ_BLOB: u8 = 3;
+const OBJ_TAG: u8 = 4;
+const OBJ_OFS_DELTA: u8 = 6;
+const OBJ_REF_DELTA: u8 = 7;
+
+/// Limits of the delta base cache of a pack, in entries and in bytes.
+const DELTA_BASE_CACHE_ENTRIES: usize = 256;
+const DELTA_BASE_CACHE_SIZE: usize = 16 << 20;
+
+/// In-memory copy of a version 2 pack index (.idx) together with the path of its pack.
+///
+/// Layout: magic, version, 256 entries fanout table, sorted SHAs, CRC32s,
+/// 32-bit offsets (MSB set means index into the 64-bit offsets table), 64-bit
+/// offsets, pack checksum and index checksum.
+pub struct PackIndex {
+    pack_path: PathBuf,
+    fanout: Vec<u32>,
+    shas: Vec<u8>,
+    offsets: Vec<u64>,
+    /// Pack file, opened on the first read and kept open.
+    file: RefCell<Option<BufReader<File>>>,
+    bases: RefCell<DeltaBaseCache>,
+}
+
+impl PackIndex {
+    pub fn load(idx_path: &Path) -> Result<Self> {
+        let raw = fs::read(idx_path).with_context(|| format!("could not read {:?}", idx_path))?;
+        if raw.len() < 8 + 256 * 4 || &raw[0..4] != IDX_MAGIC {
+            bail!("{:?} is not a version 2 pack index", idx_path);
+        }
+        let version = be_u32(&raw[4..8]);
+        if version != 2 {
+            bail!("unsupported pack index version {}", version);
+        }
+
+        let fanout: Vec<u32> = (0..256)
+            .map(|i| be_u32(&raw[8 + i * 4..12 + i * 4]))
+            .collect();
+        let count = fanout[255] as usize;
+
+        let shas_start = 8 + 256 * 4;
+        let offsets_start = shas_start + count * 20 + count * 4;
+        let large_start = offsets_start + count * 4;
+        if raw.len() < large_start + 40 {
+            bail!("pack index {:?} is truncated", idx_path);
+        }
+
+        let offsets = (0..count)
+            .map(|i| {
+                let offset = be_u32(&raw[offsets_start + i * 4..offsets_start + i * 4 + 4]);
+                if offset & 0x8000_0000 == 0 {
+                    return Ok(offset as u64);
+                }
+                let pos = large_start + (offset & 0x7fff_ffff) as usize * 8;
+                let large = raw
+                    .get(pos..pos + 8)
+                    .context("64-bit offset out of bounds")?;
+                Ok(u64::from_be_bytes(large.try_into()?))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            pack_path: idx_path.with_extension("pack"),
+            fanout,
+            shas: raw[shas_start..shas_start + count * 20].to_vec(),
+            offsets,
+            file: RefCell::default(),
+            bases: RefCell::default(),
+        })
+    }
+
+    /// Pack checksum the files are named after.
+    pub fn name(&self) -> String {
+        self.pack_path
+            .file_stem()
+            .map(|s| s.to_string_lossy().trim_start_matches("pack-").to_string())
+            .unwrap_or_default()
+    }
+
+    /// Deletes index and pack files, index first so the pack is never seen without it.
+    pub fn remove(&self) -> Result<()> {
+        fs::remove_file(self.pack_path.with_extension("idx"))?;
+        fs::remove_file(&self.pack_path)?;
+        Ok(())
+    }
+
+    fn sha_at(&self, i: usize) -> &[u8] {
+        &self.shas[i * 20..i * 20 + 20]
+    }
+
+    /// Range of entries whose SHA starts with given byte, taken from the fanout table.
+    fn bucket(&self, first: u8) -> std::ops::Range<usize> {
+        let start = match first {
+            0 => 0,
+            n => self.fanout[n as usize - 1] as usize,
+        };
+        start..self.fanout[first as usize] as usize
+    }
+
+    /// Returns offset of the object in the pack.
+    pub fn find(&self, sha: &[u8]) -> Option<u64> {
+        let range = self.bucket(sha[0]);
+        let (mut lo, mut hi) = (range.start, range.end);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            match self.sha_at(mid).cmp(sha) {
+                std::cmp::Ordering::Equal => return Some(self.offsets[mid]),
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+            }
+        }
+        None
+    }
+
+    /// Lists SHAs of packed objects starting with given hex prefix.
+    pub fn find_prefix(&self, prefix: &str) -> Vec<String> {
+        let first = match u8::from_str_radix(&prefix[..2], 16) {
+            Ok(first) => first,
+            Err(_) => return vec![],
+        };
+        self.bucket(first)
+            .map(|i| sha_to_hex(self.sha_at(i)))
+            .filter(|sha| sha.starts_with(prefix))
+            .collect()
+    }
+
+    /// Reads entry at offset as stored, inflated but with deltas not applied.
+    fn entry_raw(&self, offset: u64) -> Result<RawEntry> {
+        let mut file = self.file.borrow_mut();
+        if file.is_none() {
+            *file = Some(BufReader::new(
+                File::open(&self.pack_path)
+                    .with_context(|| format!("could not open {:?}", self.pack_path))?,
+            ));
+        }
+        let file = file.as_mut().expect("pack file is open");
+        file.seek(SeekFrom::Start(offset))?;
+
+        // type and size: 1-bit continuation, 3-bit type, 4 bits of size, then 7 bits per byte.
+        let mut byte = read_byte(file)?;
+        let kind = (byte >> 4) & 0x7;
+        let mut size = (byte & 0x0f) as u64;
+        let mut shift = 4;
+        while byte & 0x80 != 0 {
+            byte = read_byte(file)?;
+            size |= ((byte & 0x7f) as u64) << shift;
+            shift += 7;
+        }
+
+        let object_type = match kind {
+            OBJ_COMMIT => GitObjectType::Commit,
+            OBJ_TREE => GitObjectType::Tree,
+            OBJ_BLOB => GitObjectType::Blob,
+            OBJ_TAG => GitObjectType::Tag,
+            OBJ_OFS_DELTA => {
+                // offset is relative to this entry, encoded big-endian with an
+                // implicit +1 added for every continuation byte.
+                let mut byte = read_byte(file)?;
+                let mut distance = (byte & 0x7f) as u64;
+                while byte & 0x80 != 0 {
+                    byte = read_byte(file)?;
+                    distance = ((distance + 1) << 7) | (byte & 0x7f) as u64;
+                }
+                let base_offset = offset
+                    .checked_sub(distance)
+                    .context("OFS_DELTA base before start of pack")?;
+                return Ok(RawEntry::OfsDelta(base_offset, inflate(file, size)?));
+            }
+            OBJ_REF_DELTA => {
+                let mut base_sha = [0u8; 20];
+                file.read_exact(&mut base_sha)?;
+                return Ok(RawEntry::RefDelta(base_sha, inflate(file, size)?));
+            }
+            _ => bail!("unknown pack entry type {} at {}", kind, offset),
+        };
+        Ok(RawEntry::Object(object_type, inflate(file, size)?))
+    }
+}
+
+/// Pack entry as stored, with its data inflated.
+enum RawEntry {
+    Object(GitObjectType, Vec<u8>),
+    /// Delta on the entry at an offset in the same pack.
+    OfsDelta(u64, Vec<u8>),
+    /// Delta on the object with given SHA, which may be outside the pack.
+    RefDelta([u8; 20], Vec<u8>),
+}
+
+/// Recently used delta bases of a pack by offset, so entries of a delta chain
+/// do not inflate the objects it is built on again. Least recently used go
+/// first when the cache is full.
+#[derive(Default)]
+struct DeltaBaseCache {
+    /// (offset, type, content), least recently used first.
+    entries: VecDeque<(u64, GitObjectType, Rc<Vec<u8>>)>,
+    size: usize,
+}
+
+impl DeltaBaseCache {
+    fn get(&mut self, offset: u64) -> Option<(GitObjectType, Rc<Vec<u8>>)> {
+        let i = self.entries.iter().position(|e| e.0 == offset)?;
+        let entry = self.entries.remove(i)?;
+        let ret = (entry.1, entry.2.clone());
+        self.entries.push_back(entry);
+        Some(ret)
+    }
+
+    fn insert(&mut self, offset: u64, object_type: GitObjectType, data: Rc<Vec<u8>>) {
+        if data.len() > DELTA_BASE_CACHE_SIZE {
+            return;
+        }
+        while self.entries.len() >= DELTA_BASE_CACHE_ENTRIES
+            || self.size + data.len() > DELTA_BASE_CACHE_SIZE
+        {
+            match self.entries.pop_front() {
+                Some((_, _, old)) => self.size -= old.len(),
+                None => break,
+            }
+        }
+        self.size += data.len();
+        self.entries.push_back((offset, object_type, data));
+    }
+}
+
+fn be_u32(b: &[u8]) -> u32 {
+    u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+}
+
+/// Loads indexes of all packs in objects/pack.
+pub fn pack_indexes(repo: &RGitRepository) -> Result<Vec<PackIndex>> {
+    let dir = repo.repo_path(&["objects", "pack"]);
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+
+    let mut ret = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().map_or(false, |e| e == "idx") && path.with_extension("pack").is_file() {
+            ret.push(PackIndex::load(&path)?);
+        }
+    }
+    Ok(ret)
+}
+
+/// Looks the object up in packs, returns its type and inflated content with deltas applied.
+pub fn pack_object_read(
+    repo: &RGitRepository,
+    packs: &[PackIndex],
+    sha: &str,
+) -> Result<Option<(GitObjectType, Vec<u8>)>> {
+    let bin = hex_to_sha(sha)?;
+    for pack in packs {
+        if let Some(offset) = pack.find(&bin) {
+            return Ok(Some(pack_entry_read(repo, pack, offset)?));
+        }
+    }
+    Ok(None)
+}
+
+/// Reads entry at offset, resolving OFS_DELTA and REF_DELTA chains.
+fn pack_entry_read(
+    repo: &RGitRepository,
+    pack: &PackIndex,
+    offset: u64,
+) -> Result<(GitObjectType, Vec<u8>)> {
+    let ((base_type, base), delta) = match pack.entry_raw(offset)? {
+        RawEntry::Object(object_type, data) => return Ok((object_type, data)),
+        RawEntry::OfsDelta(base_offset, delta) => {
+            (delta_base_read(repo, pack, base_offset)?, delta)
+        }
+        RawEntry::RefDelta(base_sha, delta) => match pack.find(&base_sha) {
+            Some(base_offset) => (delta_base_read(repo, pack, base_offset)?, delta),
+            None => {
+                let (base_type, base) = repo.object_read_raw(&sha_to_hex(&base_sha))?;
+                ((base_type, Rc::new(base)), delta)
+            }
+        },
+    };
+    Ok((base_type, delta_apply(&base, &delta)?))
+}
+
+/// Reads entry at offset as a delta base, through the pack's cache.
+fn delta_base_read(
+    repo: &RGitRepository,
+    pack: &PackIndex,
+    offset: u64,
+) -> Result<(GitObjectType, Rc<Vec<u8>>)> {
+    if let Some(base) = pack.bases.borrow_mut().get(offset) {
+        return Ok(base);
+    }
+    let (object_type, data) = pack_entry_read(repo, pack, offset)?;
+    let data = Rc::new(data);
+    pack.bases
+        .borrow_mut()
+        .insert(offset, object_type, data.clone());
+    Ok((object_type, data))
+}
+
+fn read_byte<R: Read>(r: &mut R) -> Result<u8> {
+    let mut b = [0u8; 1];
+    r.read_exact(&mut b).context("unexpected end of pack")?;
+    Ok(b[0])
+}
+
+fn inflate<R: Read>(r: &mut R, size: u64) -> Result<Vec<u8>> {
+    let mut ret = Vec::with_capacity(size as usize);
+    ZlibDecoder::new(r)
+        .read_to_end(&mut ret)
+        .context("could not inflate pack entry")?;
+    if ret.len() as u64 != size {
+        bail!(
+            "pack entry inflated to {} bytes, expected {}",
+            ret.len(),
+            size
+        );
+    }
+    Ok(ret)
+}
+
+/// Reads little-endian base-128 size used in delta headers.
+fn delta_size(delta: &[u8], pos: &mut usize) -> Result<usize> {
+    let mut size = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *delta.get(*pos).context("truncated delta header")?;
+        *pos += 1;
+        size |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(size);
+        }
+    }
+}
+
+/// Rebuilds object from base and delta. Delta is a list of instructions: copy
+/// a range from base (MSB set, followed by offset and size bytes selected by
+/// the low bits) or insert the next 1-127 literal bytes.
+pub fn delta_apply(base: &[u8], delta: &[u8]) -> Result<Vec<u8>> {
+    let mut pos = 0;
+    let base_size = delta_size(delta, &mut pos)?;
+    if base_size != base.len() {
+        bail!("delta base is {} bytes, expected {}", base.len(), base_size);
+    }
+    let result_size = delta_size(delta, &mut pos)?;
+    let mut ret = Vec::with_capacity(result_size);
+
+    while pos < delta.len() {
+        let op = delta[pos];
+        pos += 1;
+
+        if op & 0x80 != 0 {
+            let mut args = [0usize; 7];
+            for (i, arg) in args.iter_mut().enumerate() {
+                if op & (1 << i) != 0 {
+                    *arg = *delta.get(pos).context("truncated delta copy")? as usize;
+                    pos += 1;
+                }
+            }
+            let offset = args[0] | args[1] << 8 | args[2] << 16 | args[3] << 24;
+            let size = match args[4] | args[5] << 8 | args[6] << 16 {
+                0 => 0x10000,
+                size => size,
+            };
+            ret.extend_from_slice(
+                base.get(offset..offset + size)
+                    .context("delta copy out of base bounds")?,
+            );
+        } else if op != 0 {
+            let len = op as usize;
+            ret.extend_from_slice(
+                delta
+                    .get(pos..pos + len)
+                    .context("truncated delta insert")?,
+            );
+            pos += len;
+        } else {
+            bail!("delta opcode 0 is reserved");
+        }
+    }
+
+    if ret.len() != result_size {
+        bail!(
+            "delta produced {} bytes, expected {}",
+            ret.len(),
+            result_size
+        );
+    }
+    Ok(ret)
+}
+
+/// Object queued for packing. Path is the name object was reached through, used
+/// to put versions of the same file next to each other for delta search.
+pub struct PackEntry {
+    pub sha: String,
+    pub object_type: GitObjectType,
+    pub data: Vec<u8>,
+    pub path: Vec<u8>,
+}
+
+/// Writes entries into a new pack and its version 2 index under objects/pack,
+/// returns the pack checksum naming both files.
+///
+/// Every entry is tried as a delta against up to `window` preceding entries of the
+/// same type, and stored as OFS_DELTA when that saves space without making the
+/// delta chain longer than `depth`.
+pub fn pack_write(
+    repo: &RGitRepository,
+    mut entries: Vec<PackEntry>,
+    window: usize,
+    depth: usize,
+) -> Result<String> {
+    // group same type and same file name together, biggest first: deltas
+    // removing data are smaller than ones adding it.
+    entries.sort_by(|a, b| {
+        (a.object_type as u8, &a.path, b.data.len()).cmp(&(
+            b.object_type as u8,
+            &b.path,
+            a.data.len(),
+        ))
+    });
+
+    let mut pack = b"PACK".to_vec();
+    pack.extend_from_slice(&2u32.to_be_bytes());
+    pack.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+
+    let mut offsets: Vec<u64> = Vec::with_capacity(entries.len());
+    let mut depths: Vec<usize> = Vec::with_capacity(entries.len());
+    let mut index = Vec::with_capacity(entries.len());
+
+    for (i, entry) in entries.iter().enumerate() {
+        let mut best: Option<(usize, Vec<u8>)> = None;
+        for j in (i.saturating_sub(window)..i).rev() {
+            let base = &entries[j];
+            if base.object_type != entry.object_type || depths[j] >= depth {
+                continue;
+            }
+            let delta = delta_create(&base.data, &entry.data);
+            let limit = best.as_ref().map_or(entry.data.len() / 2, |(_, d)| d.len());
+            if delta.len() < limit {
+                best = Some((j, delta));
+            }
+        }
+
+        let offset = pack.len() as u64;
+        let mut raw = vec![];
+        match best {
+            Some((j, delta)) => {
+                entry_header_write(&mut raw, OBJ_OFS_DELTA, delta.len() as u64);
+                ofs_write(&mut raw, offset - offsets[j]);
+                raw.extend(deflate(&delta)?);
+                depths.push(depths[j] + 1);
+            }
+            None => {
+                entry_header_write(
+                    &mut raw,
+                    pack_type(entry.object_type),
+                    entry.data.len() as u64,
+                );
+                raw.extend(deflate(&entry.data)?);
+                depths.push(0);
+            }
+        }
+
+        let mut crc = Crc::new();
+        crc.update(&raw);
+        index.push((hex_to_sha(&entry.sha)?, crc.sum(), offset));
+        offsets.push(offset);
+        pack.extend(raw);
+    }
+
+    let checksum = sha1(&pack);
+    pack.extend_from_slice(&checksum);
+    let name = sha_to_hex(&checksum);
+
+    let idx = idx_serialize(index, &checksum);
+    let dir = repo
+        .repo_dir(&["objects", "pack"], Some(true))?
+        .context("could not create objects/pack")?;
+    // the pack goes first, readers only look at packs with an index.
+    fs::write(dir.join(format!("pack-{}.pack", name)), pack)?;
+    fs::write(dir.join(format!("pack-{}.idx", name)), idx)?;
+    Ok(name)
+}
+
+fn pack_type(object_type: GitObjectType) -> u8 {
+    match object_type {
+        GitObjectType::Commit => OBJ_COMMIT,
+        GitObjectType::Tree => OBJ_TREE,
+        GitObjectType::Blob => OBJ_BLOB,
+        GitObjectType::Tag => OBJ_TAG,
+    }
+}
+
+fn entry_header_write(out: &mut Vec<u8>, kind: u8, size: u64) {
+    let mut byte = (kind << 4) | (size & 0x0f) as u8;
+    let mut size = size >> 4;
+    while size != 0 {
+        out.push(byte | 0x80);
+        byte = (size & 0x7f) as u8;
+        size >>= 7;
+    }
+    out.push(byte);
+}
+
+/// Inverse of the OFS_DELTA distance decoding in pack_entry_read.
+fn ofs_write(out: &mut Vec<u8>, mut distance: u64) {
+    let mut bytes = vec![(distance & 0x7f) as u8];
+    distance >>= 7;
+    while distance != 0 {
+        distance -= 1;
+        bytes.push(0x80 | (distance & 0x7f) as u8);
+        distance >>= 7;
+    }
+    bytes.reverse();
+    out.extend(bytes);
+}
+
+fn deflate(data: &[u8]) -> Result<Vec<u8>> {
+    let mut e = ZlibEncoder::new(vec![], Compression::default());
+    e.write_all(data)?;
+    Ok(e.finish()?)
+}
+
+/// Builds version 2 index from (sha, crc32, offset) of every entry.
+fn idx_serialize(mut index: Vec<(Vec<u8>, u32, u64)>, pack_checksum: &[u8]) -> Vec<u8> {
+    index.sort();
+
+    let mut idx = IDX_MAGIC.to_vec();
+    idx.extend_from_slice(&2u32.to_be_bytes());
+    for first in 0..256usize {
+        let count = index
+            .iter()
+            .filter(|(sha, _, _)| (sha[0] as usize) <= first)
+            .count();
+        idx.extend_from_slice(&(count as u32).to_be_bytes());
+    }
+    for (sha, _, _) in &index {
+        idx.extend_from_slice(sha);
+    }
+    for (_, crc, _) in &index {
+        idx.extend_from_slice(&crc.to_be_bytes());
+    }
+    let mut large = vec![];
+    for (_, _, offset) in &index {
+        if *offset < 0x8000_0000 {
+            idx.extend_from_slice(&(*offset as u32).to_be_bytes());
+        } else {
+            idx.extend_from_slice(&(0x8000_0000 | large.len() as u32).to_be_bytes());
+            large.push(*offset);
+        }
+    }
+    for offset in large {
+        idx.extend_from_slice(&offset.to_be_bytes());
+    }
+    idx.extend_from_slice(pack_checksum);
+    let checksum = sha1(&idx);
+    idx.extend(checksum);
+    idx
+}
+
+/// Writes little-endian base-128 size used in delta headers.
+fn delta_size_write(out: &mut Vec<u8>, mut size: usize) {
+    while size >= 0x80 {
+        out.push(0x80 | (size & 0x7f) as u8);
+        size >>= 7;
+    }
+    out.push(size as u8);
+}
+
+/// Size of blocks of base indexed when searching for copies.
+const DELTA_BLOCK: usize = 16;
+
+/// Computes delta turning base into target, the inverse of delta_apply.
+///
+/// Base is cut into blocks indexed by content; target is scanned for blocks
+/// present in base, which are extended in both directions and emitted as copies.
+/// Bytes in between are emitted as inserts.
+pub fn delta_create(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut out = vec![];
+    delta_size_write(&mut out, base.len());
+    delta_size_write(&mut out, target.len());
+
+    let mut blocks: HashMap<&[u8], usize> = HashMap::new();
+    for start in (0..base.len().saturating_sub(DELTA_BLOCK - 1)).step_by(DELTA_BLOCK) {
+        blocks
+            .entry(&base[start..start + DELTA_BLOCK])
+            .or_insert(start);
+    }
+
+    let mut pending: Vec<u8> = vec![];
+    let mut pos = 0;
+    while pos < target.len() {
+        let found = target
+            .get(pos..pos + DELTA_BLOCK)
+            .and_then(|block| blocks.get(block));
+        let mut start = match found {
+            Some(&start) => start,
+            None => {
+                pending.push(target[pos]);
+                pos += 1;
+                continue;
+            }
+        };
+
+        // extend forwards, then backwards over bytes waiting to be inserted.
+        let mut len = DELTA_BLOCK;
+        while start + len < base.len()
+            && pos + len < target.len()
+            && base[start + len] == target[pos + len]
+            && len < 0xff_ffff
+        {
+            len += 1;
+        }
+        let next = pos + len;
+        while start > 0 && pending.last() == Some(&base[start - 1]) && len < 0xff_ffff {
+            start -= 1;
+            len += 1;
+            pending.pop();
+        }
+
+        delta_insert_write(&mut out, &pending);
+        pending.clear();
+        delta_copy_write(&mut out, start, len);
+        pos = next;
+    }
+    delta_insert_write(&mut out, &pending);
+    out
+}
+
+fn delta_insert_write(out: &mut Vec<u8>, data: &[u8]) {
+    for chunk in data.chunks(0x7f) {
+        out.push(chunk.len() as u8);
+        out.extend_from_slice(chunk);
+    }
+}
+
+fn delta_copy_write(out: &mut Vec<u8>, offset: usize, size: usize) {
+    let mut op = 0x80u8;
+    let mut args = vec![];
+    for (i, byte) in (offset as u32).to_le_bytes().iter().enumerate() {
+        if *byte != 0 {
+            op |= 1 << i;
+            args.push(*byte);
+        }
+    }
+    for (i, byte) in (size as u32).to_le_bytes()[..3].iter().enumerate() {
+        if *byte != 0 {
+            op |= 1 << (4 + i);
+            args.push(*byte);
+        }
+    }
+    out.push(op);
+    out.extend(args);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        deflate, delta_apply, delta_create, entry_header_write, idx_serialize, ofs_write,
+        pack_entry_read, pack_indexes, pack_object_read, pack_write, PackEntry, PackIndex,
+        OBJ_BLOB, OBJ_OFS_DELTA, OBJ_REF_DELTA,
+    };
+    use crate::{
+        object::{hex_to_sha, sha1, sha_to_hex, GitObjectType},
+        patch::blob_sha,
+        testing::TempRepo,
+    };
+    use flate2::Crc;
+    use std::{fs, process::Command};
+
+    #[test]
+    fn test_delta_apply() {
+        let base = b"hello brave new world";
+        // base size 21, result size 17, copy "hello " (offset 0, size 6),
+        // insert "old ", copy "world" (offset 16, size 5) and insert "!\n".
+        let mut delta = vec![21, 17, 0x90, 6, 4];
+        delta.extend_from_slice(b"old ");
+        delta.extend_from_slice(&[0x91, 16, 5, 2]);
+        delta.extend_from_slice(b"!\n");
+
+        assert_eq!(delta_apply(base, &delta).unwrap(), b"hello old world!\n");
+        assert!(delta_apply(b"short", &delta).is_err());
+    }
+
+    #[test]
+    fn test_delta_create() {
+        let base: Vec<u8> = (0..2000u32)
+            .flat_map(|i| format!("line {}\n", i).into_bytes())
+            .collect();
+        let mut target = base[..5000].to_vec();
+        target.extend_from_slice(b"inserted in the middle\n");
+        target.extend_from_slice(&base[5100..]);
+
+        let delta = delta_create(&base, &target);
+        assert!(delta.len() < 100);
+        assert_eq!(delta_apply(&base, &delta).unwrap(), target);
+
+        let unrelated = b"nothing in common".to_vec();
+        assert_eq!(
+            delta_apply(&base, &delta_create(&base, &unrelated)).unwrap(),
+            unrelated
+        );
+    }
+
+    #[test]
+    fn test_pack_write() {
+        let repo = TempRepo::new("pack-write");
+        // versions of a file, each a line longer, make a delta chain.
+        let versions: Vec<Vec<u8>> = (0..5)
+            .map(|n| {
+                (0..100 + n)
+                    .flat_map(|i| format!("line {}\n", i).into_bytes())
+                    .collect()
+            })
+            .collect();
+        let entries = versions
+            .iter()
+            .map(|data| PackEntry {
+                sha: blob_sha(data),
+                object_type: GitObjectType::Blob,
+                data: data.clone(),
+                path: "file".into(),
+            })
+            .collect();
+        let name = pack_write(&repo, entries, 10, 2).unwrap();
+
+        let pack_path = repo
+            .dir()
+            .join(format!(".git/objects/pack/pack-{}.pack", name));
+        let pack = PackIndex::load(&pack_path.with_extension("idx")).unwrap();
+        assert_eq!(pack.name(), name);
+        let raw = fs::read(&pack_path).unwrap();
+        let mut deltas = 0;
+        for data in &versions {
+            let offset = pack.find(&hex_to_sha(&blob_sha(data)).unwrap()).unwrap();
+            deltas += ((raw[offset as usize] >> 4) & 0x7 == OBJ_OFS_DELTA) as usize;
+            assert_eq!(
+                pack_entry_read(&repo, &pack, offset).unwrap(),
+                (GitObjectType::Blob, data.clone())
+            );
+        }
+        // the biggest version is stored whole, the rest as deltas.
+        assert_eq!(deltas, 4);
+        assert_eq!(pack.find(&hex_to_sha(&blob_sha(b"missing")).unwrap()), None);
+    }
+
+    #[test]
+    fn test_pack_read() {
+        let repo = TempRepo::new("pack-read");
+        let loose = b"loose\n".to_vec();
+        repo.blob("loose\n");
+
+        // a blob, an OFS_DELTA on it, a REF_DELTA on that one, and a REF_DELTA
+        // on a loose object outside the pack.
+        let base = b"one\ntwo\nthree\n".to_vec();
+        let ofs = b"one\ntwo\nthree\nfour\n".to_vec();
+        let chained = b"zero\none\ntwo\nthree\nfour\n".to_vec();
+        let thin = b"loose\nand more\n".to_vec();
+        let mut pack = b"PACK".to_vec();
+        pack.extend_from_slice(&2u32.to_be_bytes());
+        pack.extend_from_slice(&4u32.to_be_bytes());
+        let mut index = vec![];
+        let mut entry = |pack: &mut Vec<u8>, data: &[u8], raw: Vec<u8>| {
+            let mut crc = Crc::new();
+            crc.update(&raw);
+            index.push((
+                hex_to_sha(&blob_sha(data)).unwrap(),
+                crc.sum(),
+                pack.len() as u64,
+            ));
+            pack.extend(raw);
+        };
+
+        let base_offset = pack.len() as u64;
+        let mut raw = vec![];
+        entry_header_write(&mut raw, OBJ_BLOB, base.len() as u64);
+        raw.extend(deflate(&base).unwrap());
+        entry(&mut pack, &base, raw);
+
+        let delta = delta_create(&base, &ofs);
+        let mut raw = vec![];
+        entry_header_write(&mut raw, OBJ_OFS_DELTA, delta.len() as u64);
+        ofs_write(&mut raw, pack.len() as u64 - base_offset);
+        raw.extend(deflate(&delta).unwrap());
+        entry(&mut pack, &ofs, raw);
+
+        for (base, data) in [(&ofs, &chained), (&loose, &thin)] {
+            let delta = delta_create(base, data);
+            let mut raw = vec![];
+            entry_header_write(&mut raw, OBJ_REF_DELTA, delta.len() as u64);
+            raw.extend(hex_to_sha(&blob_sha(base)).unwrap());
+            raw.extend(deflate(&delta).unwrap());
+            entry(&mut pack, data, raw);
+        }
+
+        let checksum = sha1(&pack);
+        pack.extend_from_slice(&checksum);
+        let name = sha_to_hex(&checksum);
+        let pack_dir = repo.dir().join(".git/objects/pack");
+        fs::create_dir_all(&pack_dir).unwrap();
+        fs::write(pack_dir.join(format!("pack-{}.pack", name)), &pack).unwrap();
+        fs::write(
+            pack_dir.join(format!("pack-{}.idx", name)),
+            idx_serialize(index, &checksum),
+        )
+        .unwrap();
+
+        let packs = pack_indexes(&repo).unwrap();
+        assert_eq!(packs[0].name(), name);
+        let read = |data: &[u8]| pack_object_read(&repo, &packs, &blob_sha(data)).unwrap();
+        for data in [&base, &ofs, &chained, &thin] {
+            assert_eq!(read(data), Some((GitObjectType::Blob, data.clone())));
+        }
+        // loose objects are not in the pack.
+        assert_eq!(read(&loose), None);
+        assert!(packs[0]
+            .find_prefix(&blob_sha(&ofs)[..4])
+            .contains(&blob_sha(&ofs)));
+        assert_eq!(
+            repo.object_read_raw(&blob_sha(&chained)).unwrap().1,
+            chained
+        );
+    }
+
+    #[test]
+    fn test_git_pack_read() {
+        let repo = TempRepo::new("git-pack-read");
+        let git = |args: &[&str]| -> Vec<u8> {
+            let out = Command::new("git")
+                .args(["-c", "user.name=A", "-c", "user.email=a@x"])
+                .args(args)
+                .current_dir(repo.dir())
+                .output()
+                .unwrap();
+            assert!(out.status.success(), "git {:?} failed", args);
+            out.stdout
+        };
+        // versions of a file, each a line longer, which git packs as deltas.
+        for n in 0..10 {
+            let data: String = (0..200 + n).map(|i| format!("line {}\n", i)).collect();
+            repo.write("file", &data);
+            git(&["add", "file"]);
+            git(&["commit", "-q", "-m", &format!("version {}", n)]);
+        }
+        git(&["repack", "-a", "-d", "-f", "-q", "--depth=50"]);
+        let objects = String::from_utf8(git(&["rev-list", "--objects", "--all"])).unwrap();
+        let shas: Vec<&str> = objects.lines().map(|l| &l[..40]).collect();
+        // repack -d leaves no loose copies to read instead.
+        assert!(!repo.dir().join(".git/objects").join(&shas[0][..2]).exists());
+
+        let packs = pack_indexes(&repo).unwrap();
+        assert_eq!(packs.len(), 1);
+        let raw = fs::read(&packs[0].pack_path).unwrap();
+        let mut deltas = 0;
+        for sha in shas {
+            let offset = packs[0].find(&hex_to_sha(sha).unwrap()).unwrap();
+            deltas += ((raw[offset as usize] >> 4) & 0x7 == OBJ_OFS_DELTA) as usize;
+            let (object_type, data) = pack_object_read(&repo, &packs, sha).unwrap().unwrap();
+            assert_eq!(data, git(&["cat-file", &object_type.to_string(), sha]));
+        }
+        assert!(deltas >= 9);
+        // the pack stayed open and reading the chains left their bases cached.
+        assert!(packs[0].file.borrow().is_some());
+        assert!(!packs[0].bases.borrow().entries.is_empty());
+    }
+}
@@ -0,0 +1,629 @@
+use anyhow::Context;
+use std::{collections::BTreeMap, fs, os::unix::ffi::OsStrExt, path::Path};
+
+use crate::{
+    color::{paint, BOLD, GREEN, RED},
+    diff::{diff_lines, is_binary, unified, DiffOptions},
+    file::bytes_path,
+    index::{filemode, path_matches, Index},
+    leaf::{tree_flatten, GitTreeLeaf},
+    object::{sha1, sha_to_hex},
+    refs::NULL_SHA,
+    repository::RGitRepository,
+    status::{change, worktree_change, Change},
+    treediff::TreeChange,
+    Result,
+};
+
+/// (mode, sha) of every path on one side of a diff.
+pub type Entries = BTreeMap<Vec<u8>, (u32, String)>;
+
+/// One side of a changed file: its path, mode, blob SHA and content.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiffSide {
+    pub path: Vec<u8>,
+    pub mode: u32,
+    pub sha: String,
+    pub data: Vec<u8>,
+}
+
+impl DiffSide {
+    /// Side stored as a blob. Gitlinks have no blob, they show as the commit
+    /// they point at.
+    pub fn blob(repo: &RGitRepository, path: &[u8], mode: u32, sha: &str) -> Result<Self> {
+        let data = match mode {
+            0o160000 => format!("Subproject commit {}\n", sha).into_bytes(),
+            _ => repo.object_read_raw(sha)?.1,
+        };
+        Ok(Self {
+            path: path.to_vec(),
+            mode,
+            sha: sha.to_string(),
+            data,
+        })
+    }
+
+    /// Side read from a file on disk, with the SHA its blob would have.
+    pub fn file(path: &[u8], fs_path: &Path, mode: u32) -> Result<Self> {
+        let data = match mode {
+            0o120000 => fs::read_link(fs_path)?.as_os_str().as_bytes().to_vec(),
+            _ => fs::read(fs_path).with_context(|| format!("could not read {:?}", fs_path))?,
+        };
+        Ok(Self {
+            path: path.to_vec(),
+            mode,
+            sha: blob_sha(&data),
+            data,
+        })
+    }
+}
+
+/// A changed file with content of both sides, None where it does not exist.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilePair {
+    pub change: Change,
+    /// Similarity of renamed and copied content, in percent.
+    pub score: u8,
+    pub old: Option<DiffSide>,
+    pub new: Option<DiffSide>,
+}
+
+impl FilePair {
+    /// Pair of two sides of a path, None if they are the same.
+    pub fn new(old: Option<DiffSide>, new: Option<DiffSide>) -> Option<Self> {
+        let side = |side: &Option<DiffSide>| side.as_ref().map(|s| (s.mode, s.sha.clone()));
+        change(side(&old).as_ref(), side(&new).as_ref()).map(|change| Self {
+            change,
+            score: 0,
+            old,
+            new,
+        })
+    }
+}
+
+/// SHA of content stored as a blob, without writing it.
+pub fn blob_sha(data: &[u8]) -> String {
+    let mut raw = format!("blob {}\0", data.len()).into_bytes();
+    raw.extend_from_slice(data);
+    sha_to_hex(&sha1(&raw))
+}
+
+/// Entries of a tree-ish, none for None like before the first commit.
+pub fn tree_entries(repo: &RGitRepository, treeish: Option<&str>) -> Result<Entries> {
+    let treeish = match treeish {
+        Some(treeish) => treeish,
+        None => return Ok(Entries::new()),
+    };
+    let tree = repo.object_find(treeish, Some("tree".into()), None)?;
+    tree_flatten(repo, &tree)?
+        .into_values()
+        .map(|leaf| Ok((leaf.path.clone(), (leaf.mode_bits()?, leaf.sha))))
+        .collect()
+}
+
+/// Merged entries of the index, conflicted paths are left out.
+pub fn index_entries(index: &Index) -> Entries {
+    index
+        .entries
+        .iter()
+        .filter(|e| e.stage == 0)
+        .map(|e| (e.path.clone(), (e.mode, e.sha.clone())))
+        .collect()
+}
+
+/// Entries of files in the work tree, for paths tracked in the index. Files
+/// whose stat data matches the index are not hashed again.
+pub fn worktree_entries(repo: &RGitRepository, index: &Index) -> Result<Entries> {
+    let filemode = filemode(repo);
+    let mut ret = Entries::new();
+    for e in index.entries.iter().filter(|e| e.stage == 0) {
+        let (_, mode, sha) = worktree_change(repo, e, filemode)?;
+        if mode != 0 {
+            ret.insert(e.path.clone(), (mode, sha));
+        }
+    }
+    Ok(ret)
+}
+
+/// Changes of paths that differ between old and new, sorted by path.
+pub fn entries_diff(old: &Entries, new: &Entries) -> Result<Vec<TreeChange>> {
+    let mut paths: Vec<&Vec<u8>> = old.keys().chain(new.keys()).collect();
+    paths.sort_unstable();
+    paths.dedup();
+
+    let leaf = |path: &[u8], (mode, sha): &(u32, String)| {
+        GitTreeLeaf::new(format!("{:o}", mode), path.to_vec(), sha.clone())
+    };
+    let mut ret = vec![];
+    for path in paths {
+        let old = old.get(path).map(|side| leaf(path, side));
+        let new = new.get(path).map(|side| leaf(path, side));
+        ret.extend(TreeChange::new(old, new)?);
+    }
+    Ok(ret)
+}
+
+/// Keeps changes of paths matching any of pathspecs, all when there are none.
+/// Renames and copies match by either path.
+pub fn changes_limit(changes: &mut Vec<TreeChange>, pathspecs: &[String]) {
+    if pathspecs.is_empty() {
+        return;
+    }
+    changes.retain(|c| {
+        [&c.old, &c.new].into_iter().flatten().any(|leaf| {
+            pathspecs
+                .iter()
+                .any(|p| path_matches(&leaf.path, p.as_bytes()))
+        })
+    });
+}
+
+/// Content of a side of a change. New sides come from the work tree when
+/// worktree is set, everything else from blobs.
+pub fn content_read(
+    repo: &RGitRepository,
+    leaf: &GitTreeLeaf,
+    new: bool,
+    worktree: bool,
+) -> Result<DiffSide> {
+    let mode = leaf.mode_bits()?;
+    match new && worktree && mode != 0o160000 {
+        true => DiffSide::file(
+            &leaf.path,
+            &repo.work_tree().join(bytes_path(&leaf.path)),
+            mode,
+        ),
+        false => DiffSide::blob(repo, &leaf.path, mode, &leaf.sha),
+    }
+}
+
+/// Reads content of both sides of changes.
+pub fn file_pairs(
+    repo: &RGitRepository,
+    changes: &[TreeChange],
+    worktree: bool,
+) -> Result<Vec<FilePair>> {
+    changes
+        .iter()
+        .map(|c| {
+            Ok(FilePair {
+                change: c.change,
+                score: c.score,
+                old: c
+                    .old
+                    .as_ref()
+                    .map(|leaf| content_read(repo, leaf, false, worktree))
+                    .transpose()?,
+                new: c
+                    .new
+                    .as_ref()
+                    .map(|leaf| content_read(repo, leaf, true, worktree))
+                    .transpose()?,
+            })
+        })
+        .collect()
+}
+
+/// Writes a file pair as git does in a patch: "diff --git" header, mode and
+/// index lines, then hunks with context lines around changes. A change of file
+/// type is written as a deletion followed by an addition. Object names are
+/// abbreviated against repo when there is one.
+pub fn patch_write(
+    out: &mut Vec<u8>,
+    repo: Option<&RGitRepository>,
+    pair: &FilePair,
+    options: &DiffOptions,
+) -> Result<()> {
+    if pair.change == Change::TypeChanged {
+        let deleted = FilePair {
+            change: Change::Deleted,
+            new: None,
+            ..pair.clone()
+        };
+        let added = FilePair {
+            change: Change::Added,
+            old: None,
+            ..pair.clone()
+        };
+        patch_write(out, repo, &deleted, options)?;
+        return patch_write(out, repo, &added, options);
+    }
+
+    fn side(side: &Option<DiffSide>) -> (u32, &str, &[u8]) {
+        match side {
+            Some(side) => (side.mode, &side.sha, &side.data),
+            None => (0, NULL_SHA, &[]),
+        }
+    }
+    let (old_mode, old_sha, old_data) = side(&pair.old);
+    let (new_mode, new_sha, new_data) = side(&pair.new);
+    let old_path = &pair.old.as_ref().or(pair.new.as_ref()).unwrap().path;
+    let new_path = &pair.new.as_ref().or(pair.old.as_ref()).unwrap().path;
+    let (a, b) = (
+        quote_path(&[b"a/", old_path.as_slice()].concat()),
+        quote_path(&[b"b/", new_path.as_slice()].concat()),
+    );
+
+    // header lines are painted one by one.
+    let meta = |out: &mut Vec<u8>, lines: String| {
+        for line in lines.lines() {
+            paint(out, options.color, BOLD, line.as_bytes());
+            out.push(b'\n');
+        }
+    };
+    meta(out, format!("diff --git {} {}", a, b));
+    match (&pair.old, &pair.new) {
+        (None, _) => meta(out, format!("new file mode {:06o}", new_mode)),
+        (_, None) => meta(out, format!("deleted file mode {:06o}", old_mode)),
+        _ if old_mode != new_mode => meta(
+            out,
+            format!("old mode {:06o}\nnew mode {:06o}", old_mode, new_mode),
+        ),
+        _ => {}
+    }
+    let verb = match pair.change {
+        Change::Renamed => Some("rename"),
+        Change::Copied => Some("copy"),
+        _ => None,
+    };
+    if let Some(verb) = verb {
+        meta(
+            out,
+            format!(
+                "similarity index {}%\n{} from {}\n{} to {}",
+                pair.score,
+                verb,
+                quote_path(old_path),
+                verb,
+                quote_path(new_path)
+            ),
+        );
+    }
+    if old_sha == new_sha {
+        return Ok(());
+    }
+    let mode = match old_mode == new_mode {
+        true => format!(" {:06o}", old_mode),
+        false => String::new(),
+    };
+    meta(
+        out,
+        format!(
+            "index {}..{}{}",
+            abbreviate(repo, old_sha)?,
+            abbreviate(repo, new_sha)?,
+            mode
+        ),
+    );
+
+    let (a, b) = (
+        pair.old.as_ref().map_or("/dev/null".to_string(), |_| a),
+        pair.new.as_ref().map_or("/dev/null".to_string(), |_| b),
+    );
+    if is_binary(old_data) || is_binary(new_data) {
+        out.extend_from_slice(format!("Binary files {} and {} differ\n", a, b).as_bytes());
+        return Ok(());
+    }
+    let hunks = unified(old_data, new_data, options);
+    if !hunks.is_empty() {
+        // like git, names with spaces get a tab so patch finds where they end.
+        let tab = |name: &str| match name.contains(' ') {
+            true => "\t",
+            false => "",
+        };
+        meta(out, format!("--- {}{}\n+++ {}{}", a, tab(&a), b, tab(&b)));
+        out.extend_from_slice(&hunks);
+    }
+    Ok(())
+}
+
+/// Writes "--name-only" or "--name-status" lines of changes. Renames and
+/// copies show both paths after their score.
+pub fn names_write(out: &mut Vec<u8>, changes: &[TreeChange], status: bool) {
+    for c in changes {
+        let line = match (status, c.change) {
+            (false, _) => quote_path(c.path()),
+            (true, Change::Renamed | Change::Copied) => format!(
+                "{}{:03}\t{}\t{}",
+                c.change.code(),
+                c.score,
+                quote_path(&c.old.as_ref().unwrap().path),
+                quote_path(c.path())
+            ),
+            (true, _) => format!("{}\t{}", c.change.code(), quote_path(c.path())),
+        };
+        out.extend_from_slice(line.as_bytes());
+        out.push(b'\n');
+    }
+}
+
+/// Writes "--numstat" lines: added and deleted lines with the name, dashes
+/// instead of counts for binary files.
+pub fn numstat_write(out: &mut Vec<u8>, pairs: &[FilePair], options: &DiffOptions) {
+    for pair in pairs {
+        let counts = match line_counts(pair, options) {
+            Some((added, deleted)) => format!("{}\t{}", added, deleted),
+            None => "-\t-".to_string(),
+        };
+        out.extend_from_slice(format!("{}\t{}\n", counts, stat_name(pair)).as_bytes());
+    }
+}
+
+/// Writes "--stat" lines and the summary, fitting width columns as git does:
+/// long names are cut from the left and the graph of "+" and "-" is scaled.
+pub fn stat_write(out: &mut Vec<u8>, pairs: &[FilePair], width: usize, options: &DiffOptions) {
+    if pairs.is_empty() {
+        return;
+    }
+    let stats: Vec<(String, Option<(usize, usize)>)> = pairs
+        .iter()
+        .map(|pair| (stat_name(pair), line_counts(pair, options)))
+        .collect();
+    let size = |side: &Option<DiffSide>| side.as_ref().map_or(0, |s| s.data.len());
+
+    let (mut max_len, mut max_change, mut bin_width, mut number_width) = (0, 0, 0, 0);
+    for (pair, (name, counts)) in pairs.iter().zip(&stats) {
+        max_len = max_len.max(name.chars().count());
+        match counts {
+            Some((added, deleted)) => max_change = max_change.max(added + deleted),
+            None => {
+                // "Bin XXX -> YYY bytes"
+                let digits = |n: usize| n.to_string().len();
+                bin_width = bin_width.max(14 + digits(size(&pair.old)) + digits(size(&pair.new)));
+                number_width = 3;
+            }
+        }
+    }
+    number_width = number_width.max(max_change.to_string().len());
+    let width = width.max(16 + 6 + number_width);
+    let mut graph_width = match max_change + 4 > bin_width {
+        true => max_change,
+        false => bin_width - 4,
+    };
+    let mut name_width = max_len;
+    if name_width + number_width + 6 + graph_width > width {
+        if graph_width > (width * 3 / 8).saturating_sub(number_width + 6) {
+            graph_width = (width * 3 / 8).saturating_sub(number_width + 6).max(6);
+        }
+        match name_width > width - number_width - 6 - graph_width {
+            true => name_width = width - number_width - 6 - graph_width,
+            false => graph_width = width - number_width - 6 - name_width,
+        }
+    }
+
+    let (mut files, mut insertions, mut deletions) = (0, 0, 0);
+    for (pair, (name, counts)) in pairs.iter().zip(&stats) {
+        files += 1;
+        let mut name = name.as_str();
+        let mut prefix = "";
+        if name.chars().count() > name_width {
+            prefix = "...";
+            let keep = name_width.saturating_sub(3);
+            let skip = name.chars().count() - keep;
+            name = &name[name.char_indices().nth(skip).map_or(name.len(), |(i, _)| i)..];
+            if let Some(slash) = name.find('/') {
+                name = &name[slash..];
+            }
+        }
+        let padding = name_width.saturating_sub(prefix.len() + name.chars().count());
+        let line = format!(" {}{}{:padding$} | ", prefix, name, "", padding = padding);
+        out.extend_from_slice(line.as_bytes());
+
+        let (added, deleted) = match counts {
+            Some(counts) => *counts,
+            None => {
+                let (old, new) = (size(&pair.old), size(&pair.new));
+                out.extend_from_slice(format!("{:>w$}", "Bin", w = number_width).as_bytes());
+                if old != 0 || new != 0 {
+                    out.push(b' ');
+                    paint(out, options.color, RED, old.to_string().as_bytes());
+                    out.extend_from_slice(b" -> ");
+                    paint(out, options.color, GREEN, new.to_string().as_bytes());
+                    out.extend_from_slice(b" bytes");
+                }
+                out.push(b'\n');
+                continue;
+            }
+        };
+        insertions += added;
+        deletions += deleted;
+        let (mut plus, mut minus) = (added, deleted);
+        if graph_width <= max_change {
+            let scale = |n: usize| match n {
+                0 => 0,
+                n => 1 + n * (graph_width - 1) / max_change,
+            };
+            let mut total = scale(added + deleted);
+            if total < 2 && added > 0 && deleted > 0 {
+                total = 2;
+            }
+            if added < deleted {
+                plus = scale(added);
+                minus = total - plus;
+            } else {
+                minus = scale(deleted);
+                plus = total - minus;
+            }
+        }
+        let line = format!(
+            "{:>w$}{}",
+            added + deleted,
+            if added + deleted > 0 { " " } else { "" },
+            w = number_width
+        );
+        out.extend_from_slice(line.as_bytes());
+        if plus > 0 {
+            paint(out, options.color, GREEN, "+".repeat(plus).as_bytes());
+        }
+        if minus > 0 {
+            paint(out, options.color, RED, "-".repeat(minus).as_bytes());
+        }
+        out.push(b'\n');
+    }
+
+    let plural = |n: usize, one: &str, many: &str| match n {
+        1 => format!("{} {}", n, one),
+        n => format!("{} {}", n, many),
+    };
+    let mut summary = format!(" {}", plural(files, "file changed", "files changed"));
+    if insertions > 0 || deletions == 0 {
+        summary += &format!(", {}", plural(insertions, "insertion(+)", "insertions(+)"));
+    }
+    if deletions > 0 || insertions == 0 {
+        summary += &format!(", {}", plural(deletions, "deletion(-)", "deletions(-)"));
+    }
+    out.extend_from_slice(summary.as_bytes());
+    out.push(b'\n');
+}
+
+/// Lines added and deleted between the sides of a pair, None if either side
+/// is binary.
+pub fn line_counts(pair: &FilePair, options: &DiffOptions) -> Option<(usize, usize)> {
+    let data = |side: &Option<DiffSide>| side.as_ref().map_or(vec![], |s| s.data.clone());
+    let (old, new) = (data(&pair.old), data(&pair.new));
+    if is_binary(&old) || is_binary(&new) {
+        return None;
+    }
+    Some(
+        diff_lines(&old, &new, options)
+            .iter()
+            .fold((0, 0), |(added, deleted), h| {
+                (added + h.new_len, deleted + h.old_len)
+            }),
+    )
+}
+
+/// Name of a pair in stats, "old => new" for renames and copies with common
+/// leading directories and trailing path left out of braces, as in
+/// "src/{a => b}/main.rs".
+fn stat_name(pair: &FilePair) -> String {
+    let (old, new) = match (&pair.old, &pair.new) {
+        (Some(old), Some(new)) if old.path != new.path => (&old.path, &new.path),
+        (old, new) => return quote_path(&new.as_ref().or(old.as_ref()).unwrap().path),
+    };
+    let (a, b) = (old.as_slice(), new.as_slice());
+    let (old, new) = (quote_path(old), quote_path(new));
+    if old.as_bytes() != a || new.as_bytes() != b {
+        return format!("{} => {}", old, new);
+    }
+
+    // common prefix up to and including a slash.
+    let mut prefix = 0;
+    for (i, (x, y)) in a.iter().zip(b).enumerate() {
+        if x != y {
+            break;
+        }
+        if *x == b'/' {
+            prefix = i + 1;
+        }
+    }
+    // common suffix from a slash on, not overlapping the prefix except for
+    // its slash.
+    let mut suffix = 0;
+    let limit = prefix.saturating_sub(1);
+    let (mut i, mut j) = (a.len(), b.len());
+    while i > limit && j > limit && a[i - 1] == b[j - 1] {
+        if a[i - 1] == b'/' {
+            suffix = a.len() - (i - 1);
+        }
+        i -= 1;
+        j -= 1;
+    }
+    let a_mid = a.len().saturating_sub(prefix + suffix);
+    let b_mid = b.len().saturating_sub(prefix + suffix);
+    match prefix + suffix {
+        0 => format!("{} => {}", old, new),
+        _ => format!(
+            "{}{{{} => {}}}{}",
+            &old[..prefix],
+            &old[prefix..prefix + a_mid],
+            &new[prefix..prefix + b_mid],
+            &old[old.len() - suffix..]
+        ),
+    }
+}
+
+/// Abbreviated SHA for the index line, 7 digits outside of a repository.
+fn abbreviate(repo: Option<&RGitRepository>, sha: &str) -> Result<String> {
+    match repo {
+        Some(repo) if sha != NULL_SHA => repo.abbreviate(sha, 7),
+        _ => Ok(sha[..7].to_string()),
+    }
+}
+
+/// Quotes a path the way git does when it has control characters, quotes,
+/// backslashes or non-ASCII bytes, which are written as octal escapes.
+pub fn quote_path(path: &[u8]) -> String {
+    if !path
+        .iter()
+        .any(|&b| !(0x20..0x7f).contains(&b) || b == b'"' || b == b'\\')
+    {
+        return String::from_utf8_lossy(path).into_owned();
+    }
+    let mut ret = String::from("\"");
+    for &b in path {
+        match b {
+            b'"' => ret += "\\\"",
+            b'\\' => ret += "\\\\",
+            b'\t' => ret += "\\t",
+            b'\n' => ret += "\\n",
+            b'\r' => ret += "\\r",
+            0x07 => ret += "\\a",
+            0x08 => ret += "\\b",
+            0x0b => ret += "\\v",
+            0x0c => ret += "\\f",
+            b if !(0x20..0x7f).contains(&b) => ret += &format!("\\{:03o}", b),
+            b => ret.push(b as char),
+        }
+    }
+    ret.push('"');
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{blob_sha, patch_write, quote_path, DiffSide, FilePair};
+    use crate::diff::DiffOptions;
+
+    fn side(path: &str, mode: u32, data: &[u8]) -> DiffSide {
+        DiffSide {
+            path: path.into(),
+            mode,
+            sha: blob_sha(data),
+            data: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_patch_write() {
+        let mut out = vec![];
+        let pair = FilePair::new(
+            Some(side("a.txt", 0o100644, b"one\ntwo\n")),
+            Some(side("a.txt", 0o100755, b"one\n2\n")),
+        )
+        .unwrap();
+        patch_write(&mut out, None, &pair, &DiffOptions::default()).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "diff --git a/a.txt b/a.txt\nold mode 100644\nnew mode 100755\n\
+             index 814f4a4..99b356d\n--- a/a.txt\n+++ b/a.txt\n\
+             @@ -1,2 +1,2 @@\n one\n-two\n+2\n"
+        );
+
+        let mut out = vec![];
+        let pair = FilePair::new(None, Some(side("empty", 0o100644, b""))).unwrap();
+        patch_write(&mut out, None, &pair, &DiffOptions::default()).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "diff --git a/empty b/empty\nnew file mode 100644\nindex 0000000..e69de29\n"
+        );
+    }
+
+    #[test]
+    fn test_quote_path() {
+        assert_eq!(quote_path(b"a/plain name"), "a/plain name");
+        assert_eq!(quote_path("a/tést".as_bytes()), "\"a/t\\303\\251st\"");
+        assert_eq!(quote_path(b"a/caf\xe9"), "\"a/caf\\351\"");
+        assert_eq!(quote_path(b"a/\"q\"\t"), "\"a/\\\"q\\\"\\t\"");
+    }
+}
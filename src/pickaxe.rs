@@ -0,0 +1,139 @@
+use anyhow::{bail, Context};
+use regex::bytes::{Regex, RegexBuilder};
+
+use crate::{
+    diff::{diff_lines, is_binary, lines, DiffOptions},
+    patch::{DiffSide, FilePair},
+    Result,
+};
+
+/// What makes a file pair interesting to log -S and -G.
+#[derive(Debug, Clone)]
+pub enum Pickaxe {
+    /// The number of occurrences of a string differs between the sides.
+    Count(Regex),
+    /// An added or removed line matches an extended regular expression.
+    Grep(Regex),
+}
+
+impl Pickaxe {
+    /// Pickaxe of -S, counting string.
+    pub fn count(string: &str, ignore_case: bool) -> Result<Self> {
+        if string.is_empty() {
+            bail!("-S needs a string to look for");
+        }
+        let regex = RegexBuilder::new(&regex::escape(string))
+            .case_insensitive(ignore_case)
+            .build()?;
+        Ok(Pickaxe::Count(regex))
+    }
+
+    /// Pickaxe of -G, where "^" and "$" match at line boundaries.
+    pub fn grep(pattern: &str, ignore_case: bool) -> Result<Self> {
+        let regex = RegexBuilder::new(pattern)
+            .case_insensitive(ignore_case)
+            .multi_line(true)
+            .build()
+            .with_context(|| format!("invalid regular expression: {}", pattern))?;
+        Ok(Pickaxe::Grep(regex))
+    }
+
+    /// Pairs the pickaxe finds, or all of them if all is set and it finds
+    /// one.
+    pub fn filter(&self, pairs: Vec<FilePair>, all: bool, options: &DiffOptions) -> Vec<FilePair> {
+        match all {
+            true if pairs.iter().any(|pair| self.matches(pair, options)) => pairs,
+            true => vec![],
+            false => pairs
+                .into_iter()
+                .filter(|pair| self.matches(pair, options))
+                .collect(),
+        }
+    }
+
+    fn matches(&self, pair: &FilePair, options: &DiffOptions) -> bool {
+        let (old, new) = (data(&pair.old), data(&pair.new));
+        match self {
+            Pickaxe::Count(regex) => {
+                let count = |data: Option<&[u8]>| data.map_or(0, |d| regex.find_iter(d).count());
+                count(old) != count(new)
+            }
+            // -G skips binary files, which have no lines to match.
+            Pickaxe::Grep(_) if old.map_or(false, is_binary) || new.map_or(false, is_binary) => {
+                false
+            }
+            Pickaxe::Grep(regex) => match (old, new) {
+                (Some(old), Some(new)) => {
+                    let (old_lines, new_lines) = (lines(old), lines(new));
+                    let matched =
+                        |line: &[u8]| regex.is_match(line.strip_suffix(b"\n").unwrap_or(line));
+                    diff_lines(old, new, options).iter().any(|hunk| {
+                        old_lines[hunk.old_start..hunk.old_start + hunk.old_len]
+                            .iter()
+                            .chain(&new_lines[hunk.new_start..hunk.new_start + hunk.new_len])
+                            .any(|line| matched(line))
+                    })
+                }
+                // a whole file added or removed is searched at once.
+                (Some(data), None) | (None, Some(data)) => regex.is_match(data),
+                (None, None) => false,
+            },
+        }
+    }
+}
+
+/// Content of a side, None where the file is missing.
+fn data(side: &Option<DiffSide>) -> Option<&[u8]> {
+    side.as_ref().map(|s| &s.data[..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Pickaxe;
+    use crate::{
+        diff::DiffOptions,
+        patch::{blob_sha, DiffSide, FilePair},
+    };
+
+    fn pair(old: Option<&[u8]>, new: Option<&[u8]>) -> FilePair {
+        let side = |data: &[u8]| DiffSide {
+            path: b"a.txt".to_vec(),
+            mode: 0o100644,
+            sha: blob_sha(data),
+            data: data.to_vec(),
+        };
+        FilePair::new(old.map(side), new.map(side)).unwrap()
+    }
+
+    #[test]
+    fn test_pickaxe() {
+        let options = DiffOptions::default();
+        let found = |pickaxe: &Pickaxe, old, new| {
+            !pickaxe
+                .filter(vec![pair(old, new)], false, &options)
+                .is_empty()
+        };
+
+        let count = Pickaxe::count("foo", false).unwrap();
+        assert!(found(&count, Some(b"bar\n"), Some(b"foo\n")));
+        assert!(found(&count, Some(b"foo\n"), None));
+        // moving a line keeps the count.
+        assert!(!found(&count, Some(b"foo\nbar\n"), Some(b"bar\nfoo\n")));
+        assert!(!found(&count, Some(b"foo\n"), Some(b"Foo\nfoo\n")));
+        let count = Pickaxe::count("foo", true).unwrap();
+        assert!(!found(&count, Some(b"foo\n"), Some(b"FOO\n")));
+        assert!(Pickaxe::count("", false).is_err());
+
+        let grep = Pickaxe::grep("^fo+$", false).unwrap();
+        assert!(found(&grep, Some(b"foo\nbar\n"), Some(b"bar\nfoo\n")));
+        assert!(!found(&grep, Some(b"a\nfoo\n"), Some(b"b\nfoo\n")));
+        assert!(found(&grep, None, Some(b"bar\nfoo\n")));
+        assert!(!found(&grep, Some(b"foo\0\n"), Some(b"bar\n")));
+
+        let all = |all| {
+            let pairs = vec![pair(None, Some(b"foo\n")), pair(None, Some(b"bar\n"))];
+            count.filter(pairs, all, &options).len()
+        };
+        assert_eq!((all(false), all(true)), (1, 2));
+    }
+}
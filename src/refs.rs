@@ -0,0 +1,164 @@
+use anyhow::{bail, Context};
+use indexmap::IndexMap;
+use std::{fs, io::Write, path::Path};
+
+use crate::{commit::Signature, repository::RGitRepository, Result};
+
+/// SHA standing for a missing object, like the old value of a new reference.
+pub(crate) const NULL_SHA: &str = "0000000000000000000000000000000000000000";
+
+/// Reads the reference file (e.g. "HEAD" or "refs/heads/master") and follows
+/// "ref: " indirections until a SHA is found.
+/// Returns None if reference does not exist yet, like HEAD of a fresh repository.
+pub fn ref_resolve(repo: &RGitRepository, reference: &str) -> Result<Option<String>> {
+    let path = repo.repo_path(&reference.split('/').collect::<Vec<_>>());
+    if !path.is_file() {
+        // refs moved out by gc live only in packed-refs.
+        return Ok(packed_refs(repo)?.get(reference).cloned());
+    }
+
+    let data = fs::read(&path).with_context(|| format!("could not read {:?}", path))?;
+    let data = String::from_utf8_lossy(&data);
+    let data = data.trim_end();
+
+    if let Some(target) = data.strip_prefix("ref: ") {
+        return ref_resolve(repo, target);
+    }
+    // the SHA may be followed by more, like branch names in FETCH_HEAD.
+    let sha = data.split(char::is_whitespace).next().unwrap_or_default();
+    match sha.len() == 40 && sha.chars().all(|c| c.is_ascii_hexdigit()) {
+        true => Ok(Some(sha.to_lowercase())),
+        false => bail!(
+            "reference {} is not a SHA or a symbolic reference",
+            reference
+        ),
+    }
+}
+
+/// Returns name of the branch HEAD points at, None if HEAD is detached.
+pub fn head_branch(repo: &RGitRepository) -> Result<Option<String>> {
+    let head = fs::read_to_string(repo.repo_path(&["HEAD"])).context("could not read HEAD")?;
+    Ok(head
+        .trim_end()
+        .strip_prefix("ref: refs/heads/")
+        .map(String::from))
+}
+
+/// Lists all references under given prefix (e.g. "refs/tags"), sorted by name.
+/// Keys are full reference names, values are resolved SHAs.
+pub fn ref_list(repo: &RGitRepository, prefix: &str) -> Result<IndexMap<String, String>> {
+    let mut ret: IndexMap<String, String> = packed_refs(repo)?
+        .into_iter()
+        .filter(|(name, _)| name.starts_with(&format!("{}/", prefix)))
+        .collect();
+    // loose refs take precedence over packed ones.
+    let path = repo.repo_path(&prefix.split('/').collect::<Vec<_>>());
+    if path.is_dir() {
+        ref_list_dir(repo, &path, prefix, &mut ret)?;
+    }
+    ret.sort_keys();
+    Ok(ret)
+}
+
+fn ref_list_dir(
+    repo: &RGitRepository,
+    path: &Path,
+    prefix: &str,
+    ret: &mut IndexMap<String, String>,
+) -> Result<()> {
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let name = format!("{}/{}", prefix, entry.file_name().to_string_lossy());
+        if entry.file_type()?.is_dir() {
+            ref_list_dir(repo, &entry.path(), &name, ret)?;
+        } else if let Some(sha) = ref_resolve(repo, &name)? {
+            ret.insert(name, sha);
+        }
+    }
+    Ok(())
+}
+
+/// Points reference (e.g. "refs/tags/v1.0") at given SHA, creating missing directories.
+pub fn ref_create(repo: &RGitRepository, reference: &str, sha: &str) -> Result<()> {
+    if reference
+        .split('/')
+        .any(|p| p.is_empty() || p == "." || p == "..")
+    {
+        bail!("invalid reference name {}", reference);
+    }
+    let path = repo
+        .repo_file(&reference.split('/').collect::<Vec<_>>(), Some(true))
+        .context("could not create path for reference")?;
+    fs::write(path, format!("{}\n", sha))?;
+    Ok(())
+}
+
+/// Reads .git/packed-refs, a list of "<sha> <refname>" lines. Peeled tag lines
+/// starting with '^' and comments are skipped.
+pub fn packed_refs(repo: &RGitRepository) -> Result<IndexMap<String, String>> {
+    let path = repo.repo_path(&["packed-refs"]);
+    if !path.is_file() {
+        return Ok(IndexMap::new());
+    }
+
+    let mut ret = IndexMap::new();
+    for line in fs::read_to_string(path)?.lines() {
+        if line.starts_with('#') || line.starts_with('^') || line.is_empty() {
+            continue;
+        }
+        let (sha, name) = line
+            .split_once(' ')
+            .with_context(|| format!("malformed packed-refs line {}", line))?;
+        ret.insert(name.to_string(), sha.to_string());
+    }
+    Ok(ret)
+}
+
+/// Appends an entry to the reflog of reference, e.g. "HEAD" or "refs/heads/master".
+pub fn reflog_append(
+    repo: &RGitRepository,
+    reference: &str,
+    old: Option<&str>,
+    new: &str,
+    who: &Signature,
+    message: &str,
+) -> Result<()> {
+    let mut path = vec!["logs"];
+    path.extend(reference.split('/'));
+    let path = repo
+        .repo_file(&path, Some(true))
+        .context("could not create path for reflog")?;
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    writeln!(
+        file,
+        "{} {} {}\t{}",
+        old.unwrap_or(NULL_SHA),
+        new,
+        who,
+        message
+    )?;
+    Ok(())
+}
+
+/// Moves the branch HEAD points at, or HEAD itself when detached, from old to
+/// new and records it in the reflogs.
+pub fn head_update(
+    repo: &RGitRepository,
+    old: Option<&str>,
+    new: &str,
+    who: &Signature,
+    message: &str,
+) -> Result<()> {
+    match head_branch(repo)? {
+        Some(branch) => {
+            let reference = format!("refs/heads/{}", branch);
+            ref_create(repo, &reference, new)?;
+            reflog_append(repo, &reference, old, new, who, message)?;
+        }
+        None => fs::write(repo.repo_path(&["HEAD"]), format!("{}\n", new))?,
+    }
+    reflog_append(repo, "HEAD", old, new, who, message)
+}
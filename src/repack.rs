@@ -0,0 +1,252 @@
+use anyhow::Context;
+use indexmap::IndexMap;
+use std::{collections::HashSet, fs, path::Path};
+
+use crate::{
+    index::Index,
+    object::GitObjectType,
+    pack::{pack_indexes, pack_write, PackEntry},
+    refs::{packed_refs, ref_list, ref_resolve, NULL_SHA},
+    repository::RGitRepository,
+    Result,
+};
+
+/// Lists every object reachable from HEAD, refs, the index and reflogs together
+/// with the path it was first reached through, without recursion so long
+/// histories do not overflow.
+pub fn objects_reachable(repo: &RGitRepository) -> Result<IndexMap<String, Vec<u8>>> {
+    let mut ret = IndexMap::new();
+    let mut stack: Vec<(String, Vec<u8>)> =
+        roots(repo)?.into_iter().map(|s| (s, "".into())).collect();
+    while let Some((sha, path)) = stack.pop() {
+        if ret.contains_key(&sha) {
+            continue;
+        }
+        let object = repo.object_read(&sha)?;
+        ret.insert(sha.clone(), path);
+
+        match object.object_type.context("object type is None")? {
+            GitObjectType::Commit => {
+                let commit = object.commit()?;
+                stack.push((commit.tree, "".into()));
+                stack.extend(commit.parents.into_iter().map(|p| (p, "".into())));
+            }
+            GitObjectType::Tree => {
+                for leaf in object.tree.context("tree is empty")? {
+                    // gitlinks point at commits of other repositories.
+                    if leaf.mode != "160000" {
+                        stack.push((leaf.sha, leaf.path));
+                    }
+                }
+            }
+            GitObjectType::Tag => stack.push((object.tag_target()?, "".into())),
+            GitObjectType::Blob => {}
+        }
+    }
+    Ok(ret)
+}
+
+/// Objects reachability starts from. Those the index and reflogs name are kept
+/// too, so staged content and amended or reset commits survive. Those naming
+/// objects already gone are skipped, as git does for reflogs.
+fn roots(repo: &RGitRepository) -> Result<Vec<String>> {
+    let mut ret: Vec<String> = ref_list(repo, "refs")?.into_values().collect();
+    ret.extend(ref_resolve(repo, "HEAD")?);
+
+    // gitlinks point at commits of other repositories.
+    let mut named: Vec<String> = Index::read(repo)?
+        .entries
+        .into_iter()
+        .filter(|e| e.mode != 0o160000)
+        .map(|e| e.sha)
+        .collect();
+    let logs = repo.repo_path(&["logs"]);
+    if logs.is_dir() {
+        reflog_shas(&logs, &mut named)?;
+    }
+    named.sort();
+    named.dedup();
+    ret.extend(
+        named
+            .into_iter()
+            .filter(|sha| sha != NULL_SHA && repo.object_read_raw(sha).is_ok()),
+    );
+    Ok(ret)
+}
+
+/// Collects old and new values of entries of the reflogs under path.
+fn reflog_shas(path: &Path, ret: &mut Vec<String>) -> Result<()> {
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            reflog_shas(&entry.path(), ret)?;
+            continue;
+        }
+        for line in fs::read_to_string(entry.path())?.lines() {
+            ret.extend(line.split(' ').take(2).map(String::from));
+        }
+    }
+    Ok(())
+}
+
+/// Packs reachable objects. Without `all` only loose objects are packed, with it
+/// everything reachable goes to a single new pack replacing the old ones.
+/// With `delete` packs and loose objects made redundant are removed.
+/// Returns name of the new pack, None if there was nothing to pack.
+pub fn repack(
+    repo: &RGitRepository,
+    all: bool,
+    delete: bool,
+    window: usize,
+    depth: usize,
+) -> Result<Option<String>> {
+    let old_packs = pack_indexes(repo)?;
+    let reachable = objects_reachable(repo)?;
+
+    let mut entries = vec![];
+    for (sha, path) in &reachable {
+        if !all && !is_loose(repo, sha) {
+            continue;
+        }
+        let (object_type, data) = repo.object_read_raw(sha)?;
+        entries.push(PackEntry {
+            sha: sha.clone(),
+            object_type,
+            data,
+            path: path.clone(),
+        });
+    }
+    if entries.is_empty() {
+        return Ok(None);
+    }
+
+    let packed: HashSet<String> = entries.iter().map(|e| e.sha.clone()).collect();
+    let name = pack_write(repo, entries, window, depth)?;
+    repo.packs_reload();
+
+    if delete {
+        if all {
+            for pack in old_packs {
+                if pack.name() != name {
+                    pack.remove()?;
+                }
+            }
+            repo.packs_reload();
+        }
+        prune_packed(repo, &packed)?;
+    }
+    Ok(Some(name))
+}
+
+fn is_loose(repo: &RGitRepository, sha: &str) -> bool {
+    repo.repo_path(&["objects", &sha[..2], &sha[2..]]).is_file()
+}
+
+/// Removes loose copies of objects that were packed, and directories left empty.
+fn prune_packed(repo: &RGitRepository, packed: &HashSet<String>) -> Result<()> {
+    for sha in packed {
+        let path = repo.repo_path(&["objects", &sha[..2], &sha[2..]]);
+        if path.is_file() {
+            fs::remove_file(&path)?;
+            let dir = path.parent().context("loose object without directory")?;
+            if fs::read_dir(dir)?.next().is_none() {
+                fs::remove_dir(dir)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Moves all refs into .git/packed-refs, with peeled values of annotated tags,
+/// and deletes the loose ref files. Symbolic refs are kept as files.
+pub fn pack_refs(repo: &RGitRepository) -> Result<()> {
+    let mut refs = packed_refs(repo)?;
+    let mut loose = vec![];
+    for (name, sha) in ref_list(repo, "refs")? {
+        let path = repo.repo_path(&name.split('/').collect::<Vec<_>>());
+        if path.is_file() {
+            if fs::read_to_string(&path)?.starts_with("ref: ") {
+                continue;
+            }
+            loose.push(path);
+        }
+        refs.insert(name, sha);
+    }
+    refs.sort_keys();
+
+    let mut content = String::from("# pack-refs with: peeled fully-peeled sorted \n");
+    for (name, sha) in &refs {
+        content += &format!("{} {}\n", sha, name);
+        if repo.object_read(sha)?.object_type == Some(GitObjectType::Tag) {
+            let peeled = repo.object_find(&format!("{}^{{}}", sha), None, None)?;
+            content += &format!("^{}\n", peeled);
+        }
+    }
+
+    let path = repo
+        .repo_file(&["packed-refs"], None)
+        .context("could not create packed-refs")?;
+    fs::write(path, content)?;
+    for path in loose {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::repack;
+    use crate::{
+        index::Index,
+        pack::pack_indexes,
+        refs::{ref_create, reflog_append},
+        testing::{entry, signature, TempRepo},
+    };
+
+    #[test]
+    fn test_repack() {
+        let repo = TempRepo::new("repack");
+        let commit = |data: &str, parents: &[&str]| {
+            let tree = repo.tree(&[("a", data)]);
+            repo.commit(&tree).parents(parents).message(data).write()
+        };
+
+        let first = commit("one", &[]);
+        let second = commit("two", &[&first]);
+        ref_create(&repo, "refs/heads/master", &second).unwrap();
+        // only the reflog remembers a commit amended away, only the index
+        // a staged blob.
+        let amended = commit("amended", &[&first]);
+        reflog_append(
+            &repo,
+            "HEAD",
+            Some(&amended),
+            &second,
+            &signature(1),
+            "amend",
+        )
+        .unwrap();
+        let staged = repo.blob("staged");
+        Index {
+            version: 2,
+            entries: vec![entry("a", &staged, 0)],
+        }
+        .write(&repo)
+        .unwrap();
+        let garbage = repo.blob("garbage");
+
+        let loose = |sha: &str| {
+            let objects = repo.dir().join(".git/objects");
+            objects.join(&sha[..2]).join(&sha[2..]).is_file()
+        };
+        let kept = [&first, &second, &amended, &staged];
+        assert!(repack(&repo, false, true, 10, 50).unwrap().is_some());
+        assert!(kept.iter().all(|sha| !loose(sha)));
+        assert!(repack(&repo, true, true, 10, 50).unwrap().is_some());
+        assert_eq!(pack_indexes(&repo).unwrap().len(), 1);
+        assert!(kept.iter().all(|sha| repo.object_read_raw(sha).is_ok()));
+        // unreachable objects are neither packed nor deleted.
+        assert!(loose(&garbage));
+        assert_eq!(repack(&repo, false, true, 10, 50).unwrap(), None);
+    }
+}
@@ -2,18 +2,28 @@ use anyhow::{bail, Context, Ok};
 use configparser::ini::Ini;
 use flate2::read::ZlibDecoder;
 
-use crate::{object::GitObject, Result};
+use crate::{
+    object::{object_parse, GitObject, GitObjectType},
+    pack::{pack_indexes, pack_object_read, PackIndex},
+    refs::ref_resolve,
+    revparse::rev_parse,
+    Result,
+};
 use std::{
+    cell::RefCell,
     collections::HashMap,
     fs::{self, File},
-    io::Read,
-    path::{Path, PathBuf},
+    io::{Read, Write},
+    path::{Component, Path, PathBuf},
+    rc::Rc,
+    str::FromStr,
 };
 
 pub struct RGitRepository {
     work_tree: PathBuf,
     git_dir: PathBuf,
     conf: HashMap<String, HashMap<String, Option<String>>>,
+    packs: RefCell<Option<Rc<Vec<PackIndex>>>>,
 }
 
 impl RGitRepository {
@@ -30,6 +40,7 @@ impl RGitRepository {
             git_dir,
             work_tree: path.to_path_buf(),
             conf: HashMap::default(),
+            packs: RefCell::default(),
         };
 
         // ead configuration file in .git/config
@@ -62,8 +73,46 @@ impl RGitRepository {
         Ok(rgit_repo)
     }
 
+    pub fn work_tree(&self) -> &Path {
+        &self.work_tree
+    }
+
+    pub fn git_dir(&self) -> &Path {
+        &self.git_dir
+    }
+
+    /// Converts path relative to current directory into '/' separated path relative
+    /// to the work tree. Path does not have to exist.
+    pub fn relative_path(&self, path: &Path) -> Result<String> {
+        let work_tree = fs::canonicalize(&self.work_tree)?;
+        let mut absolute = PathBuf::new();
+        for component in std::env::current_dir()?.join(path).components() {
+            match component {
+                Component::ParentDir => {
+                    absolute.pop();
+                }
+                Component::CurDir => {}
+                c => absolute.push(c),
+            }
+        }
+
+        let relative = absolute
+            .strip_prefix(&work_tree)
+            .with_context(|| format!("{:?} is outside repository", path))?;
+        Ok(relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/"))
+    }
+
+    /// Returns value of `key` in `section` of the repository configuration.
+    pub fn config_get(&self, section: &str, key: &str) -> Option<String> {
+        self.conf.get(section)?.get(key)?.clone()
+    }
+
     /// Computes path under repo's gitdir.
-    fn repo_path(&self, path: &[&str]) -> PathBuf {
+    pub(crate) fn repo_path(&self, path: &[&str]) -> PathBuf {
         let mut path_buf = self.git_dir.to_path_buf();
         path_buf.extend(path);
         return path_buf;
@@ -86,7 +135,7 @@ impl RGitRepository {
     }
 
     /// Same as repo_path, but mkdir *path if absent if mkdir.
-    fn repo_dir(&self, path: &[&str], mkdir: Option<bool>) -> Result<Option<PathBuf>> {
+    pub(crate) fn repo_dir(&self, path: &[&str], mkdir: Option<bool>) -> Result<Option<PathBuf>> {
         let path = self.repo_path(path);
 
         debug!(
@@ -118,28 +167,167 @@ impl RGitRepository {
     /// “objects” directory in the gitdir. That is, the path to e673d1b7eaa0aa01b5bc2442d570a765bdaae751 is
     /// .git/objects/e6/73d1b7eaa0aa01b5bc2442d570a765bdaae751.
     pub fn object_read(&self, sha: &str) -> Result<GitObject> {
-        match self.repo_file(&vec!["objects", &sha[0..2], &sha[2..]], None) {
-            Some(path) => {
-                debug!("object_read - path: {:?}", path);
-                let mut z = ZlibDecoder::new(File::open(path).context("could not open a file")?);
-                let mut s = String::new();
-                z.read_to_string(&mut s)
-                    .context("could not read to string")?;
-
-                GitObject::object_read(s, self)
+        let (object_type, data) = self.object_read_raw(sha)?;
+        GitObject::new(self, Some(data), Some(object_type))
+    }
+
+    /// Reads type and content of the object, from a loose file or any of the packs.
+    pub(crate) fn object_read_raw(&self, sha: &str) -> Result<(GitObjectType, Vec<u8>)> {
+        if sha.len() != 40 {
+            bail!("invalid object id {}", sha);
+        }
+        let path = self.repo_path(&["objects", &sha[0..2], &sha[2..]]);
+        if path.is_file() {
+            debug!("object_read - path: {:?}", path);
+            let mut z = ZlibDecoder::new(File::open(path).context("could not open a file")?);
+            let mut raw = vec![];
+            z.read_to_end(&mut raw).context("could not read object")?;
+
+            return object_parse(&raw);
+        }
+
+        pack_object_read(self, &self.packs()?, sha)?
+            .with_context(|| format!("object {} not found", sha))
+    }
+
+    /// Pack indexes, loaded once per repository.
+    fn packs(&self) -> Result<Rc<Vec<PackIndex>>> {
+        if let Some(packs) = self.packs.borrow().as_ref() {
+            return Ok(packs.clone());
+        }
+        let packs = Rc::new(pack_indexes(self)?);
+        *self.packs.borrow_mut() = Some(packs.clone());
+        Ok(packs)
+    }
+
+    /// Drops cached pack indexes, so packs written or removed since are picked up.
+    pub(crate) fn packs_reload(&self) {
+        *self.packs.borrow_mut() = None;
+    }
+
+    /// Resolves name to object's SHA. Name can be a full or abbreviated hash, HEAD,
+    /// a reference name like "master", "v1.0", "origin/master" or "refs/heads/master",
+    /// or any revision expression understood by rev_parse.
+    ///
+    /// If fmt is given, object is peeled until it matches: tags are followed to the
+    /// object they point at and commits to their tree, unless follow is false.
+    pub fn object_find(
+        &self,
+        name: &str,
+        fmt: Option<String>,
+        follow: Option<bool>,
+    ) -> Result<String> {
+        let follow = follow.unwrap_or(true);
+        let mut sha = rev_parse(self, name)?;
+
+        let fmt = match fmt {
+            Some(fmt) => GitObjectType::from_str(&fmt)?,
+            None => return Ok(sha),
+        };
+
+        loop {
+            let object = self.object_read(&sha)?;
+            let object_type = object.object_type.context("object type is None")?;
+            if object_type == fmt {
+                return Ok(sha);
+            }
+            if !follow {
+                bail!(
+                    "{} is a {}, not a {}",
+                    name,
+                    object_type.to_string(),
+                    fmt.to_string()
+                );
+            }
+
+            sha = match (object_type, fmt) {
+                (GitObjectType::Tag, _) => object.tag_target()?,
+                (GitObjectType::Commit, GitObjectType::Tree) => object.commit()?.tree,
+                _ => bail!("{} cannot be peeled to a {}", name, fmt.to_string()),
+            };
+        }
+    }
+
+    /// Resolves name to a single SHA without peeling, following git's order:
+    /// the name itself under gitdir (HEAD, refs/heads/x), then refs/tags,
+    /// refs/heads and refs/remotes, and finally abbreviated object hashes.
+    /// Only all-caps pseudo-refs like ORIG_HEAD and full reference names are
+    /// looked up as they are, so a branch named "config" is not the file.
+    pub(crate) fn object_resolve(&self, name: &str) -> Result<String> {
+        if name.is_empty() {
+            bail!("empty object name");
+        }
+        if name.len() == 40 && name.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Ok(name.to_lowercase());
+        }
+
+        let pseudo = name.chars().all(|c| c.is_ascii_uppercase() || c == '_');
+        let exact = match pseudo || name.starts_with("refs/") {
+            true => Some(name.to_string()),
+            false => None,
+        };
+        for candidate in exact.into_iter().chain([
+            format!("refs/{}", name),
+            format!("refs/tags/{}", name),
+            format!("refs/heads/{}", name),
+            format!("refs/remotes/{}", name),
+            format!("refs/remotes/{}/HEAD", name),
+        ]) {
+            if let Some(sha) = ref_resolve(self, &candidate)? {
+                return Ok(sha);
+            }
+        }
+
+        if name.len() >= 4 && name.chars().all(|c| c.is_ascii_hexdigit()) {
+            let mut candidates = self.object_candidates(&name.to_lowercase())?;
+            match candidates.len() {
+                0 => {}
+                1 => return Ok(candidates.remove(0)),
+                _ => bail!(
+                    "short SHA {} is ambiguous, candidates are:\n{}",
+                    name,
+                    candidates.join("\n")
+                ),
+            }
+        }
+
+        bail!("unknown revision {}", name)
+    }
+
+    /// Returns the shortest unique prefix of sha, at least min_len long.
+    pub fn abbreviate(&self, sha: &str, min_len: usize) -> Result<String> {
+        for len in min_len.max(4)..sha.len() {
+            if self.object_candidates(&sha[..len])?.len() <= 1 {
+                return Ok(sha[..len].to_string());
             }
-            None => bail!("object not found"),
         }
+        Ok(sha.to_string())
     }
 
-    pub fn object_find(&self, name: &str, fmt: Option<String>, follow: Option<bool>) -> String {
-        name.to_string()
+    /// Lists SHAs of stored objects, loose and packed, starting with given hex prefix.
+    fn object_candidates(&self, prefix: &str) -> Result<Vec<String>> {
+        let mut ret = vec![];
+        let dir = self.repo_path(&["objects", &prefix[..2]]);
+        if dir.is_dir() {
+            for entry in fs::read_dir(dir)? {
+                let rest = entry?.file_name().to_string_lossy().to_string();
+                if rest.starts_with(&prefix[2..]) {
+                    ret.push(format!("{}{}", &prefix[..2], rest));
+                }
+            }
+        }
+        for pack in self.packs()?.iter() {
+            ret.extend(pack.find_prefix(prefix));
+        }
+        ret.sort();
+        ret.dedup();
+        Ok(ret)
     }
 
     pub fn cat_file(&self, obj: &str, fmt: Option<String>) -> Result<()> {
-        let object = self.object_read(&self.object_find(obj, fmt, None))?;
+        let object = self.object_read(&self.object_find(obj, fmt, None)?)?;
         debug!("cat_file - object found");
-        println!("{}", object.serialize());
+        std::io::stdout().write_all(&object.serialize()?)?;
         Ok(())
     }
 }
@@ -224,3 +412,88 @@ pub fn repo_find<P: AsRef<Path>>(
     }
     return repo_find(Some(parent), Some(required));
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{patch::blob_sha, refs::ref_create, testing::TempRepo};
+    use std::{collections::HashMap, fs};
+
+    #[test]
+    fn test_object_find() {
+        let repo = TempRepo::new("object-find");
+        // two blobs sharing the first four digits.
+        let mut seen = HashMap::new();
+        let (one, two) = (0..)
+            .find_map(|n| {
+                let data = n.to_string();
+                let sha = blob_sha(data.as_bytes());
+                seen.insert(sha[..4].to_string(), data.clone())
+                    .map(|other| (repo.blob(&other), repo.blob(&data)))
+            })
+            .unwrap();
+        let three = repo.blob("three");
+        for (name, sha) in [
+            ("refs/heads/master", &one),
+            ("refs/heads/v1", &one),
+            ("refs/tags/v1", &two),
+            ("refs/heads/config", &three),
+            ("refs/heads/index", &three),
+            ("refs/remotes/origin/HEAD", &two),
+            ("ORIG_HEAD", &three),
+        ] {
+            ref_create(&repo, name, sha).unwrap();
+        }
+        fs::write(repo.dir().join(".git/index"), b"DIRC").unwrap();
+        fs::write(repo.dir().join(".git/refs/heads/broken"), "not a sha\n").unwrap();
+
+        let find = |name: &str| repo.object_find(name, None, None).ok();
+        let unique = (0..40)
+            .find(|&i| one.as_bytes()[i] != two.as_bytes()[i])
+            .unwrap()
+            + 1;
+        let results = [
+            "HEAD",
+            "master",
+            "heads/master",
+            "refs/heads/master",
+            "v1",
+            "heads/v1",
+            "config",
+            "index",
+            "origin",
+            "ORIG_HEAD",
+            &one[..4],
+            &one[..unique],
+            &one.to_uppercase(),
+            "broken",
+            "description",
+        ]
+        .map(find);
+        let ambiguous = repo.object_find(&one[..4], None, None).unwrap_err();
+
+        let [one, two, three] = [&one, &two, &three].map(|sha| Some(sha.clone()));
+        assert_eq!(
+            results,
+            [
+                one.clone(),
+                one.clone(),
+                one.clone(),
+                one.clone(),
+                // tags come before branches.
+                two.clone(),
+                one.clone(),
+                // branches named like files of the git directory.
+                three.clone(),
+                three.clone(),
+                two,
+                three,
+                None,
+                one.clone(),
+                one,
+                None,
+                None,
+            ]
+        );
+        assert!(ambiguous.to_string().contains("ambiguous"));
+    }
+}
@@ -0,0 +1,555 @@
+use anyhow::{bail, Context};
+use std::{
+    collections::{BinaryHeap, HashSet},
+    fs,
+};
+
+use crate::{
+    index::Index,
+    object::GitObjectType,
+    refs::{head_branch, ref_list, ref_resolve},
+    repository::RGitRepository,
+    Result,
+};
+
+/// Single navigation step applied after the base revision.
+#[derive(Debug, PartialEq)]
+enum RevOp {
+    /// `^N`, N-th parent, `^0` is the commit itself.
+    Parent(usize),
+    /// `~N`, N-th generation ancestor following first parents.
+    Ancestor(usize),
+    /// `^{type}`, `^{}` or `^{/text}`.
+    Peel(String),
+}
+
+/// Parsed revision expression.
+#[derive(Debug, PartialEq)]
+enum Rev {
+    /// `<base><ops>[:<path>]`, e.g. `master~2^{tree}` or `HEAD:src/main.rs`.
+    Expr {
+        base: String,
+        ops: Vec<RevOp>,
+        path: Option<String>,
+    },
+    /// `:/text`, youngest commit reachable from any ref with message containing text.
+    MessageSearch(String),
+    /// `:path` or `:N:path`, blob staged in the index.
+    Index { stage: usize, path: String },
+}
+
+/// Revision argument of commands that take ranges, empty sides of ranges
+/// stand for HEAD.
+#[derive(Debug, PartialEq)]
+pub enum RevRange {
+    /// `A`, a single revision.
+    Single(String),
+    /// `A..B`, commits reachable from B but not from A.
+    Between(String, String),
+    /// `A...B`, commits reachable from either A or B but not from both.
+    Symmetric(String, String),
+}
+
+/// Splits revision argument on "..", or "...", outside of braces and before
+/// any `:path`, so `HEAD:a..b` and `:/fix..typo` are single revisions.
+pub fn range_parse(revision: &str) -> RevRange {
+    let or_head = |name: &str| match name.is_empty() {
+        true => "HEAD".to_string(),
+        false => name.to_string(),
+    };
+    let mut depth = 0;
+    for (i, c) in revision.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ':' if depth == 0 => break,
+            '.' if depth == 0 && revision[i..].starts_with("..") => {
+                let from = or_head(&revision[..i]);
+                return match revision[i..].strip_prefix("...") {
+                    Some(to) => RevRange::Symmetric(from, or_head(to)),
+                    None => RevRange::Between(from, or_head(&revision[i + 2..])),
+                };
+            }
+            _ => {}
+        }
+    }
+    RevRange::Single(revision.to_string())
+}
+
+fn parse(expr: &str) -> Result<Rev> {
+    if let Some(rest) = expr.strip_prefix(':') {
+        if let Some(text) = rest.strip_prefix('/') {
+            return Ok(Rev::MessageSearch(text.to_string()));
+        }
+        let bytes = rest.as_bytes();
+        if bytes.len() > 2 && bytes[0].is_ascii_digit() && bytes[1] == b':' {
+            return Ok(Rev::Index {
+                stage: (bytes[0] - b'0') as usize,
+                path: rest[2..].to_string(),
+            });
+        }
+        return Ok(Rev::Index {
+            stage: 0,
+            path: rest.to_string(),
+        });
+    }
+
+    // split "<rev>:<path>" on the first colon outside of braces.
+    let mut depth = 0;
+    let mut split = None;
+    for (i, c) in expr.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ':' if depth == 0 => {
+                split = Some(i);
+                break;
+            }
+            _ => {}
+        }
+    }
+    let (rev, path) = match split {
+        Some(i) => (&expr[..i], Some(expr[i + 1..].to_string())),
+        None => (expr, None),
+    };
+
+    // base ends at the first '^' or '~' outside of "@{...}".
+    let mut depth = 0;
+    let mut base_end = rev.len();
+    for (i, c) in rev.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            '^' | '~' if depth == 0 => {
+                base_end = i;
+                break;
+            }
+            _ => {}
+        }
+    }
+    let base = &rev[..base_end];
+    if base.is_empty() {
+        bail!("revision {} has no base", expr);
+    }
+
+    let mut ops = vec![];
+    let mut rest = &rev[base_end..];
+    while let Some(op) = rest.chars().next() {
+        rest = &rest[op.len_utf8()..];
+        if op == '^' && rest.starts_with('{') {
+            let close = rest
+                .find('}')
+                .with_context(|| format!("unterminated ^{{ in {}", expr))?;
+            ops.push(RevOp::Peel(rest[1..close].to_string()));
+            rest = &rest[close + 1..];
+            continue;
+        }
+
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let n = match digits {
+            0 => 1,
+            _ => rest[..digits].parse()?,
+        };
+        rest = &rest[digits..];
+
+        match op {
+            '^' => ops.push(RevOp::Parent(n)),
+            '~' => ops.push(RevOp::Ancestor(n)),
+            _ => bail!("unexpected {} in revision {}", op, expr),
+        }
+    }
+
+    Ok(Rev::Expr {
+        base: base.to_string(),
+        ops,
+        path,
+    })
+}
+
+/// Evaluates revision expression (`HEAD~3`, `main^2`, `v1.0^{tree}`, `HEAD:src/main.rs`,
+/// `@{-1}`, `branch@{upstream}`, `:/fix typo`, ...) to an object's SHA.
+pub fn rev_parse(repo: &RGitRepository, expr: &str) -> Result<String> {
+    match parse(expr)? {
+        Rev::MessageSearch(text) => {
+            let starts = ref_list(repo, "refs")?.into_values().collect::<Vec<_>>();
+            message_search(repo, starts, &text)
+        }
+        Rev::Index { stage, path } => Index::read(repo)?
+            .entries
+            .into_iter()
+            .find(|e| e.path == path.as_bytes() && e.stage as usize == stage)
+            .map(|e| e.sha)
+            .with_context(|| format!("path {} is not in the index at stage {}", path, stage)),
+        Rev::Expr { base, ops, path } => {
+            let mut sha = resolve_base(repo, &base)?;
+            for op in ops {
+                sha = match op {
+                    RevOp::Parent(0) => repo.object_find(&sha, Some("commit".into()), None)?,
+                    RevOp::Parent(n) => {
+                        let commit = repo.object_read(&repo.object_find(
+                            &sha,
+                            Some("commit".into()),
+                            None,
+                        )?)?;
+                        commit
+                            .commit()?
+                            .parents
+                            .get(n - 1)
+                            .with_context(|| format!("{} has no parent {}", sha, n))?
+                            .clone()
+                    }
+                    RevOp::Ancestor(n) => {
+                        let mut sha = repo.object_find(&sha, Some("commit".into()), None)?;
+                        for _ in 0..n {
+                            let commit = repo.object_read(&sha)?.commit()?;
+                            sha = commit
+                                .parents
+                                .first()
+                                .with_context(|| format!("{} has no parent", sha))?
+                                .clone();
+                        }
+                        sha
+                    }
+                    RevOp::Peel(target) => peel(repo, &sha, &target)?,
+                };
+            }
+
+            match path {
+                Some(path) => tree_lookup(repo, &sha, &path),
+                None => Ok(sha),
+            }
+        }
+    }
+}
+
+/// Resolves base revision, including "@" and "@{...}" reflog and upstream forms.
+fn resolve_base(repo: &RGitRepository, base: &str) -> Result<String> {
+    if base == "@" {
+        return repo.object_resolve("HEAD");
+    }
+
+    let (name, selector) = match base.find("@{") {
+        Some(i) if base.ends_with('}') => (&base[..i], &base[i + 2..base.len() - 1]),
+        _ => return repo.object_resolve(base),
+    };
+
+    if let Some(n) = selector.strip_prefix('-') {
+        if !name.is_empty() {
+            bail!("{} is not allowed after a name in {}", selector, base);
+        }
+        let branch = previous_checkout(repo, n.parse()?)?;
+        return repo.object_resolve(&branch);
+    }
+
+    let branch = || -> Result<String> {
+        match name {
+            "" | "HEAD" => head_branch(repo)?.context("HEAD is detached"),
+            name => Ok(name.to_string()),
+        }
+    };
+
+    match selector {
+        "upstream" | "u" => {
+            let branch = branch()?;
+            let reference = upstream(repo, &branch)?;
+            ref_resolve(repo, &reference)?
+                .with_context(|| format!("upstream {} of {} does not exist", reference, branch))
+        }
+        n => {
+            let n: usize = n
+                .parse()
+                .with_context(|| format!("unsupported selector @{{{}}}", n))?;
+            // HEAD has its own reflog, which also works when it is detached.
+            let reference = match name {
+                "HEAD" => name.to_string(),
+                _ => format!("refs/heads/{}", branch()?),
+            };
+            reflog_entry(repo, &reference, n)
+        }
+    }
+}
+
+/// Peels object to the given type, `^{}` peels tags until a non-tag object is found.
+fn peel(repo: &RGitRepository, sha: &str, target: &str) -> Result<String> {
+    if let Some(text) = target.strip_prefix('/') {
+        let sha = repo.object_find(sha, Some("commit".into()), None)?;
+        return message_search(repo, vec![sha], text);
+    }
+
+    match target {
+        "" => {
+            let mut sha = sha.to_string();
+            loop {
+                let object = repo.object_read(&sha)?;
+                if object.object_type != Some(GitObjectType::Tag) {
+                    return Ok(sha);
+                }
+                sha = object.tag_target()?;
+            }
+        }
+        "object" => {
+            repo.object_read(sha)?;
+            Ok(sha.to_string())
+        }
+        fmt => repo.object_find(sha, Some(fmt.into()), None),
+    }
+}
+
+/// Finds path in the tree of given tree-ish.
+pub fn tree_lookup(repo: &RGitRepository, treeish: &str, path: &str) -> Result<String> {
+    let mut sha = repo.object_find(treeish, Some("tree".into()), None)?;
+    for component in path.split('/').filter(|c| !c.is_empty()) {
+        let tree = repo
+            .object_read(&sha)?
+            .tree
+            .with_context(|| format!("{} is not a tree", sha))?;
+        sha = tree
+            .into_iter()
+            .find(|leaf| leaf.path == component.as_bytes())
+            .with_context(|| format!("path {} does not exist in {}", path, treeish))?
+            .sha;
+    }
+    Ok(sha)
+}
+
+/// Returns youngest commit reachable from starts whose message contains text.
+fn message_search(repo: &RGitRepository, starts: Vec<String>, text: &str) -> Result<String> {
+    let mut seen = HashSet::new();
+    let mut queue = BinaryHeap::new();
+    for sha in starts {
+        // refs may point at tags or trees, only commits are searched.
+        if let Ok(sha) = repo.object_find(&sha, Some("commit".into()), None) {
+            let commit = repo.object_read(&sha)?.commit()?;
+            queue.push((commit.committer.time, sha));
+        }
+    }
+
+    while let Some((_, sha)) = queue.pop() {
+        if !seen.insert(sha.clone()) {
+            continue;
+        }
+        let commit = repo.object_read(&sha)?.commit()?;
+        if commit.message_text().contains(text) {
+            return Ok(sha);
+        }
+        for parent in commit.parents {
+            let time = repo.object_read(&parent)?.commit()?.committer.time;
+            queue.push((time, parent));
+        }
+    }
+    bail!("no commit message matches {}", text)
+}
+
+/// Reads N-th previously checked out branch from HEAD's reflog.
+pub(crate) fn previous_checkout(repo: &RGitRepository, n: usize) -> Result<String> {
+    let log =
+        fs::read_to_string(repo.repo_path(&["logs", "HEAD"])).context("no reflog for HEAD")?;
+    log.lines()
+        .rev()
+        .filter_map(|line| {
+            let (_, message) = line.split_once('\t')?;
+            let rest = message.strip_prefix("checkout: moving from ")?;
+            rest.split_once(" to ").map(|(from, _)| from.to_string())
+        })
+        .nth(n.saturating_sub(1))
+        .with_context(|| format!("only fewer than {} checkouts in reflog", n))
+}
+
+/// Reads N-th prior value of reference from its reflog, 0 is the current value.
+fn reflog_entry(repo: &RGitRepository, reference: &str, n: usize) -> Result<String> {
+    let mut path = vec!["logs"];
+    path.extend(reference.split('/'));
+    let log = fs::read_to_string(repo.repo_path(&path))
+        .with_context(|| format!("no reflog for {}", reference))?;
+    let line = log
+        .lines()
+        .rev()
+        .nth(n)
+        .with_context(|| format!("reflog of {} has only {} entries", reference, n))?;
+    let new = line.split(' ').nth(1).context("malformed reflog entry")?;
+    Ok(new.to_string())
+}
+
+/// Returns remote tracking reference configured as upstream of a branch.
+fn upstream(repo: &RGitRepository, branch: &str) -> Result<String> {
+    // configparser lowercases section names.
+    let section = format!("branch \"{}\"", branch).to_lowercase();
+    let remote = repo
+        .config_get(&section, "remote")
+        .with_context(|| format!("no upstream configured for branch {}", branch))?;
+    let merge = repo
+        .config_get(&section, "merge")
+        .with_context(|| format!("no upstream configured for branch {}", branch))?;
+
+    if remote == "." {
+        return Ok(merge);
+    }
+    let merge = merge.strip_prefix("refs/heads/").unwrap_or(&merge);
+    Ok(format!("refs/remotes/{}/{}", remote, merge))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, range_parse, rev_parse, Rev, RevOp, RevRange};
+    use crate::{
+        index::Index,
+        refs::{ref_create, reflog_append},
+        repository::RGitRepository,
+        testing::{entry, signature, TempRepo},
+    };
+    use std::{fs::OpenOptions, io::Write};
+
+    #[test]
+    fn test_parse_rev() {
+        assert_eq!(
+            parse("main~3^2^{tree}:src/main.rs").unwrap(),
+            Rev::Expr {
+                base: "main".into(),
+                ops: vec![
+                    RevOp::Ancestor(3),
+                    RevOp::Parent(2),
+                    RevOp::Peel("tree".into())
+                ],
+                path: Some("src/main.rs".into()),
+            }
+        );
+        assert_eq!(
+            parse("branch@{upstream}^").unwrap(),
+            Rev::Expr {
+                base: "branch@{upstream}".into(),
+                ops: vec![RevOp::Parent(1)],
+                path: None,
+            }
+        );
+        assert_eq!(
+            parse("HEAD^{/fix: typo}~").unwrap(),
+            Rev::Expr {
+                base: "HEAD".into(),
+                ops: vec![RevOp::Peel("/fix: typo".into()), RevOp::Ancestor(1)],
+                path: None,
+            }
+        );
+        assert_eq!(
+            parse(":/fix typo").unwrap(),
+            Rev::MessageSearch("fix typo".into())
+        );
+        assert_eq!(
+            parse(":2:README").unwrap(),
+            Rev::Index {
+                stage: 2,
+                path: "README".into()
+            }
+        );
+        assert!(parse("^HEAD").is_err());
+        assert!(parse("HEAD^é").is_err());
+        assert!(parse("HEAD~€").is_err());
+    }
+
+    #[test]
+    fn test_range_parse() {
+        let range = |from: &str, to: &str| RevRange::Between(from.into(), to.into());
+        assert_eq!(range_parse("a..b"), range("a", "b"));
+        assert_eq!(range_parse("..b"), range("HEAD", "b"));
+        assert_eq!(range_parse("a.."), range("a", "HEAD"));
+        assert_eq!(
+            range_parse("a...b"),
+            RevRange::Symmetric("a".into(), "b".into())
+        );
+        assert_eq!(range_parse("HEAD^{/a..b}..x"), range("HEAD^{/a..b}", "x"));
+        for single in ["HEAD:a..b", ":/a..b", "HEAD^{/a...b}", "^a"] {
+            assert_eq!(range_parse(single), RevRange::Single(single.into()));
+        }
+    }
+
+    #[test]
+    fn test_rev_parse() {
+        let repo = TempRepo::new("rev-parse");
+        let first = repo
+            .commit(&repo.tree(&[("a.txt", "1")]))
+            .message("first")
+            .write();
+        let second = repo
+            .commit(&repo.tree(&[("a.txt", "2")]))
+            .parents(&[&first])
+            .time(2)
+            .message("second")
+            .write();
+        let side = repo
+            .commit(&repo.tree(&[("b.txt", "b")]))
+            .parents(&[&first])
+            .time(3)
+            .message("side fix")
+            .write();
+        let merge_tree = repo.tree(&[("a.txt", "2"), ("b.txt", "b")]);
+        let merge = repo
+            .commit(&merge_tree)
+            .parents(&[&second, &side])
+            .time(4)
+            .message("merge")
+            .write();
+        ref_create(&repo, "refs/heads/master", &merge).unwrap();
+        ref_create(&repo, "refs/heads/side", &side).unwrap();
+        ref_create(&repo, "refs/remotes/origin/master", &second).unwrap();
+        let log = |reference: &str, old: Option<&str>, new: &str, message: &str| {
+            reflog_append(&repo, reference, old, new, &signature(1), message).unwrap()
+        };
+        log("refs/heads/master", None, &first, "commit");
+        log("refs/heads/master", Some(&first), &second, "commit");
+        log("refs/heads/master", Some(&second), &merge, "merge side");
+        log("HEAD", None, &first, "commit");
+        log(
+            "HEAD",
+            Some(&first),
+            &side,
+            "checkout: moving from master to side",
+        );
+        log(
+            "HEAD",
+            Some(&side),
+            &merge,
+            "checkout: moving from side to master",
+        );
+        let mut config = OpenOptions::new()
+            .append(true)
+            .open(repo.dir().join(".git/config"))
+            .unwrap();
+        writeln!(
+            config,
+            "[branch \"master\"]\n\tremote = origin\n\tmerge = refs/heads/master"
+        )
+        .unwrap();
+        let (staged, ours) = (repo.blob("staged"), repo.blob("ours"));
+        Index {
+            version: 2,
+            entries: vec![entry("a.txt", &staged, 0), entry("b.txt", &ours, 2)],
+        }
+        .write(&repo)
+        .unwrap();
+        // config is read when the repository is opened.
+        let repo = RGitRepository::init(repo.dir(), false).unwrap();
+        let parse = |expr: &str| rev_parse(&repo, expr).unwrap();
+
+        assert_eq!(parse("HEAD~"), second);
+        assert_eq!(parse("HEAD~2"), first);
+        assert_eq!(parse("HEAD^0"), merge);
+        assert_eq!(parse("HEAD^2"), side);
+        assert_eq!(parse("master^2~1"), first);
+        assert!(rev_parse(&repo, "HEAD^3").is_err());
+        assert_eq!(parse("HEAD^{tree}"), merge_tree);
+        assert_eq!(parse("HEAD^{/fix}"), side);
+        assert_eq!(parse(":/second"), second);
+        assert_eq!(parse("HEAD:b.txt"), parse("side:b.txt"));
+        assert_eq!(parse("HEAD^:a.txt"), parse("@{u}:a.txt"));
+        assert_eq!(parse(":a.txt"), staged);
+        assert_eq!(parse(":2:b.txt"), ours);
+        assert!(rev_parse(&repo, ":b.txt").is_err());
+        assert_eq!(parse("@{0}"), merge);
+        assert_eq!(parse("master@{2}"), first);
+        assert_eq!(parse("HEAD@{1}"), side);
+        assert_eq!(parse("@{-1}"), side);
+        assert_eq!(parse("@{upstream}"), second);
+        assert_eq!(parse("master@{u}~"), first);
+    }
+}
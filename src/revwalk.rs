@@ -0,0 +1,963 @@
+use anyhow::Context;
+use regex::{Regex, RegexBuilder};
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet, VecDeque},
+};
+
+use crate::{
+    commit::{Commit, Signature},
+    patch::changes_limit,
+    repository::RGitRepository,
+    revparse::{range_parse, RevRange},
+    treediff::tree_diff,
+    Result,
+};
+
+// flags of commits met by a walk.
+const SEEN: u8 = 1;
+const UNINTERESTING: u8 = 1 << 1;
+// excluded by a revision given, which still counts for simplification.
+const BOTTOM: u8 = 1 << 2;
+// no change to the paths the walk is limited to.
+const TREESAME: u8 = 1 << 3;
+
+// paint of merge base search: reached from either side, below a common
+// ancestor, and found common.
+const PARENT1: u8 = 1;
+const PARENT2: u8 = 1 << 1;
+const STALE: u8 = 1 << 2;
+const RESULT: u8 = 1 << 3;
+
+// commits limiting goes on looking at once only excluded ones are left, in
+// case clock skew hides an interesting one behind them.
+const SLOP: usize = 5;
+
+/// Order in which a walk returns commits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    /// Most recently committed of the commits reached so far first.
+    Default,
+    /// No parent before all its children, otherwise by commit date.
+    Date,
+    /// No parent before all its children, and commits of a line of history
+    /// together.
+    Topo,
+}
+
+/// Settings of which commits a walk returns, and how.
+#[derive(Debug, Clone)]
+pub struct WalkOptions {
+    pub order: Order,
+    /// Follow only the first parent of merges.
+    pub first_parent: bool,
+    /// Return commits oldest first, after counting and skipping them.
+    pub reverse: bool,
+    /// Stop after this many commits.
+    pub max_count: Option<usize>,
+    /// Leave out this many commits first.
+    pub skip: usize,
+    /// Rewrite parents to the nearest ancestors shown, and keep merges
+    /// tying them together, as the graph needs.
+    pub rewrite_parents: bool,
+    pub filter: CommitFilter,
+}
+
+/// Which of the commits walked are returned. Patterns of a kind match if
+/// any of them does, and all kinds given must match.
+#[derive(Debug, Clone, Default)]
+pub struct CommitFilter {
+    /// Patterns of "Name <email>" of the author.
+    pub authors: Vec<Regex>,
+    /// Patterns of "Name <email>" of the committer.
+    pub committers: Vec<Regex>,
+    /// Patterns of lines of the message.
+    pub grep: Vec<Regex>,
+    /// Whether every pattern of grep must match, not any.
+    pub all_match: bool,
+    /// Stop at commits committed before this time.
+    pub since: Option<i64>,
+    /// Leave out commits committed after this time.
+    pub until: Option<i64>,
+    /// Only merges, or only commits that are not.
+    pub merges: Option<bool>,
+    /// Leave out commits not changing any of these paths, and follow only
+    /// the parent a merge took them from when there is one.
+    pub paths: Vec<String>,
+}
+
+impl CommitFilter {
+    /// Whether author, committer and message of commit match.
+    fn matches(&self, commit: &Commit) -> bool {
+        let identity = |signature: &Signature| format!("{} <{}>", signature.name, signature.email);
+        let any = |patterns: &[Regex], text: &str| {
+            patterns.is_empty() || patterns.iter().any(|p| p.is_match(text))
+        };
+        if !any(&self.authors, &identity(&commit.author))
+            || !any(&self.committers, &identity(&commit.committer))
+        {
+            return false;
+        }
+        let grep = |p: &Regex| commit.message_text().lines().any(|line| p.is_match(line));
+        match self.all_match {
+            true => self.grep.iter().all(grep),
+            false => self.grep.is_empty() || self.grep.iter().any(grep),
+        }
+    }
+}
+
+/// Compiles a POSIX basic regular expression, the kind git's --grep,
+/// --author and --committer take: "\(", "\|", "\+" and the like are
+/// operators and "(", "|", "+" literal.
+pub fn basic_regex(pattern: &str, ignore_case: bool) -> Result<Regex> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut regex = String::new();
+    let mut i = 0;
+    // start of the bracket expression inside, if in one.
+    let mut bracket = None;
+    while i < chars.len() {
+        let c = chars[i];
+        i += 1;
+        match (bracket, c) {
+            // "[:alpha:]" and the like are copied whole.
+            (Some(_), '[') if chars.get(i) == Some(&':') => {
+                let end = (i..chars.len().saturating_sub(1))
+                    .find(|&j| chars[j] == ':' && chars[j + 1] == ']')
+                    .map_or(chars.len(), |j| j + 2);
+                regex.extend(&chars[i - 1..end]);
+                i = end;
+                continue;
+            }
+            // "]" first in the brackets is part of them.
+            (Some(start), ']') if i - 1 > start => bracket = None,
+            (Some(_), '\\' | '[') => regex.push('\\'),
+            (None, '[') => bracket = Some(i + (chars.get(i) == Some(&'^')) as usize),
+            (None, '\\') => {
+                match chars.get(i) {
+                    Some(&c) if "(){}|+?".contains(c) => regex.push(c),
+                    Some('<' | '>') => regex.push_str("\\b"),
+                    Some(&c) => {
+                        regex.push('\\');
+                        regex.push(c);
+                    }
+                    None => regex.push('\\'),
+                }
+                i += 1;
+                continue;
+            }
+            (None, '(' | ')' | '{' | '}' | '|' | '+' | '?') => regex.push('\\'),
+            _ => {}
+        }
+        regex.push(c);
+    }
+    RegexBuilder::new(&regex)
+        .case_insensitive(ignore_case)
+        .build()
+        .with_context(|| format!("invalid regular expression: {}", pattern))
+}
+
+/// Best common ancestors of two commits, latest first, as "git merge-base
+/// --all" lists them.
+pub fn merge_bases(repo: &RGitRepository, one: &str, two: &str) -> Result<Vec<String>> {
+    let mut walk = RevWalk::new(
+        repo,
+        WalkOptions {
+            order: Order::Default,
+            first_parent: false,
+            reverse: false,
+            max_count: None,
+            skip: 0,
+            rewrite_parents: false,
+            filter: CommitFilter::default(),
+        },
+    );
+    let (one, two) = (walk.node(one), walk.node(two));
+    Ok(walk
+        .merge_bases(one, two)?
+        .into_iter()
+        .map(|base| walk.nodes[base].sha.clone())
+        .collect())
+}
+
+/// A commit met by the walk, read once its parents are needed.
+struct Node {
+    sha: String,
+    commit: Option<Commit>,
+    /// Nodes of the parents, once read.
+    parents: Vec<usize>,
+    flags: u8,
+}
+
+/// Iterator over commits reachable from some revisions but not from
+/// others, the way git's revision walk goes: latest committed first through
+/// a priority queue, or all of them sorted up front when excluded commits
+/// or topological order need the whole picture.
+pub struct RevWalk<'a> {
+    repo: &'a RGitRepository,
+    options: WalkOptions,
+    nodes: Vec<Node>,
+    index: HashMap<String, usize>,
+    /// Revisions given, in order.
+    tips: Vec<usize>,
+    /// Commits to visit, latest committed first and first queued first
+    /// among equal dates.
+    queue: BinaryHeap<(i64, Reverse<usize>, usize)>,
+    queued: usize,
+    prepared: bool,
+    /// Commits left, when they were all found up front.
+    sorted: Option<VecDeque<usize>>,
+    /// Commits left oldest last, when returning them in reverse.
+    reversed: Option<Vec<usize>>,
+}
+
+impl<'a> RevWalk<'a> {
+    pub fn new(repo: &'a RGitRepository, options: WalkOptions) -> Self {
+        RevWalk {
+            repo,
+            options,
+            nodes: vec![],
+            index: HashMap::new(),
+            tips: vec![],
+            queue: BinaryHeap::new(),
+            queued: 0,
+            prepared: false,
+            sorted: None,
+            reversed: None,
+        }
+    }
+
+    /// Adds a revision argument: a commit to start at, "^A" to exclude
+    /// commits reachable from A, "A..B" for commits reachable from B but not
+    /// A, or "A...B" for those reachable from either but not both. Empty
+    /// sides of ranges stand for HEAD.
+    pub fn push(&mut self, revision: &str) -> Result<()> {
+        self.revision_add(revision, false)
+    }
+
+    /// Adds a revision argument with the meaning of "^" reversed, as git
+    /// takes those after --not.
+    pub fn hide(&mut self, revision: &str) -> Result<()> {
+        self.revision_add(revision, true)
+    }
+
+    fn revision_add(&mut self, revision: &str, excluded: bool) -> Result<()> {
+        match range_parse(revision) {
+            RevRange::Symmetric(from, to) => {
+                let (from, to) = (self.resolve(&from)?, self.resolve(&to)?);
+                let (one, two) = (self.node(&from), self.node(&to));
+                for base in self.merge_bases(one, two)? {
+                    let sha = self.nodes[base].sha.clone();
+                    self.tip_add(&sha, !excluded)?;
+                }
+                self.tip_add(&from, excluded)?;
+                self.tip_add(&to, excluded)
+            }
+            RevRange::Between(from, to) => {
+                let (from, to) = (self.resolve(&from)?, self.resolve(&to)?);
+                self.tip_add(&from, !excluded)?;
+                self.tip_add(&to, excluded)
+            }
+            RevRange::Single(revision) => match revision.strip_prefix('^') {
+                Some(name) => {
+                    let sha = self.resolve(name)?;
+                    self.tip_add(&sha, !excluded)
+                }
+                None => {
+                    let sha = self.resolve(&revision)?;
+                    self.tip_add(&sha, excluded)
+                }
+            },
+        }
+    }
+
+    fn resolve(&self, name: &str) -> Result<String> {
+        self.repo
+            .object_find(name, Some("commit".into()), None)
+            .with_context(|| format!("bad revision '{}'", name))
+    }
+
+    fn tip_add(&mut self, sha: &str, excluded: bool) -> Result<()> {
+        let i = self.node(sha);
+        self.parse(i)?;
+        if excluded {
+            self.nodes[i].flags |= UNINTERESTING | BOTTOM;
+        }
+        self.tips.push(i);
+        Ok(())
+    }
+
+    fn node(&mut self, sha: &str) -> usize {
+        if let Some(&i) = self.index.get(sha) {
+            return i;
+        }
+        self.nodes.push(Node {
+            sha: sha.to_string(),
+            commit: None,
+            parents: vec![],
+            flags: 0,
+        });
+        self.index.insert(sha.to_string(), self.nodes.len() - 1);
+        self.nodes.len() - 1
+    }
+
+    fn parse(&mut self, i: usize) -> Result<()> {
+        if self.nodes[i].commit.is_some() {
+            return Ok(());
+        }
+        let sha = self.nodes[i].sha.clone();
+        let commit = self
+            .repo
+            .object_read(&sha)
+            .with_context(|| format!("could not read commit {}", sha))?
+            .commit()?;
+        let mut parents = vec![];
+        for parent in &commit.parents {
+            parents.push(self.node(parent));
+        }
+        self.nodes[i].parents = parents;
+        self.nodes[i].commit = Some(commit);
+        Ok(())
+    }
+
+    fn time(&self, i: usize) -> i64 {
+        self.nodes[i]
+            .commit
+            .as_ref()
+            .map_or(0, |commit| commit.committer.time)
+    }
+
+    fn queue_push(&mut self, i: usize) {
+        self.queue.push((self.time(i), Reverse(self.queued), i));
+        self.queued += 1;
+    }
+
+    /// Best common ancestors of two commits: those reachable from both that
+    /// no other such commit can reach, latest first. Commits read on the way
+    /// stay read, which lets excluding them reach further.
+    fn merge_bases(&mut self, one: usize, two: usize) -> Result<Vec<usize>> {
+        if one == two {
+            return Ok(vec![one]);
+        }
+        let mut paint = HashMap::new();
+        let bases: Vec<usize> = self
+            .paint_down(one, &[two], &mut paint)?
+            .into_iter()
+            .filter(|base| paint[base] & STALE == 0)
+            .collect();
+        if bases.len() < 2 {
+            return Ok(bases);
+        }
+
+        // a base reachable from another one is not among the best.
+        let mut redundant = vec![false; bases.len()];
+        for i in 0..bases.len() {
+            if redundant[i] {
+                continue;
+            }
+            let others: Vec<usize> = (0..bases.len())
+                .filter(|&j| j != i && !redundant[j])
+                .collect();
+            let twos: Vec<usize> = others.iter().map(|&j| bases[j]).collect();
+            let mut paint = HashMap::new();
+            self.paint_down(bases[i], &twos, &mut paint)?;
+            redundant[i] = paint[&bases[i]] & PARENT2 != 0;
+            for j in others {
+                redundant[j] |= paint[&bases[j]] & PARENT1 != 0;
+            }
+        }
+        let mut ret: Vec<usize> = (0..bases.len())
+            .filter(|&i| !redundant[i])
+            .map(|i| bases[i])
+            .collect();
+        ret.sort_by_key(|&i| Reverse(self.time(i)));
+        Ok(ret)
+    }
+
+    /// Paints ancestors of one and twos by which side reaches them, until
+    /// only ancestors of commits reached from both sides are left. Returns
+    /// those commits latest first.
+    fn paint_down(
+        &mut self,
+        one: usize,
+        twos: &[usize],
+        paint: &mut HashMap<usize, u8>,
+    ) -> Result<Vec<usize>> {
+        let mut queue = BinaryHeap::new();
+        let mut queued = 0;
+        for (i, flag) in [(one, PARENT1)]
+            .into_iter()
+            .chain(twos.iter().map(|&two| (two, PARENT2)))
+        {
+            self.parse(i)?;
+            *paint.entry(i).or_default() |= flag;
+            queue.push((self.time(i), Reverse(queued), i));
+            queued += 1;
+        }
+
+        let mut ret = vec![];
+        while queue.iter().any(|(.., i)| paint[i] & STALE == 0) {
+            let (.., i) = queue.pop().expect("queue is not empty");
+            let mut flags = paint[&i] & (PARENT1 | PARENT2 | STALE);
+            if flags == PARENT1 | PARENT2 {
+                if paint[&i] & RESULT == 0 {
+                    *paint.get_mut(&i).expect("painted") |= RESULT;
+                    ret.push(i);
+                }
+                flags |= STALE;
+            }
+            for parent in self.nodes[i].parents.clone() {
+                if paint.get(&parent).map_or(false, |&p| p & flags == flags) {
+                    continue;
+                }
+                self.parse(parent)?;
+                *paint.entry(parent).or_default() |= flags;
+                queue.push((self.time(parent), Reverse(queued), parent));
+                queued += 1;
+            }
+        }
+        ret.sort_by_key(|&i| Reverse(self.time(i)));
+        Ok(ret)
+    }
+
+    /// Excludes the ancestors of commit read so far. The others are excluded
+    /// when reached from it.
+    fn parents_uninteresting_mark(&mut self, i: usize) {
+        let mut stack = vec![i];
+        while let Some(i) = stack.pop() {
+            for parent in self.nodes[i].parents.clone() {
+                let node = &mut self.nodes[parent];
+                if node.flags & UNINTERESTING != 0 {
+                    continue;
+                }
+                node.flags |= UNINTERESTING;
+                if !node.parents.is_empty() {
+                    stack.push(parent);
+                }
+            }
+        }
+    }
+
+    /// Queues the parents of a commit not seen yet. Parents of excluded
+    /// commits are excluded too.
+    fn parents_process(&mut self, i: usize) -> Result<()> {
+        if self.nodes[i].flags & UNINTERESTING != 0 {
+            for parent in self.nodes[i].parents.clone() {
+                self.nodes[parent].flags |= UNINTERESTING;
+                // missing history below excluded commits does not matter.
+                if self.parse(parent).is_err() {
+                    continue;
+                }
+                if !self.nodes[parent].parents.is_empty() {
+                    self.parents_uninteresting_mark(parent);
+                }
+                if self.nodes[parent].flags & SEEN == 0 {
+                    self.nodes[parent].flags |= SEEN;
+                    self.queue_push(parent);
+                }
+            }
+            return Ok(());
+        }
+        self.simplify(i)?;
+        for parent in self.nodes[i].parents.clone() {
+            self.parse(parent)?;
+            if self.nodes[parent].flags & SEEN == 0 {
+                self.nodes[parent].flags |= SEEN;
+                self.queue_push(parent);
+            }
+            if self.options.first_parent {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether commit is excluded or a bottom of what is, which
+    /// simplification takes for part of the history.
+    fn relevant(&self, i: usize) -> bool {
+        self.nodes[i].flags & (UNINTERESTING | BOTTOM) != UNINTERESTING
+    }
+
+    /// Whether paths the walk is limited to differ between two trees, None
+    /// standing for an empty one.
+    fn paths_changed(&self, old: Option<&str>, new: &str) -> Result<bool> {
+        let mut changes = tree_diff(self.repo, old, Some(new))?;
+        changes_limit(&mut changes, &self.options.filter.paths);
+        Ok(!changes.is_empty())
+    }
+
+    /// Marks a commit not changing the paths the walk is limited to. A merge
+    /// taking them from one of its parents as is keeps only that parent,
+    /// so the walk does not go down the others.
+    fn simplify(&mut self, i: usize) -> Result<()> {
+        if self.options.filter.paths.is_empty() {
+            return Ok(());
+        }
+        let tree = self.nodes[i]
+            .commit
+            .as_ref()
+            .expect("commit is read")
+            .tree
+            .clone();
+        if self.nodes[i].parents.is_empty() {
+            if !self.paths_changed(None, &tree)? {
+                self.nodes[i].flags |= TREESAME;
+            }
+            return Ok(());
+        }
+
+        let (mut relevant_parents, mut relevant_change, mut irrelevant_change) = (0, false, false);
+        for (n, parent) in self.nodes[i].parents.clone().into_iter().enumerate() {
+            let relevant = self.relevant(parent);
+            relevant_parents += relevant as usize;
+            // the first parent is followed whatever side brought the paths.
+            if n == 1 && self.options.first_parent {
+                break;
+            }
+            self.parse(parent)?;
+            let parent_tree = &self.nodes[parent]
+                .commit
+                .as_ref()
+                .expect("commit is read")
+                .tree;
+            match (self.paths_changed(Some(parent_tree), &tree)?, relevant) {
+                (false, true) => {
+                    self.nodes[i].parents = vec![parent];
+                    self.nodes[i].flags |= TREESAME;
+                    return Ok(());
+                }
+                (false, false) => {}
+                (true, true) => relevant_change = true,
+                (true, false) => irrelevant_change = true,
+            }
+        }
+        // parents not relevant count only when no parent is.
+        let same = match relevant_parents {
+            0 => !irrelevant_change,
+            _ => !relevant_change,
+        };
+        if same {
+            self.nodes[i].flags |= TREESAME;
+        }
+        Ok(())
+    }
+
+    /// Whether the walk returns a commit it met, the way it stands now.
+    fn shown(&mut self, i: usize) -> Result<bool> {
+        if self.nodes[i].flags & UNINTERESTING != 0 {
+            return Ok(false);
+        }
+        self.parse(i)?;
+        let (node, filter) = (&self.nodes[i], &self.options.filter);
+        let commit = node.commit.as_ref().expect("commit is read");
+        if filter
+            .until
+            .map_or(false, |until| commit.committer.time > until)
+            || filter
+                .merges
+                .map_or(false, |merges| merges != (node.parents.len() > 1))
+            || !filter.matches(commit)
+        {
+            return Ok(false);
+        }
+        if node.flags & TREESAME == 0 {
+            return Ok(true);
+        }
+        // merges of shown lines of history tie them together in a graph.
+        Ok(self.options.rewrite_parents
+            && node
+                .parents
+                .iter()
+                .filter(|&&parent| self.relevant(parent))
+                .count()
+                >= 2)
+    }
+
+    fn prepare(&mut self) -> Result<()> {
+        let mut limited = self.options.order != Order::Default;
+        for i in self.tips.clone() {
+            if self.nodes[i].flags & UNINTERESTING != 0 {
+                self.parents_uninteresting_mark(i);
+                limited = true;
+            }
+            if self.nodes[i].flags & SEEN == 0 {
+                self.nodes[i].flags |= SEEN;
+                self.queue_push(i);
+            }
+        }
+
+        if limited {
+            let mut list = self.limit()?;
+            if self.options.order != Order::Default {
+                list = self.topo_sort(&list, self.options.order == Order::Date);
+            }
+            self.sorted = Some(list.into());
+        }
+        if self.options.reverse {
+            let mut list = vec![];
+            while let Some(i) = self.next_counted()? {
+                list.push(i);
+            }
+            self.reversed = Some(list);
+        }
+        Ok(())
+    }
+
+    /// Walks until only excluded commits are left, returning the commits
+    /// not excluded so far in the order met.
+    fn limit(&mut self) -> Result<Vec<usize>> {
+        let mut slop = SLOP;
+        let mut date = i64::MAX;
+        let mut ret = vec![];
+        while let Some((_, _, i)) = self.queue.pop() {
+            if self.too_old(i) {
+                self.nodes[i].flags |= UNINTERESTING;
+            }
+            self.parents_process(i)?;
+            if self.nodes[i].flags & UNINTERESTING != 0 {
+                self.parents_uninteresting_mark(i);
+                slop = self.still_interesting(date, slop);
+                match slop {
+                    0 => break,
+                    _ => continue,
+                }
+            }
+            if let Some(until) = self.options.filter.until {
+                if self.time(i) > until {
+                    continue;
+                }
+            }
+            date = self.time(i);
+            ret.push(i);
+        }
+        self.queue.clear();
+        Ok(ret)
+    }
+
+    /// Whether commit was committed before the walk stops.
+    fn too_old(&self, i: usize) -> bool {
+        self.options
+            .filter
+            .since
+            .map_or(false, |since| self.time(i) < since)
+    }
+
+    /// Commits left to look at before limiting can stop, given the date of
+    /// the last commit not excluded.
+    fn still_interesting(&self, date: i64, slop: usize) -> usize {
+        let (next_date, ..) = match self.queue.peek() {
+            Some(next) => *next,
+            None => return 0,
+        };
+        let everybody_uninteresting = self
+            .queue
+            .iter()
+            .all(|&(.., i)| self.nodes[i].flags & UNINTERESTING != 0);
+        match date <= next_date || !everybody_uninteresting {
+            true => SLOP,
+            false => slop - 1,
+        }
+    }
+
+    /// Sorts commits so that parents come after all their children. Ready
+    /// commits go by date, or else the latest ready first, which keeps lines
+    /// of history together.
+    fn topo_sort(&self, list: &[usize], by_date: bool) -> Vec<usize> {
+        // 1 + children not returned yet, 0 once returned or when not listed.
+        let mut indegree = vec![0; self.nodes.len()];
+        for &i in list {
+            indegree[i] = 1;
+        }
+        for &i in list {
+            for &parent in &self.nodes[i].parents {
+                if indegree[parent] > 0 {
+                    indegree[parent] += 1;
+                }
+            }
+        }
+
+        let mut queue = BinaryHeap::new();
+        let mut count = 0;
+        let mut put = |queue: &mut BinaryHeap<((i64, i64), usize)>, i: usize| {
+            count += 1;
+            queue.push(match by_date {
+                true => ((self.time(i), -count), i),
+                false => ((0, count), i),
+            });
+        };
+        // tips go in list order, which the latest first would reverse.
+        let tips: Vec<usize> = list.iter().copied().filter(|&i| indegree[i] == 1).collect();
+        match by_date {
+            true => tips.iter().for_each(|&i| put(&mut queue, i)),
+            false => tips.iter().rev().for_each(|&i| put(&mut queue, i)),
+        }
+        let mut ret = vec![];
+        while let Some((_, i)) = queue.pop() {
+            for &parent in &self.nodes[i].parents {
+                if indegree[parent] == 0 {
+                    continue;
+                }
+                indegree[parent] -= 1;
+                if indegree[parent] == 1 {
+                    put(&mut queue, parent);
+                }
+            }
+            indegree[i] = 0;
+            ret.push(i);
+        }
+        ret
+    }
+
+    /// Next commit shown, before counting and skipping.
+    fn next_commit(&mut self) -> Result<Option<usize>> {
+        loop {
+            let i = match &mut self.sorted {
+                Some(list) => list.pop_front(),
+                None => self.queue.pop().map(|(.., i)| i),
+            };
+            let i = match i {
+                Some(i) => i,
+                None => return Ok(None),
+            };
+            if self.sorted.is_none() {
+                // history beyond an old commit is left unwalked.
+                if self.too_old(i) {
+                    continue;
+                }
+                self.parents_process(i)?;
+            }
+            // excluded after being listed, through a commit met later.
+            if self.shown(i)? {
+                return Ok(Some(i));
+            }
+        }
+    }
+
+    fn next_counted(&mut self) -> Result<Option<usize>> {
+        if self.options.max_count == Some(0) {
+            return Ok(None);
+        }
+        let mut next = self.next_commit()?;
+        while next.is_some() && self.options.skip > 0 {
+            self.options.skip -= 1;
+            next = self.next_commit()?;
+        }
+        if let Some(count) = &mut self.options.max_count {
+            *count -= 1;
+        }
+        Ok(next)
+    }
+
+    fn next_shown(&mut self) -> Result<Option<(String, Commit)>> {
+        if !self.prepared {
+            self.prepared = true;
+            self.prepare()?;
+        }
+        let next = match &mut self.reversed {
+            Some(list) => list.pop(),
+            None => self.next_counted()?,
+        };
+        Ok(next.map(|i| {
+            let node = &self.nodes[i];
+            (
+                node.sha.clone(),
+                node.commit.clone().expect("walked commit is read"),
+            )
+        }))
+    }
+
+    /// Gives back the place in --max-count of a commit returned but not
+    /// shown after all.
+    pub fn uncount(&mut self) {
+        if let Some(count) = &mut self.options.max_count {
+            *count += 1;
+        }
+    }
+
+    /// Parents of a commit returned, which log diffs it against. When
+    /// rewriting parents, those not shown are replaced by their nearest
+    /// ancestor that is shown or excluded.
+    pub fn parents(&self, sha: &str) -> Vec<String> {
+        self.parents_rewritten(self.index[sha])
+            .into_iter()
+            .map(|parent| self.nodes[parent].sha.clone())
+            .collect()
+    }
+
+    /// Parents of a commit returned that the walk shows or would show, which
+    /// a graph draws lines to. When rewriting parents, those not shown are
+    /// replaced by their nearest ancestor that is.
+    pub fn parents_shown(&mut self, sha: &str) -> Result<Vec<String>> {
+        let mut parents = self.parents_rewritten(self.index[sha]);
+        if self.options.first_parent {
+            parents.truncate(1);
+        }
+        let mut ret = vec![];
+        for parent in parents {
+            if self.shown(parent)? {
+                ret.push(self.nodes[parent].sha.clone());
+            }
+        }
+        Ok(ret)
+    }
+
+    fn parents_rewritten(&self, i: usize) -> Vec<usize> {
+        let mut parents = self.nodes[i].parents.clone();
+        if self.options.rewrite_parents && !self.options.filter.paths.is_empty() {
+            parents = parents
+                .into_iter()
+                .filter_map(|parent| self.parent_rewrite(parent))
+                .collect();
+            let mut seen = HashSet::new();
+            parents.retain(|&parent| seen.insert(parent));
+        }
+        parents
+    }
+
+    /// Nearest ancestor of parent through simplified history that is
+    /// excluded or changes the paths, None when history ends first.
+    fn parent_rewrite(&self, mut parent: usize) -> Option<usize> {
+        loop {
+            let node = &self.nodes[parent];
+            if node.flags & UNINTERESTING != 0 || node.flags & TREESAME == 0 {
+                return Some(parent);
+            }
+            let relevant: Vec<usize> = match (&node.parents[..], self.options.first_parent) {
+                ([], _) => return None,
+                ([first, ..], true) | ([first], _) => vec![*first],
+                (parents, false) => parents
+                    .iter()
+                    .copied()
+                    .filter(|&p| self.relevant(p))
+                    .collect(),
+            };
+            match relevant[..] {
+                [next] => parent = next,
+                _ => return Some(parent),
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for RevWalk<'a> {
+    type Item = Result<(String, Commit)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_shown().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{basic_regex, merge_bases, CommitFilter, Order, RevWalk, WalkOptions};
+    use crate::{commit::EMPTY_TREE, testing::TempRepo};
+
+    #[test]
+    fn test_rev_walk() {
+        let repo = TempRepo::new("rev-walk");
+        let commit = |parents: &[&str], time: i64| {
+            repo.commit(EMPTY_TREE)
+                .parents(parents)
+                .time(time)
+                .message(&time.to_string())
+                .write()
+        };
+        // a - b - c - m
+        //  \         /
+        //   d ------
+        let a = commit(&[], 1);
+        let b = commit(&[&a], 2);
+        let d = commit(&[&a], 3);
+        let c = commit(&[&b], 4);
+        let m = commit(&[&c, &d], 5);
+
+        // commits are named by their time.
+        let walk = |revisions: &[String], options| -> Vec<i64> {
+            let mut walk = RevWalk::new(&repo, options);
+            for revision in revisions {
+                walk.push(revision).unwrap();
+            }
+            walk.map(|item| item.unwrap().1.committer.time).collect()
+        };
+        let options = WalkOptions {
+            order: Order::Default,
+            first_parent: false,
+            reverse: false,
+            max_count: None,
+            skip: 0,
+            rewrite_parents: false,
+            filter: CommitFilter::default(),
+        };
+        let all = [m.clone()];
+        assert_eq!(walk(&all, options.clone()), [5, 4, 3, 2, 1]);
+        let topo = WalkOptions {
+            order: Order::Topo,
+            ..options.clone()
+        };
+        assert_eq!(walk(&all, topo), [5, 3, 4, 2, 1]);
+        let first_parent = WalkOptions {
+            first_parent: true,
+            reverse: true,
+            max_count: Some(3),
+            ..options.clone()
+        };
+        assert_eq!(walk(&all, first_parent), [2, 4, 5]);
+        let skip = WalkOptions {
+            skip: 1,
+            ..options.clone()
+        };
+        assert_eq!(walk(&all, skip), [4, 3, 2, 1]);
+
+        let range = |revision: String| walk(&[revision], options.clone());
+        assert_eq!(range(format!("{}..{}", b, m)), [5, 4, 3]);
+        assert_eq!(range(format!("{}...{}", c, d)), [4, 3, 2]);
+        assert_eq!(
+            walk(&[format!("^{}", d), c.clone()], options.clone()),
+            [4, 2]
+        );
+        let filter = |filter| {
+            let options = WalkOptions {
+                filter,
+                ..options.clone()
+            };
+            walk(&all, options)
+        };
+        let merges = |merges| CommitFilter {
+            merges: Some(merges),
+            ..Default::default()
+        };
+        assert_eq!(filter(merges(true)), [5]);
+        assert_eq!(filter(merges(false)), [4, 3, 2, 1]);
+        let grep = CommitFilter {
+            grep: vec![basic_regex("^[24]$", false).unwrap()],
+            ..Default::default()
+        };
+        assert_eq!(filter(grep), [4, 2]);
+        let since = CommitFilter {
+            since: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(filter(since), [5, 4, 3]);
+        let until = CommitFilter {
+            until: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(filter(until), [3, 2, 1]);
+
+        assert_eq!(merge_bases(&repo, &c, &d).unwrap(), [a]);
+        assert_eq!(merge_bases(&repo, &m, &b).unwrap(), [b]);
+    }
+
+    #[test]
+    fn test_basic_regex() {
+        let matches = |pattern, text| basic_regex(pattern, false).unwrap().is_match(text);
+        assert!(matches("a+b", "a+b"));
+        assert!(!matches("a+b", "aab"));
+        assert!(matches("a\\+b", "aab"));
+        assert!(matches("\\(x\\|y\\)z", "yz"));
+        assert!(matches("[]a]", "]"));
+        assert!(matches("[[:digit:]]\\{2\\}", "42"));
+        assert!(matches("\\<fix\\>", "a fix"));
+        assert!(!matches("\\<fix\\>", "prefix"));
+        assert!(basic_regex("FIX", true).unwrap().is_match("fix"));
+    }
+}
@@ -0,0 +1,717 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::Path,
+    rc::Rc,
+};
+
+use crate::{
+    file::{bytes_path, ignore_by_file_name, slash_path_bytes, FileNode, FileType},
+    ignore::Ignore,
+    index::{file_mode, file_read, filemode, Index, IndexEntry},
+    leaf::{tree_flatten, GitTreeLeaf},
+    object::{GitObject, GitObjectType},
+    patch::quote_path,
+    refs::{head_branch, ref_resolve, NULL_SHA},
+    repository::RGitRepository,
+    treediff::{detect_renames, RenameOptions, TreeChange},
+    Result,
+};
+
+/// How a path differs between HEAD and the index, or the index and the work tree.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Change {
+    Added,
+    Modified,
+    Deleted,
+    /// Regular file became a symlink or the other way around.
+    TypeChanged,
+    /// Path moved, with similar enough content.
+    Renamed,
+    /// New path with content similar to a path that stays.
+    Copied,
+}
+
+impl Change {
+    /// Letter git uses for the change in short status and --name-status.
+    pub fn code(&self) -> char {
+        match self {
+            Change::Added => 'A',
+            Change::Modified => 'M',
+            Change::Deleted => 'D',
+            Change::TypeChanged => 'T',
+            Change::Renamed => 'R',
+            Change::Copied => 'C',
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Change::Added => "new file",
+            Change::Modified => "modified",
+            Change::Deleted => "deleted",
+            Change::TypeChanged => "typechange",
+            Change::Renamed => "renamed",
+            Change::Copied => "copied",
+        }
+    }
+}
+
+/// Tracked path with its staged and unstaged changes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatusEntry {
+    pub path: Vec<u8>,
+    /// Path in HEAD of a staged rename.
+    pub orig_path: Option<Vec<u8>>,
+    /// Similarity of a renamed path's content, in percent.
+    pub score: u8,
+    /// Change from HEAD to the index.
+    pub staged: Option<Change>,
+    /// Change from the index to the work tree.
+    pub unstaged: Option<Change>,
+    /// (mode, sha) in HEAD and in the index.
+    pub head: Option<(u32, String)>,
+    pub index: Option<(u32, String)>,
+    /// Mode in the work tree, 0 if the file is missing.
+    pub worktree_mode: u32,
+}
+
+/// Path with conflict stages in the index.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnmergedEntry {
+    pub path: Vec<u8>,
+    /// (mode, sha) of stages 1 (base), 2 (ours) and 3 (theirs).
+    pub stages: [Option<(u32, String)>; 3],
+    pub worktree_mode: u32,
+}
+
+impl UnmergedEntry {
+    /// Two letter status code, the same git uses in short format.
+    fn code(&self) -> &'static str {
+        match (
+            self.stages[0].is_some(),
+            self.stages[1].is_some(),
+            self.stages[2].is_some(),
+        ) {
+            (true, true, true) => "UU",
+            (false, true, true) => "AA",
+            (true, true, false) => "UD",
+            (true, false, true) => "DU",
+            (false, true, false) => "AU",
+            (false, false, true) => "UA",
+            _ => "DD",
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self.code() {
+            "UU" => "both modified",
+            "AA" => "both added",
+            "UD" => "deleted by them",
+            "DU" => "deleted by us",
+            "AU" => "added by us",
+            "UA" => "added by them",
+            _ => "both deleted",
+        }
+    }
+}
+
+/// Differences between HEAD, the index and the work tree.
+#[derive(Debug, Default)]
+pub struct Status {
+    /// Branch HEAD points at, None when detached.
+    pub branch: Option<String>,
+    /// Commit HEAD points at, None before the first commit.
+    pub head: Option<String>,
+    /// Tracked paths that changed, sorted by path.
+    pub changes: Vec<StatusEntry>,
+    pub unmerged: Vec<UnmergedEntry>,
+    /// Untracked files that are not ignored, directories without tracked files end with "/".
+    pub untracked: Vec<Vec<u8>>,
+}
+
+impl Status {
+    pub fn read(repo: &RGitRepository) -> Result<Self> {
+        let head = ref_resolve(repo, "HEAD")?;
+        let head_tree = match &head {
+            Some(sha) => tree_flatten(repo, &repo.object_find(sha, Some("tree".into()), None)?)?,
+            None => Default::default(),
+        };
+        let index = Index::read(repo)?;
+        let filemode = filemode(repo);
+
+        let mut unmerged: BTreeMap<Vec<u8>, UnmergedEntry> = BTreeMap::new();
+        for e in index.entries.iter().filter(|e| e.stage > 0) {
+            let entry = unmerged
+                .entry(e.path.clone())
+                .or_insert_with(|| UnmergedEntry {
+                    path: e.path.clone(),
+                    stages: Default::default(),
+                    worktree_mode: 0,
+                });
+            entry.stages[e.stage as usize - 1] = Some((e.mode, e.sha.clone()));
+        }
+        for entry in unmerged.values_mut() {
+            entry.worktree_mode =
+                fs::symlink_metadata(repo.work_tree().join(bytes_path(&entry.path)))
+                    .map_or(0, |m| file_mode(&m, filemode));
+        }
+
+        let staged: BTreeMap<&[u8], &IndexEntry> = index
+            .entries
+            .iter()
+            .filter(|e| e.stage == 0)
+            .map(|e| (e.path.as_slice(), e))
+            .collect();
+        let mut paths: Vec<&[u8]> = head_tree.keys().map(Vec::as_slice).collect();
+        paths.extend(staged.keys());
+        paths.sort_unstable();
+        paths.dedup();
+
+        let mut changes = vec![];
+        for path in paths {
+            if unmerged.contains_key(path) {
+                continue;
+            }
+            let head = head_tree
+                .get(path)
+                .map(|leaf| -> Result<(u32, String)> {
+                    Ok((u32::from_str_radix(&leaf.mode, 8)?, leaf.sha.clone()))
+                })
+                .transpose()?;
+            let entry = staged.get(path);
+            let index = entry.map(|e| (e.mode, e.sha.clone()));
+
+            let (unstaged, worktree_mode) = match entry {
+                Some(e) => {
+                    let (change, mode, _) = worktree_change(repo, e, filemode)?;
+                    (change, mode)
+                }
+                None => (None, 0),
+            };
+            let entry = StatusEntry {
+                path: path.to_vec(),
+                orig_path: None,
+                score: 0,
+                staged: change(head.as_ref(), index.as_ref()),
+                unstaged,
+                head,
+                index,
+                worktree_mode,
+            };
+            if entry.staged.is_some() || entry.unstaged.is_some() {
+                changes.push(entry);
+            }
+        }
+        let renames = repo
+            .config_get("status", "renames")
+            .or_else(|| repo.config_get("diff", "renames"));
+        if renames.as_deref() != Some("false") {
+            changes = renames_detect(repo, changes)?;
+        }
+
+        let tracked: HashSet<&[u8]> = index.entries.iter().map(|e| e.path.as_slice()).collect();
+        let mut tracked_dirs = HashSet::new();
+        for path in &tracked {
+            let mut dir = *path;
+            while let Some(slash) = dir.iter().rposition(|&b| b == b'/') {
+                let parent = &dir[..slash];
+                if !tracked_dirs.insert(parent) {
+                    break;
+                }
+                dir = parent;
+            }
+        }
+        let work_tree = repo.work_tree();
+        let root = FileNode::traverse_and_build(
+            work_tree,
+            vec![
+                ignore_by_file_name(".git".into()),
+                Rc::new(Ignore::for_repo(repo)?).skip_predicate(),
+            ],
+        )?;
+        let mut untracked = vec![];
+        untracked_collect(&root, work_tree, &tracked, &tracked_dirs, &mut untracked)?;
+        untracked.sort();
+
+        Ok(Self {
+            branch: head_branch(repo)?,
+            head,
+            changes,
+            unmerged: unmerged.into_values().collect(),
+            untracked,
+        })
+    }
+
+    /// Long human readable format. Paths are shown relative to prefix, the
+    /// current directory inside the work tree.
+    pub fn long_format(&self, repo: &RGitRepository, prefix: &str) -> Result<String> {
+        let mut ret = match (&self.branch, &self.head) {
+            (Some(branch), _) => format!("On branch {}\n", branch),
+            (None, Some(head)) => format!("HEAD detached at {}\n", repo.abbreviate(head, 7)?),
+            (None, None) => "Not currently on any branch.\n".to_string(),
+        };
+        if self.head.is_none() {
+            ret += "\nNo commits yet\n\n";
+        }
+        if repo.repo_path(&["MERGE_HEAD"]).exists() {
+            ret += match self.unmerged.is_empty() {
+                true => "All conflicts fixed but you are still merging.\n\n",
+                false => "You have unmerged paths.\n\n",
+            };
+        }
+
+        let staged: Vec<_> = self.changes.iter().filter(|e| e.staged.is_some()).collect();
+        if !staged.is_empty() {
+            ret += "Changes to be committed:\n";
+            for e in &staged {
+                let label = format!("{}:", e.staged.unwrap().label());
+                ret += &format!("\t{:<12}{}\n", label, e.paths_display(prefix));
+            }
+            ret += "\n";
+        }
+        if !self.unmerged.is_empty() {
+            ret += "Unmerged paths:\n";
+            for e in &self.unmerged {
+                let label = format!("{}:", e.label());
+                ret += &format!("\t{:<17}{}\n", label, path_display(&e.path, prefix));
+            }
+            ret += "\n";
+        }
+        let unstaged: Vec<_> = self
+            .changes
+            .iter()
+            .filter(|e| e.unstaged.is_some())
+            .collect();
+        if !unstaged.is_empty() {
+            ret += "Changes not staged for commit:\n";
+            for e in &unstaged {
+                let label = format!("{}:", e.unstaged.unwrap().label());
+                ret += &format!("\t{:<12}{}\n", label, path_display(&e.path, prefix));
+            }
+            ret += "\n";
+        }
+        if !self.untracked.is_empty() {
+            ret += "Untracked files:\n";
+            for path in &self.untracked {
+                ret += &format!("\t{}\n", path_display(path, prefix));
+            }
+            ret += "\n";
+        }
+
+        if staged.is_empty() {
+            let unstaged = !unstaged.is_empty() || !self.unmerged.is_empty();
+            ret += match (unstaged, self.untracked.is_empty()) {
+                (true, _) => "no changes added to commit\n",
+                (false, false) => "nothing added to commit but untracked files present\n",
+                (false, true) if self.head.is_none() => "nothing to commit\n",
+                (false, true) => "nothing to commit, working tree clean\n",
+            };
+        }
+        Ok(ret)
+    }
+
+    /// Short format, "XY path" per line where X is the staged and Y the unstaged
+    /// change. Porcelain v1 is the same with paths relative to the work tree.
+    pub fn short_format(&self, prefix: &str) -> String {
+        let mut lines: Vec<(&[u8], String)> = self
+            .changes
+            .iter()
+            .map(|e| {
+                let code = |c: Option<Change>| c.map_or(' ', |c| c.code());
+                let line = format!(
+                    "{}{} {}",
+                    code(e.staged),
+                    code(e.unstaged),
+                    e.paths_display(prefix)
+                );
+                (e.path.as_slice(), line)
+            })
+            .collect();
+        lines.extend(self.unmerged.iter().map(|e| {
+            (
+                e.path.as_slice(),
+                format!("{} {}", e.code(), path_display(&e.path, prefix)),
+            )
+        }));
+        lines.sort();
+
+        let mut ret = String::new();
+        for (_, line) in lines {
+            ret += &line;
+            ret += "\n";
+        }
+        for path in &self.untracked {
+            ret += &format!("?? {}\n", path_display(path, prefix));
+        }
+        ret
+    }
+
+    /// Porcelain v2 format, with modes and object names of every side.
+    pub fn porcelain_v2(&self) -> String {
+        let mut lines: Vec<(&[u8], String)> = self
+            .changes
+            .iter()
+            .map(|e| {
+                let code = |c: Option<Change>| c.map_or('.', |c| c.code());
+                let (mode_head, sha_head) = mode_sha(&e.head);
+                let (mode_index, sha_index) = mode_sha(&e.index);
+                let line = format!(
+                    "{} {}{} N... {:06o} {:06o} {:06o} {} {}",
+                    if e.orig_path.is_some() { 2 } else { 1 },
+                    code(e.staged),
+                    code(e.unstaged),
+                    mode_head,
+                    mode_index,
+                    e.worktree_mode,
+                    sha_head,
+                    sha_index
+                );
+                // renames add the score and, after a tab, the path in HEAD.
+                let line = match &e.orig_path {
+                    Some(orig) => format!(
+                        "{} R{} {}\t{}",
+                        line,
+                        e.score,
+                        quote_path(&e.path),
+                        quote_path(orig)
+                    ),
+                    None => format!("{} {}", line, quote_path(&e.path)),
+                };
+                (e.path.as_slice(), line)
+            })
+            .collect();
+        lines.extend(self.unmerged.iter().map(|e| {
+            let [(m1, h1), (m2, h2), (m3, h3)] = [
+                mode_sha(&e.stages[0]),
+                mode_sha(&e.stages[1]),
+                mode_sha(&e.stages[2]),
+            ];
+            let line = format!(
+                "u {} N... {:06o} {:06o} {:06o} {:06o} {} {} {} {}",
+                e.code(),
+                m1,
+                m2,
+                m3,
+                e.worktree_mode,
+                h1,
+                h2,
+                h3,
+                quote_path(&e.path)
+            );
+            (e.path.as_slice(), line)
+        }));
+        lines.sort();
+
+        let mut ret = String::new();
+        for (_, line) in lines {
+            ret += &line;
+            ret += "\n";
+        }
+        for path in &self.untracked {
+            ret += &format!("? {}\n", quote_path(path));
+        }
+        ret
+    }
+}
+
+impl StatusEntry {
+    /// Path relative to prefix, "old -> new" for renames.
+    fn paths_display(&self, prefix: &str) -> String {
+        match &self.orig_path {
+            Some(orig) => format!(
+                "{} -> {}",
+                path_display(orig, prefix),
+                path_display(&self.path, prefix)
+            ),
+            None => path_display(&self.path, prefix),
+        }
+    }
+}
+
+/// Pairs paths deleted from the index with ones added with similar content,
+/// which become staged renames shown under the new path.
+fn renames_detect(repo: &RGitRepository, changes: Vec<StatusEntry>) -> Result<Vec<StatusEntry>> {
+    let leaf = |path: &[u8], (mode, sha): &(u32, String)| {
+        GitTreeLeaf::new(format!("{:o}", mode), path.to_vec(), sha.clone())
+    };
+    let mut tree_changes = vec![];
+    for e in &changes {
+        if let Some(Change::Added | Change::Deleted) = e.staged {
+            let old = e.head.as_ref().map(|side| leaf(&e.path, side));
+            let new = e.index.as_ref().map(|side| leaf(&e.path, side));
+            tree_changes.extend(TreeChange::new(old, new)?);
+        }
+    }
+    let read = |leaf: &GitTreeLeaf, _| Ok(repo.object_read_raw(&leaf.sha)?.1);
+    let renames: HashMap<Vec<u8>, (Vec<u8>, u8)> =
+        detect_renames(tree_changes, RenameOptions::default(), read)?
+            .into_iter()
+            .filter(|c| c.change == Change::Renamed)
+            .map(|c| (c.path().to_vec(), (c.old.unwrap().path, c.score)))
+            .collect();
+    let sources: HashSet<&[u8]> = renames.values().map(|(old, _)| old.as_slice()).collect();
+    let heads: HashMap<&[u8], &Option<(u32, String)>> = changes
+        .iter()
+        .map(|e| (e.path.as_slice(), &e.head))
+        .collect();
+
+    let mut ret = vec![];
+    for e in &changes {
+        if sources.contains(e.path.as_slice()) {
+            continue;
+        }
+        let mut e = e.clone();
+        if let Some((orig, score)) = renames.get(&e.path) {
+            e.staged = Some(Change::Renamed);
+            e.head = heads[orig.as_slice()].clone();
+            e.orig_path = Some(orig.clone());
+            e.score = *score;
+        }
+        ret.push(e);
+    }
+    Ok(ret)
+}
+
+fn mode_sha(side: &Option<(u32, String)>) -> (u32, &str) {
+    match side {
+        Some((mode, sha)) => (*mode, sha),
+        None => (0, NULL_SHA),
+    }
+}
+
+/// Change between two (mode, sha) sides of a path.
+pub(crate) fn change(from: Option<&(u32, String)>, to: Option<&(u32, String)>) -> Option<Change> {
+    match (from, to) {
+        (None, None) => None,
+        (None, Some(_)) => Some(Change::Added),
+        (Some(_), None) => Some(Change::Deleted),
+        (Some((from_mode, _)), Some((to_mode, _)))
+            if from_mode & 0o170000 != to_mode & 0o170000 =>
+        {
+            Some(Change::TypeChanged)
+        }
+        (Some(from), Some(to)) if from != to => Some(Change::Modified),
+        _ => None,
+    }
+}
+
+/// Compares index entry with the file on disk, returns the change, mode and
+/// blob SHA of the file. Content is hashed only when stat data does not match
+/// the index, a missing file has mode 0 and the null SHA.
+pub(crate) fn worktree_change(
+    repo: &RGitRepository,
+    entry: &IndexEntry,
+    filemode: bool,
+) -> Result<(Option<Change>, u32, String)> {
+    if entry.skip_worktree {
+        return Ok((None, entry.mode, entry.sha.clone()));
+    }
+    let fs_path = repo.work_tree().join(bytes_path(&entry.path));
+    let metadata = match fs::symlink_metadata(&fs_path) {
+        Ok(metadata) if !metadata.is_dir() => metadata,
+        _ => return Ok((Some(Change::Deleted), 0, NULL_SHA.to_string())),
+    };
+    // gitlinks are other repositories, their content is not compared.
+    if entry.mode == 0o160000 {
+        return Ok((None, entry.mode, entry.sha.clone()));
+    }
+
+    let mut mode = file_mode(&metadata, filemode);
+    if !filemode && mode == 0o100644 && entry.mode & 0o170000 == 0o100000 {
+        mode = entry.mode;
+    }
+    if entry.stat_matches(&metadata) && mode == entry.mode {
+        return Ok((None, mode, entry.sha.clone()));
+    }
+
+    let sha = GitObject::new(repo, Some(file_read(&fs_path)?), Some(GitObjectType::Blob))?
+        .object_write(Some(false))?;
+    let ret = change(
+        Some(&(entry.mode, entry.sha.clone())),
+        Some(&(mode, sha.clone())),
+    );
+    Ok((ret, mode, sha))
+}
+
+/// Collects untracked files under node. Directories without any tracked file
+/// are reported once, as "dir/", and empty ones not at all.
+fn untracked_collect(
+    node: &FileNode,
+    work_tree: &Path,
+    tracked: &HashSet<&[u8]>,
+    tracked_dirs: &HashSet<&[u8]>,
+    ret: &mut Vec<Vec<u8>>,
+) -> Result<()> {
+    for child in node.children.iter().flatten() {
+        let path = slash_path_bytes(child.path.strip_prefix(work_tree)?);
+        match child.file_type {
+            FileType::Directory if tracked_dirs.contains(path.as_slice()) => {
+                untracked_collect(child, work_tree, tracked, tracked_dirs, ret)?
+            }
+            FileType::Directory if !child.files().is_empty() => {
+                ret.push([path, b"/".to_vec()].concat())
+            }
+            FileType::Directory => {}
+            _ if !tracked.contains(path.as_slice()) => ret.push(path),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Shows path, relative to the work tree, relative to prefix directory instead,
+/// quoted like git does.
+fn path_display(path: &[u8], prefix: &str) -> String {
+    if prefix.is_empty() {
+        return quote_path(path);
+    }
+    let mut path: Vec<&[u8]> = path.split(|&b| b == b'/').collect();
+    let mut prefix: Vec<&[u8]> = prefix.as_bytes().split(|&b| b == b'/').collect();
+    while !path.is_empty() && !prefix.is_empty() && path[0] == prefix[0] && path.len() > 1 {
+        path.remove(0);
+        prefix.remove(0);
+    }
+    let mut ret = b"../".repeat(prefix.len());
+    ret.extend(path.join(&b'/'));
+    if ret.is_empty() {
+        ret.extend(b"./");
+    }
+    quote_path(&ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{path_display, Status};
+    use crate::{
+        index::Index,
+        refs::ref_create,
+        testing::{entry, TempRepo},
+    };
+
+    #[test]
+    fn test_status_read() {
+        let repo = TempRepo::new("status");
+        let entry = |path: &str, data: &str, stage: u8| entry(path, &repo.blob(data), stage);
+        let head = ["conflict", "deleted", "gone", "kept", "staged", "unstaged"];
+        let tree = repo.tree(&head.map(|path| (path, path)));
+        let commit = repo.commit(&tree).write();
+        ref_create(&repo, "refs/heads/master", &commit).unwrap();
+
+        Index {
+            version: 2,
+            entries: vec![
+                entry("added", "added", 0),
+                entry("conflict", "conflict", 1),
+                entry("conflict", "ours", 2),
+                entry("conflict", "theirs", 3),
+                entry("deleted", "deleted", 0),
+                entry("kept", "kept", 0),
+                entry("staged", "new", 0),
+                entry("unstaged", "unstaged", 0),
+            ],
+        }
+        .write(&repo)
+        .unwrap();
+        for (path, data) in [
+            ("added", "added"),
+            ("conflict", "merged"),
+            ("kept", "kept"),
+            ("staged", "new"),
+            ("unstaged", "changed"),
+            ("new.txt", "new"),
+            ("newdir/x", "x"),
+        ] {
+            repo.write(path, data);
+        }
+
+        let status = Status::read(&repo).unwrap();
+        let (deleted, unstaged) = (repo.blob("deleted"), repo.blob("unstaged"));
+
+        assert_eq!(status.branch.as_deref(), Some("master"));
+        assert_eq!(status.head, Some(commit));
+        let paths: Vec<&[u8]> = status.changes.iter().map(|e| e.path.as_slice()).collect();
+        assert_eq!(
+            paths,
+            [
+                b"added".as_slice(),
+                b"deleted",
+                b"gone",
+                b"staged",
+                b"unstaged"
+            ]
+        );
+        assert_eq!(status.unmerged.len(), 1);
+        assert!(status.unmerged[0].stages.iter().all(Option::is_some));
+        assert_eq!(status.untracked, [b"new.txt".as_slice(), b"newdir/"]);
+        assert_eq!(
+            status.short_format(""),
+            "A  added\nUU conflict\n D deleted\nD  gone\nM  staged\n M unstaged\n\
+             ?? new.txt\n?? newdir/\n"
+        );
+        let v2 = status.porcelain_v2();
+        let v2: Vec<&str> = v2.lines().collect();
+        assert_eq!(v2.len(), 8);
+        assert_eq!(
+            v2[2],
+            format!(
+                "1 .D N... 100644 100644 000000 {} {} deleted",
+                deleted, deleted
+            )
+        );
+        assert_eq!(
+            v2[5],
+            format!(
+                "1 .M N... 100644 100644 100644 {} {} unstaged",
+                unstaged, unstaged
+            )
+        );
+        assert!(v2[1].starts_with("u UU N... 100644 100644 100644 100644 "));
+        assert_eq!(v2[6..], ["? new.txt", "? newdir/"]);
+    }
+
+    #[test]
+    fn test_status_renames() {
+        let repo = TempRepo::new("status-renames");
+        let data: String = (1..20).map(|i| format!("line {}\n", i)).collect();
+        let tree = repo.tree(&[("f.txt", &data), ("kept", "kept")]);
+        let commit = repo.commit(&tree).write();
+        ref_create(&repo, "refs/heads/master", &commit).unwrap();
+        let sha = repo.blob(&data);
+        Index {
+            version: 2,
+            entries: vec![
+                entry("g.txt", &sha, 0),
+                entry("kept", &repo.blob("kept"), 0),
+            ],
+        }
+        .write(&repo)
+        .unwrap();
+        repo.write("g.txt", &format!("{}changed\n", data));
+        repo.write("kept", "kept");
+
+        let status = Status::read(&repo).unwrap();
+        assert_eq!(status.changes.len(), 1);
+        assert_eq!(status.short_format(""), "RM f.txt -> g.txt\n");
+        assert_eq!(
+            status.porcelain_v2().lines().next().unwrap(),
+            format!(
+                "2 RM N... 100644 100644 100644 {} {} R100 g.txt\tf.txt",
+                sha, sha
+            )
+        );
+        let long = status.long_format(&repo, "").unwrap();
+        assert!(long.contains("\trenamed:    f.txt -> g.txt\n"));
+        assert!(long.contains("\tmodified:   g.txt\n"));
+    }
+
+    #[test]
+    fn test_path_display() {
+        assert_eq!(path_display(b"src/main.rs", ""), "src/main.rs");
+        assert_eq!(path_display(b"src/main.rs", "src"), "main.rs");
+        assert_eq!(path_display(b"README", "src/bin"), "../../README");
+        assert_eq!(path_display(b"src/a/b", "src/c"), "../a/b");
+        assert_eq!(path_display(b"dir/", "dir"), "./");
+        assert_eq!(path_display(b"src/caf\xe9", "src"), "\"caf\\351\"");
+    }
+}
@@ -0,0 +1,155 @@
+//! Fixtures of tests that need a repository on disk.
+
+use std::{
+    env, fs,
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    commit::{Commit, Signature},
+    index::{Index, IndexEntry},
+    object::{GitObject, GitObjectType},
+    repository::{repo_create, RGitRepository},
+};
+
+/// Empty repository in a temporary directory, removed on drop, so also when
+/// a test fails.
+pub(crate) struct TempRepo {
+    repo: RGitRepository,
+    dir: PathBuf,
+}
+
+impl TempRepo {
+    /// Creates repository in a directory named after the test, name must be
+    /// unique among tests.
+    pub fn new(name: &str) -> Self {
+        let dir = env::temp_dir().join(format!("rgit-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        repo_create(&dir).unwrap();
+        // canonical, so paths given to commands are found inside the work tree.
+        let dir = fs::canonicalize(&dir).unwrap();
+        let repo = RGitRepository::init(&dir, false).unwrap();
+        Self { repo, dir }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Absolute path of a work tree file, the way commands take paths.
+    pub fn path(&self, path: &str) -> String {
+        self.dir.join(path).to_string_lossy().to_string()
+    }
+
+    /// Writes work tree file, creating its directories.
+    pub fn write(&self, path: &str, data: &str) {
+        let path = self.dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, data).unwrap();
+    }
+
+    /// Content of work tree file, None if it is missing.
+    pub fn read(&self, path: &str) -> Option<String> {
+        fs::read_to_string(self.dir.join(path)).ok()
+    }
+
+    /// Writes blob, returns its SHA.
+    pub fn blob(&self, data: &str) -> String {
+        GitObject::new(&self.repo, Some(data.into()), Some(GitObjectType::Blob))
+            .unwrap()
+            .object_write(None)
+            .unwrap()
+    }
+
+    /// Writes tree of regular files given as (path, content), returns its SHA.
+    pub fn tree(&self, files: &[(&str, &str)]) -> String {
+        let entries = files
+            .iter()
+            .map(|(path, data)| entry(path, &self.blob(data), 0))
+            .collect();
+        Index {
+            version: 2,
+            entries,
+        }
+        .write_tree(&self.repo)
+        .unwrap()
+    }
+
+    /// Starts commit of tree, with no parents, at time 1 and message "commit".
+    pub fn commit(&self, tree: &str) -> CommitBuilder<'_> {
+        CommitBuilder {
+            repo: &self.repo,
+            commit: Commit {
+                tree: tree.into(),
+                parents: vec![],
+                author: signature(1),
+                committer: signature(1),
+                encoding: None,
+                extra_headers: vec![],
+                message: b"commit\n".to_vec(),
+            },
+        }
+    }
+}
+
+impl Deref for TempRepo {
+    type Target = RGitRepository;
+
+    fn deref(&self) -> &RGitRepository {
+        &self.repo
+    }
+}
+
+impl Drop for TempRepo {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Commit being set up by a test, written by write.
+pub(crate) struct CommitBuilder<'a> {
+    repo: &'a RGitRepository,
+    commit: Commit,
+}
+
+impl CommitBuilder<'_> {
+    pub fn parents(mut self, parents: &[&str]) -> Self {
+        self.commit.parents = parents.iter().map(|p| p.to_string()).collect();
+        self
+    }
+
+    /// Author and committer time.
+    pub fn time(mut self, time: i64) -> Self {
+        self.commit.author = signature(time);
+        self.commit.committer = signature(time);
+        self
+    }
+
+    /// Message, a newline is appended.
+    pub fn message(mut self, message: &str) -> Self {
+        self.commit.message = format!("{}\n", message).into_bytes();
+        self
+    }
+
+    /// Writes commit, returns its SHA.
+    pub fn write(self) -> String {
+        self.commit.write(self.repo).unwrap()
+    }
+}
+
+/// Signature of "A <a@x>" at time in UTC.
+pub(crate) fn signature(time: i64) -> Signature {
+    format!("A <a@x> {} +0000", time).parse().unwrap()
+}
+
+/// Index entry of a regular file without stat data, so it is always hashed.
+pub(crate) fn entry(path: &str, sha: &str, stage: u8) -> IndexEntry {
+    IndexEntry {
+        mode: 0o100644,
+        sha: sha.into(),
+        stage,
+        path: path.into(),
+        ..Default::default()
+    }
+}
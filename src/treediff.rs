@@ -0,0 +1,379 @@
+use anyhow::Context;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::{
+    commit::EMPTY_TREE,
+    diff::is_binary,
+    leaf::GitTreeLeaf,
+    repository::RGitRepository,
+    status::{change, Change},
+    Result,
+};
+
+/// Divisor of span hashes, the same git uses so similarity scores match.
+const HASHBASE: u32 = 107927;
+
+/// A path that changed between two trees. Sides carry full paths, which
+/// differ for renames and copies. Added paths have no old side, deleted ones
+/// no new side.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeChange {
+    pub change: Change,
+    pub old: Option<GitTreeLeaf>,
+    pub new: Option<GitTreeLeaf>,
+    /// Similarity of renamed and copied content, in percent.
+    pub score: u8,
+}
+
+impl TreeChange {
+    /// Change between two sides of a path, None if they are the same.
+    pub fn new(old: Option<GitTreeLeaf>, new: Option<GitTreeLeaf>) -> Result<Option<Self>> {
+        let side = |leaf: &Option<GitTreeLeaf>| -> Result<Option<(u32, String)>> {
+            leaf.as_ref()
+                .map(|leaf| Ok((leaf.mode_bits()?, leaf.sha.clone())))
+                .transpose()
+        };
+        Ok(
+            change(side(&old)?.as_ref(), side(&new)?.as_ref()).map(|change| Self {
+                change,
+                old,
+                new,
+                score: 0,
+            }),
+        )
+    }
+
+    /// Path after the change, the old one for deletions.
+    pub fn path(&self) -> &[u8] {
+        &self.new.as_ref().or(self.old.as_ref()).unwrap().path
+    }
+}
+
+/// Options of rename detection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenameOptions {
+    /// Least similarity in percent for a pair of files to be related.
+    pub threshold: u8,
+    /// Also look for copies of modified files, not only renames of deleted ones.
+    pub copies: bool,
+}
+
+impl Default for RenameOptions {
+    fn default() -> Self {
+        Self {
+            threshold: 50,
+            copies: false,
+        }
+    }
+}
+
+/// Compares two trees recursively, None standing for an empty tree. Subtrees
+/// with the same SHA on both sides are skipped without being read. Changes of
+/// blobs, symlinks and gitlinks are returned sorted by path.
+pub fn tree_diff(
+    repo: &RGitRepository,
+    old: Option<&str>,
+    new: Option<&str>,
+) -> Result<Vec<TreeChange>> {
+    let mut ret = vec![];
+    tree_diff_at(repo, b"", old, new, &mut ret)?;
+    ret.sort_by(|a, b| a.path().cmp(b.path()));
+    Ok(ret)
+}
+
+fn tree_diff_at(
+    repo: &RGitRepository,
+    prefix: &[u8],
+    old: Option<&str>,
+    new: Option<&str>,
+    out: &mut Vec<TreeChange>,
+) -> Result<()> {
+    if old == new {
+        return Ok(());
+    }
+    let (old, new) = (tree_entries(repo, old)?, tree_entries(repo, new)?);
+    let mut names: Vec<&Vec<u8>> = old.keys().chain(new.keys()).collect();
+    names.sort_unstable();
+    names.dedup();
+
+    for name in names {
+        let path = [prefix, name].concat();
+        let (old, new) = (old.get(name), new.get(name));
+        // a subtree replaced by a file, or the other way around, is both.
+        if subtree(old).is_some() || subtree(new).is_some() {
+            let prefix = [path.as_slice(), b"/"].concat();
+            tree_diff_at(repo, &prefix, subtree(old), subtree(new), out)?;
+        }
+        let file = |leaf: Option<&GitTreeLeaf>| {
+            leaf.filter(|leaf| !leaf.is_tree())
+                .map(|leaf| GitTreeLeaf::new(leaf.mode.clone(), path.clone(), leaf.sha.clone()))
+        };
+        if let Some(change) = TreeChange::new(file(old), file(new))? {
+            out.push(change);
+        }
+    }
+    Ok(())
+}
+
+/// SHA of leaf if it is a subtree.
+fn subtree(leaf: Option<&GitTreeLeaf>) -> Option<&str> {
+    leaf.filter(|leaf| leaf.is_tree())
+        .map(|leaf| leaf.sha.as_str())
+}
+
+/// Entries of one tree level by name, none for None. The empty tree need
+/// not be stored, like in git.
+fn tree_entries(
+    repo: &RGitRepository,
+    sha: Option<&str>,
+) -> Result<BTreeMap<Vec<u8>, GitTreeLeaf>> {
+    let sha = match sha {
+        Some(sha) if sha != EMPTY_TREE => sha,
+        _ => return Ok(BTreeMap::new()),
+    };
+    let tree = repo
+        .object_read(sha)?
+        .tree
+        .with_context(|| format!("{} is not a tree", sha))?;
+    Ok(tree
+        .into_iter()
+        .map(|leaf| (leaf.path.clone(), leaf))
+        .collect())
+}
+
+/// Pairs added paths with deleted ones of the same or similar content, which
+/// become renames. With copies, modified paths are sources too and pairs with
+/// them become copies. A deleted path can be a source more than once only
+/// with copies, its last pair by path is the rename and others are copies.
+/// Content is read with read, which is told whether a side is a new one.
+pub fn detect_renames<F>(
+    changes: Vec<TreeChange>,
+    options: RenameOptions,
+    read: F,
+) -> Result<Vec<TreeChange>>
+where
+    F: Fn(&GitTreeLeaf, bool) -> Result<Vec<u8>>,
+{
+    let sources: Vec<usize> = (0..changes.len())
+        .filter(|&i| match changes[i].change {
+            Change::Deleted => true,
+            Change::Modified => options.copies,
+            _ => false,
+        })
+        .collect();
+    let targets: Vec<usize> = (0..changes.len())
+        .filter(|&i| changes[i].change == Change::Added)
+        .collect();
+    if sources.is_empty() || targets.is_empty() {
+        return Ok(changes);
+    }
+    let old = |i: usize| changes[i].old.as_ref().unwrap();
+    let new = |i: usize| changes[i].new.as_ref().unwrap();
+
+    // target -> (source, score)
+    let mut pairs: BTreeMap<usize, (usize, u8)> = BTreeMap::new();
+    let mut used = HashSet::new();
+    let usable = |used: &HashSet<usize>, source: &usize| options.copies || !used.contains(source);
+
+    // exact renames first, preferring a source with the same file name.
+    for &target in &targets {
+        let source = sources
+            .iter()
+            .filter(|s| old(**s).sha == new(target).sha && usable(&used, s))
+            .min_by_key(|s| file_name(&old(**s).path) != file_name(&new(target).path));
+        if let Some(&source) = source {
+            pairs.insert(target, (source, 100));
+            used.insert(source);
+        }
+    }
+
+    let regular = |leaf: &GitTreeLeaf| leaf.mode_bits().map_or(false, |m| m & 0o170000 == 0o100000);
+    let targets: Vec<usize> = targets
+        .into_iter()
+        .filter(|t| !pairs.contains_key(t) && regular(new(*t)))
+        .collect();
+    let sources: Vec<usize> = sources.into_iter().filter(|s| regular(old(*s))).collect();
+    let spans = |i: usize, is_new: bool| -> Result<(usize, HashMap<u32, usize>)> {
+        let data = read(if is_new { new(i) } else { old(i) }, is_new)?;
+        Ok((data.len(), span_hashes(&data)))
+    };
+    let source_spans = match targets.is_empty() {
+        true => vec![],
+        false => sources
+            .iter()
+            .map(|&s| spans(s, false))
+            .collect::<Result<Vec<_>>>()?,
+    };
+    let mut matches = vec![];
+    for &target in &targets {
+        let target_spans = spans(target, true)?;
+        for (&source, source_spans) in sources.iter().zip(&source_spans) {
+            let score = similarity(source_spans, &target_spans, options.threshold);
+            if score >= options.threshold {
+                matches.push((score, target, source));
+            }
+        }
+    }
+    matches.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)));
+    for (score, target, source) in matches {
+        if pairs.contains_key(&target) || !usable(&used, &source) {
+            continue;
+        }
+        pairs.insert(target, (source, score));
+        used.insert(source);
+    }
+
+    // the last pair of a deleted source, in path order, is its rename.
+    let mut renames = HashMap::new();
+    for (&target, &(source, _)) in &pairs {
+        if changes[source].change == Change::Deleted {
+            renames.insert(source, target);
+        }
+    }
+    let mut ret = vec![];
+    for (i, change) in changes.iter().enumerate() {
+        if renames.contains_key(&i) {
+            continue;
+        }
+        match pairs.get(&i) {
+            Some(&(source, score)) => ret.push(TreeChange {
+                change: match renames.get(&source) == Some(&i) {
+                    true => Change::Renamed,
+                    false => Change::Copied,
+                },
+                old: Some(old(source).clone()),
+                new: change.new.clone(),
+                score,
+            }),
+            None => ret.push(change.clone()),
+        }
+    }
+    ret.sort_by(|a, b| a.path().cmp(b.path()));
+    Ok(ret)
+}
+
+fn file_name(path: &[u8]) -> &[u8] {
+    path.rsplit(|&b| b == b'/').next().unwrap_or(path)
+}
+
+/// Bytes of content in spans ending at a newline or 64 bytes long, counted by
+/// hash of the span. Carriage returns before newlines do not count in text.
+fn span_hashes(data: &[u8]) -> HashMap<u32, usize> {
+    let text = !is_binary(data);
+    let mut ret = HashMap::new();
+    let (mut accum1, mut accum2, mut n) = (0u32, 0u32, 0);
+    for (i, &c) in data.iter().enumerate() {
+        if text && c == b'\r' && data.get(i + 1) == Some(&b'\n') {
+            continue;
+        }
+        let old1 = accum1;
+        accum1 = (accum1 << 7) ^ (accum2 >> 25);
+        accum2 = (accum2 << 7) ^ (old1 >> 25);
+        accum1 = accum1.wrapping_add(c as u32);
+        n += 1;
+        if n < 64 && c != b'\n' {
+            continue;
+        }
+        let hash = accum1.wrapping_add(accum2.wrapping_mul(0x61)) % HASHBASE;
+        *ret.entry(hash).or_insert(0) += n;
+        (accum1, accum2, n) = (0, 0, 0);
+    }
+    if n > 0 {
+        let hash = accum1.wrapping_add(accum2.wrapping_mul(0x61)) % HASHBASE;
+        *ret.entry(hash).or_insert(0) += n;
+    }
+    ret
+}
+
+/// Percentage of the larger content found in the other one, the way git
+/// scores renames. Pairs whose sizes alone rule out threshold score 0.
+fn similarity(
+    (src_size, src): &(usize, HashMap<u32, usize>),
+    (dst_size, dst): &(usize, HashMap<u32, usize>),
+    threshold: u8,
+) -> u8 {
+    let max_size = *src_size.max(dst_size);
+    let delta = src_size.abs_diff(*dst_size);
+    if max_size == 0 || max_size * (100 - threshold as usize) < delta * 100 {
+        return 0;
+    }
+    let copied: usize = src
+        .iter()
+        .map(|(hash, count)| dst.get(hash).map_or(0, |d| *d.min(count)))
+        .sum();
+    (copied * 100 / max_size) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{detect_renames, similarity, span_hashes, RenameOptions, TreeChange};
+    use crate::{leaf::GitTreeLeaf, patch::blob_sha, status::Change};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_similarity() {
+        let old: Vec<u8> = (0..10)
+            .flat_map(|i| format!("line {}\n", i).into_bytes())
+            .collect();
+        let mut new = old.clone();
+        new.extend_from_slice(b"line 10\nline 11\n");
+        let (old, new) = (
+            (old.len(), span_hashes(&old)),
+            (new.len(), span_hashes(&new)),
+        );
+        assert_eq!(similarity(&old, &new, 50), 81);
+        assert_eq!(similarity(&old, &old, 50), 100);
+        // CRLF line endings of text do not count.
+        let crlf = b"line 0\r\nline 1\r\n";
+        assert_eq!(span_hashes(crlf), span_hashes(b"line 0\nline 1\n"));
+    }
+
+    #[test]
+    fn test_detect_renames() {
+        let contents: HashMap<&str, &[u8]> = [
+            ("a.txt", b"one\ntwo\nthree\nfour\n".as_slice()),
+            ("b.txt", b"one\ntwo\nthree\nfour\nfive\n".as_slice()),
+            ("gone", b"unrelated\n".as_slice()),
+            ("new", b"something else\n".as_slice()),
+        ]
+        .into_iter()
+        .collect();
+        let leaf = |path: &str, name: &str| {
+            Some(GitTreeLeaf::new(
+                "100644".into(),
+                path.into(),
+                blob_sha(contents[name]),
+            ))
+        };
+        let changes = vec![
+            TreeChange::new(leaf("a.txt", "a.txt"), None)
+                .unwrap()
+                .unwrap(),
+            TreeChange::new(None, leaf("dir/b.txt", "b.txt"))
+                .unwrap()
+                .unwrap(),
+            TreeChange::new(leaf("gone", "gone"), None)
+                .unwrap()
+                .unwrap(),
+            TreeChange::new(None, leaf("new", "new")).unwrap().unwrap(),
+        ];
+        let read = |leaf: &GitTreeLeaf, _| {
+            Ok(contents
+                .values()
+                .find(|c| blob_sha(c) == leaf.sha)
+                .unwrap()
+                .to_vec())
+        };
+        let ret = detect_renames(changes, RenameOptions::default(), read).unwrap();
+        let summary: Vec<(Change, &[u8], u8)> =
+            ret.iter().map(|c| (c.change, c.path(), c.score)).collect();
+        assert_eq!(
+            summary,
+            vec![
+                (Change::Renamed, b"dir/b.txt".as_slice(), 79),
+                (Change::Deleted, b"gone", 0),
+                (Change::Added, b"new", 0),
+            ]
+        );
+    }
+}